@@ -53,7 +53,8 @@ impl AssetManager {
         default_material: &Material,
         texture_layout: &wgpu::BindGroupLayout,
     ) -> anyhow::Result<usize> {
-        let mut serialized = load_serialized_model(filepath.as_path());
+        let mut serialized = load_serialized_model(filepath.as_path())
+            .with_context(|| format!("Could not load model {} from {:?}", name, filepath))?;
         let mut path = filepath.clone();
         path.pop();
         let model = load_model_from_serialized(
@@ -91,7 +92,8 @@ impl AssetManager {
         texture_layout: &wgpu::BindGroupLayout,
         skeletal_context: &skeletal_context::SkeletalContext,
     ) -> anyhow::Result<usize> {
-        let mut serialized = load_serialized_model(filepath.as_path());
+        let mut serialized = load_serialized_model(filepath.as_path())
+            .with_context(|| format!("Could not load model {} from {:?}", name, filepath))?;
         let mut path = filepath.clone();
         path.pop();
         let model = load_skinned_model_from_serialized(
@@ -146,6 +148,33 @@ impl AssetManager {
     //     }
     // }
 
+    // Makes the models and textures again on a new device, after the one they were created on was lost. Everything
+    // is rebuilt from the copies kept on the CPU, into the same indices, with the materials' alpha settings kept.
+    pub fn recreate_gpu_resources(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture_layout: &wgpu::BindGroupLayout,
+        recreated: &mut RecreatedTextures,
+    ) -> anyhow::Result<()> {
+        for (idx, model) in self.models.iter_mut().enumerate() {
+            model
+                .recreate_gpu_resources(device, queue, texture_layout, recreated)
+                .with_context(|| format!("Could not recreate model {:?}", self.model_names.get(&idx)))?;
+        }
+        for (idx, model) in self.skinned_models.iter_mut().enumerate() {
+            model
+                .recreate_gpu_resources(device, queue, texture_layout, recreated)
+                .with_context(|| format!("Could not recreate skinned model {:?}", self.skinned_model_names.get(&idx)))?;
+        }
+        for (idx, texture) in self.textures.iter_mut().enumerate() {
+            *texture = texture
+                .recreate(device, queue, recreated)
+                .with_context(|| format!("Could not recreate texture {:?}", self.texture_names.get(&idx)))?;
+        }
+        anyhow::Result::Ok(())
+    }
+
     pub fn load_audio_clip_from_file(
         &mut self,
         filepath: &Path,
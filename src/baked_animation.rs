@@ -0,0 +1,143 @@
+// Animation clips sampled ahead of time into skinning matrices, for crowds. Each frame of each clip is one row of a
+// texture, holding every bone's matrix (joint model matrix times inverse bind matrix, as in update_characters). Drawing
+// a crowd member then only needs its clip and time, with no anim graph to evaluate. See crowd.rs and passes::crowd.
+// Baking is slow enough to do offline: bake_clips, then to_bytes, and from_bytes at load time.
+
+use msgpacker::*;
+use ozz_animation_rs::*;
+use std::{cell::RefCell, rc::Rc};
+
+// Keeps the texture within the 8192 texels wgpu guarantees, at 3 texels per bone.
+pub const MAX_BAKED_BONES: usize = 2730;
+pub const MAX_BAKED_FRAMES: usize = 8192;
+
+#[derive(Clone, Debug, PartialEq, MsgPacker)]
+pub struct BakedClip {
+    pub name: String,
+    // Rows of the texture. The first and last frames are the clip's start and end.
+    pub first_frame: u32,
+    pub num_frames: u32,
+    // In seconds.
+    pub duration: f32,
+}
+
+impl BakedClip {
+    // The two frames (absolute rows) around a time and the blend between them. Time wraps around the clip.
+    pub fn frames_at(&self, time: f32) -> ([u32; 2], f32) {
+        let last = self.num_frames.saturating_sub(1);
+        if last == 0 || self.duration <= 0.0 {
+            return ([self.first_frame, self.first_frame], 0.0);
+        }
+        let position = time.rem_euclid(self.duration) / self.duration * last as f32;
+        let frame = (position as u32).min(last - 1);
+        (
+            [self.first_frame + frame, self.first_frame + frame + 1],
+            (position - frame as f32).clamp(0.0, 1.0),
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, MsgPacker)]
+pub struct BakedAnimationData {
+    pub num_bones: u32,
+    pub frames_per_second: f32,
+    pub clips: Vec<BakedClip>,
+    // Frame after frame, each the top three rows of every bone's matrix.
+    pub texels: Vec<[f32; 4]>,
+}
+
+impl BakedAnimationData {
+    pub fn num_frames(&self) -> u32 {
+        self.clips.iter().map(|c| c.first_frame + c.num_frames).max().unwrap_or(0)
+    }
+
+    pub fn clip_index(&self, name: &str) -> Option<usize> {
+        self.clips.iter().position(|c| c.name == name)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut results = Vec::new();
+        self.pack(&mut results);
+        results
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        match Self::unpack(bytes) {
+            Ok((_, val)) => Ok(val),
+            Err(err) => Err(anyhow::anyhow!("Could not read baked animation: {:?}", err)),
+        }
+    }
+
+    // The matrix of a bone at a frame, as the shader rebuilds it.
+    pub fn bone_matrix(&self, frame: u32, bone: u32) -> glam::Mat4 {
+        let start = ((frame * self.num_bones + bone) * 3) as usize;
+        let rows = &self.texels[start..start + 3];
+        glam::Mat4::from_cols_array_2d(&[rows[0], rows[1], rows[2], [0.0, 0.0, 0.0, 1.0]]).transpose()
+    }
+}
+
+// Samples each animation at frames_per_second, plus its last frame so that loops close.
+// inverse_bind_matrices are the skinned model's.
+pub fn bake_clips(
+    skeleton: Rc<Skeleton>,
+    animations: &[(String, Rc<Animation>)],
+    inverse_bind_matrices: &[glam::Mat4],
+    frames_per_second: f32,
+) -> anyhow::Result<BakedAnimationData> {
+    let num_bones = skeleton.num_joints();
+    if num_bones > MAX_BAKED_BONES {
+        anyhow::bail!("Skeleton has {} joints, more than the {} that can be baked", num_bones, MAX_BAKED_BONES);
+    }
+    if inverse_bind_matrices.len() < num_bones {
+        anyhow::bail!("Expected {} inverse bind matrices, got {}", num_bones, inverse_bind_matrices.len());
+    }
+
+    let local_transforms = Rc::new(RefCell::new(vec![SoaTransform::default(); skeleton.num_soa_joints()]));
+    let model_matrices = Rc::new(RefCell::new(vec![glam::Mat4::default(); num_bones]));
+
+    let mut local_to_model_job: LocalToModelJob = LocalToModelJob::default();
+    local_to_model_job.set_skeleton(skeleton.clone());
+    local_to_model_job.set_input(local_transforms.clone());
+    local_to_model_job.set_output(model_matrices.clone());
+
+    let mut results = BakedAnimationData {
+        num_bones: num_bones as u32,
+        frames_per_second,
+        clips: Vec::new(),
+        texels: Vec::new(),
+    };
+
+    for (name, animation) in animations {
+        let duration = animation.duration();
+        let num_frames = ((duration * frames_per_second).ceil() as u32 + 1).max(2);
+        let first_frame = results.num_frames();
+        if (first_frame + num_frames) as usize > MAX_BAKED_FRAMES {
+            anyhow::bail!("Baking {} would go past {} frames", name, MAX_BAKED_FRAMES);
+        }
+
+        let mut sampling_job: SamplingJob = SamplingJob::default();
+        sampling_job.set_animation(animation.clone());
+        sampling_job.set_context(SamplingContext::new(animation.num_tracks()));
+        sampling_job.set_output(local_transforms.clone());
+
+        for frame in 0..num_frames {
+            sampling_job.set_ratio(frame as f32 / (num_frames - 1) as f32);
+            sampling_job.run()?;
+            local_to_model_job.run()?;
+
+            for (bone, m) in model_matrices.borrow().iter().enumerate() {
+                let rows = (*m * inverse_bind_matrices[bone]).transpose().to_cols_array_2d();
+                results.texels.extend_from_slice(&rows[..3]);
+            }
+        }
+
+        results.clips.push(BakedClip {
+            name: name.clone(),
+            first_frame,
+            num_frames,
+            duration,
+        });
+    }
+
+    Ok(results)
+}
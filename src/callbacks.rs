@@ -15,6 +15,14 @@ pub fn init_user_update_callback(callback: fn (gfx_ctx: &mut GraphicsContext, &m
     *USER_UPDATE_CALLBACK.lock().unwrap() = Some(callback);
 }
 
+// Called after a device loss, once the engine has recreated its own GPU data, including the models, textures and
+// terrain materials it was given. Anything else the setup or update callbacks created on the device goes here.
+pub static USER_DEVICE_RECREATED_CALLBACK: Lazy<Mutex<Option<fn(&mut GraphicsContext, &mut UserContext)>>> = Lazy::new(|| Mutex::new(None));
+
+pub fn init_user_device_recreated_callback(callback: fn (gfx_ctx: &mut GraphicsContext, &mut UserContext)) {
+    *USER_DEVICE_RECREATED_CALLBACK.lock().unwrap() = Some(callback);
+}
+
 pub static USER_GUI_CALLBACK: Lazy<Mutex<Option<fn(&mut EguiRenderer, &mut UserContext)>>> = Lazy::new(|| Mutex::new(None));
 
 pub fn init_user_gui_callback(callback: fn (&mut EguiRenderer, &mut UserContext)) {
@@ -2,6 +2,8 @@ use std::f32::consts::PI;
 use std::ops::*;
 use wgpu::util::DeviceExt;
 
+use crate::picking::Ray;
+
 
 const PI_TIMES_2: f32 = PI * 2.0;
 
@@ -84,10 +86,40 @@ impl Camera {
         view_matrix
     }
 
+    // World space ray through a cursor position in physical pixels, with the origin at the top left.
+    pub fn cursor_ray(&self, cursor: glam::Vec2, window_size: glam::Vec2) -> Ray {
+        let ndc_x = cursor.x / window_size.x * 2.0 - 1.0;
+        let ndc_y = 1.0 - cursor.y / window_size.y * 2.0;
+        let inverse = (self.projection.calc_matrix() * self.view_matrix()).inverse();
+        let near = inverse.project_point3(glam::Vec3::new(ndc_x, ndc_y, 0.0));
+        let far = inverse.project_point3(glam::Vec3::new(ndc_x, ndc_y, 1.0));
+        Ray::new(near, far - near)
+    }
+
     pub fn reset(&mut self) {
         self.up = self.world_up;
     }
 
+    // Switches between perspective and orthographic at runtime. The uniform picks it up on the next update.
+    pub fn set_projection_kind(&mut self, kind: ProjectionKind) {
+        self.projection.kind = kind;
+    }
+
+    // Orthographic views are sized to show what perspective showed at the ground plane (y = 0),
+    // so toggling doesn't jump much.
+    pub fn toggle_projection(&mut self) {
+        let kind = match self.projection.kind {
+            ProjectionKind::Perspective => {
+                let distance = self.eye.y.abs().max(1.0);
+                ProjectionKind::Orthographic {
+                    view_height: 2.0 * distance * (self.projection.fovy_rad * 0.5).tan(),
+                }
+            }
+            ProjectionKind::Orthographic { .. } => ProjectionKind::Perspective,
+        };
+        self.set_projection_kind(kind);
+    }
+
     pub fn change_pitch(&mut self, rads: f32) {
         let mut temp = rads;
         //Check bounds with the max pitch rate so that we aren't moving too fast
@@ -153,11 +185,25 @@ impl Camera {
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ProjectionKind {
+    Perspective,
+    // view_height is the world space height of the view at a zoom of 1.
+    Orthographic { view_height: f32 },
+}
+
+pub const MIN_ZOOM: f32 = 0.01;
+pub const MAX_ZOOM: f32 = 100.0;
+
+#[derive(Debug, Copy, Clone)]
 pub struct Projection {
     pub aspect_ratio: f32,
     pub fovy_rad: f32,
     pub znear: f32,
     pub zfar: f32,
+    pub kind: ProjectionKind,
+    // Greater than 1 magnifies. Narrows the field of view in perspective and shrinks the view in orthographic.
+    pub zoom: f32,
 }
 // pub const OPENGL_TO_WGPU_MATRIX : glam::Mat4 = glam::Mat4::from_cols(
 //     glam::Vec4::new(1.0, 0.0, 0.0, 0.0),
@@ -173,18 +219,59 @@ impl Projection {
             fovy_rad,
             znear,
             zfar,
+            kind: ProjectionKind::Perspective,
+            zoom: 1.0,
+        }
+    }
+
+    pub fn new_orthographic(height: u32, width: u32, view_height: f32, znear: f32, zfar: f32) -> Self {
+        Self {
+            aspect_ratio: width as f32 / height as f32,
+            fovy_rad: degrees_to_radians(45.0),
+            znear,
+            zfar,
+            kind: ProjectionKind::Orthographic { view_height },
+            zoom: 1.0,
         }
     }
 
+    pub fn is_orthographic(&self) -> bool {
+        matches!(self.kind, ProjectionKind::Orthographic { .. })
+    }
+
+    pub fn set_zoom(&mut self, zoom: f32) {
+        self.zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+    }
+
+    pub fn zoom_by(&mut self, factor: f32) {
+        self.set_zoom(self.zoom * factor);
+    }
+
     pub fn resize(&mut self, height: u32, width: u32) -> () {
         self.aspect_ratio = width as f32 / height as f32;
     }
 
     pub fn calc_matrix(&self) -> glam::Mat4 {
         //OPENGL_TO_WGPU_MATRIX * glam::Mat4::perspective_rh(self.fovy_rad, self.aspect_ratio, self.znear, self.zfar);
-        let results =
-            glam::camera::rh::proj::directx::perspective(self.fovy_rad, self.aspect_ratio, self.znear, self.zfar);
-        results
+        match self.kind {
+            ProjectionKind::Perspective => {
+                // Zooming narrows the field of view, keeping it under 180 degrees.
+                let fovy = (2.0 * ((self.fovy_rad * 0.5).tan() / self.zoom).atan()).min(PI - 0.01);
+                glam::camera::rh::proj::directx::perspective(fovy, self.aspect_ratio, self.znear, self.zfar)
+            }
+            ProjectionKind::Orthographic { view_height } => {
+                let half_height = view_height * 0.5 / self.zoom;
+                let half_width = half_height * self.aspect_ratio;
+                glam::camera::rh::proj::directx::orthographic(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    self.znear,
+                    self.zfar,
+                )
+            }
+        }
     }
 }
 
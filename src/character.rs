@@ -9,6 +9,14 @@ pub struct Character {
     // TODO:
     // Colliders, IK, pathfinding
     pub anim_graph: AnimGraph,
+    // Outlined by the render graph's outline pass.
+    pub highlighted: bool,
+    // Blend shape weights, in the order of the skinned model's morph_target_names. Missing ones are zero.
+    pub morph_weights: Vec<f32>,
+    // Passed to the instance data each frame, see Instance.
+    pub tint: glam::Vec4,
+    pub emissive: f32,
+    pub user_data: glam::Vec4,
 }
 
 impl Character {
@@ -27,6 +35,11 @@ impl Character {
                     position,
                     orientation,
                     anim_graph: val,
+                    highlighted: false,
+                    morph_weights: Vec::new(),
+                    tint: glam::Vec4::ONE,
+                    emissive: 0.0,
+                    user_data: glam::Vec4::ZERO,
                 })
             }
             Err(err) => {
@@ -38,4 +51,12 @@ impl Character {
             }
         }
     }
+
+    // Index from SkinnedModel::morph_target_index.
+    pub fn set_morph_weight(&mut self, morph_target_idx: usize, weight: f32) {
+        if morph_target_idx >= self.morph_weights.len() {
+            self.morph_weights.resize(morph_target_idx + 1, 0.0);
+        }
+        self.morph_weights[morph_target_idx] = weight;
+    }
 }
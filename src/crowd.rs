@@ -0,0 +1,178 @@
+// Many copies of one skinned model, each playing a baked clip. Cheaper than characters by far: nothing is evaluated
+// on the CPU but each member's time, and their bones come from the baked texture. Meant for background crowds, so
+// there's no picking, outlines, morph targets or blending between clips. Crowds live on the Scene. The active scene's
+// are stepped by the window each update, with Scene::update_crowds.
+
+use crate::baked_animation::*;
+use crate::instance::*;
+use crate::model::Vertex;
+use crate::texture::Texture;
+use std::{mem, rc::Rc};
+
+pub struct CrowdMember {
+    pub instance: Instance,
+    // Into the baked animation's clips.
+    pub clip: usize,
+    // Seconds into the clip. Wraps around.
+    pub time: f32,
+    pub speed: f32,
+}
+
+impl CrowdMember {
+    pub fn to_raw(&self, animation: &BakedAnimationData) -> CrowdInstanceRaw {
+        let (frames, blend) = match animation.clips.get(self.clip) {
+            Some(clip) => clip.frames_at(self.time),
+            None => ([0, 0], 0.0),
+        };
+        let instance = &self.instance;
+        CrowdInstanceRaw {
+            model: glam::Mat4::from_scale_rotation_translation(
+                instance.scale.into(),
+                instance.orientation,
+                instance.position.into(),
+            )
+            .to_cols_array_2d(),
+            frames,
+            blend,
+            tint: instance.tint.to_array(),
+            emissive: instance.emissive,
+            user_data: instance.user_data.to_array(),
+        }
+    }
+}
+
+pub struct Crowd {
+    pub skinned_model_idx: usize,
+    pub animation: Rc<BakedAnimationData>,
+    pub members: Vec<CrowdMember>,
+    pub visible: bool,
+    // Created by the crowd renderer on first use.
+    pub texture: Option<Texture>,
+    pub bind_group: Option<wgpu::BindGroup>,
+    pub instance_buffers: Vec<InstanceBuffer>,
+}
+
+impl Crowd {
+    pub fn new(skinned_model_idx: usize, animation: Rc<BakedAnimationData>) -> Self {
+        Self {
+            skinned_model_idx,
+            animation,
+            members: Vec::new(),
+            visible: true,
+            texture: None,
+            bind_group: None,
+            instance_buffers: Vec::new(),
+        }
+    }
+
+    pub fn add_member(&mut self, instance: Instance, clip: usize, time: f32) -> usize {
+        self.members.push(CrowdMember {
+            instance,
+            clip: clip.min(self.animation.clips.len().saturating_sub(1)),
+            time,
+            speed: 1.0,
+        });
+        self.members.len() - 1
+    }
+
+    pub fn advance(&mut self, dt: web_time::Duration) {
+        let dt = dt.as_secs_f32();
+        for member in self.members.iter_mut() {
+            member.time += dt * member.speed;
+            if let Some(clip) = self.animation.clips.get(member.clip) {
+                member.time = member.time.rem_euclid(clip.duration.max(f32::EPSILON));
+            }
+        }
+    }
+
+    // Drops what was made on the old device. The renderer makes it again from the baked data.
+    pub fn recreate_gpu_resources(&mut self) {
+        self.texture = None;
+        self.bind_group = None;
+        self.instance_buffers.clear();
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CrowdInstanceRaw {
+    pub model: [[f32; 4]; 4],
+    // Rows of the baked texture to blend between, and how far along.
+    pub frames: [u32; 2],
+    pub blend: f32,
+    pub tint: [f32; 4],
+    pub emissive: f32,
+    pub user_data: [f32; 4],
+}
+
+impl CrowdInstanceRaw {
+    pub fn composed(&self, mesh_mat: &glam::Mat4) -> Self {
+        let model_mat = glam::Mat4::from_cols_array_2d(&self.model);
+        Self {
+            model: (model_mat * *mesh_mat).to_cols_array_2d(),
+            ..*self
+        }
+    }
+}
+
+impl Vertex for CrowdInstanceRaw {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<CrowdInstanceRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                // Model matrix, after the skinned vertex's slots 0-6.
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 7,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 8,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                // Frames
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Uint32x2,
+                },
+                // Blend
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 18]>() as wgpu::BufferAddress,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Float32,
+                },
+                // Tint
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 19]>() as wgpu::BufferAddress,
+                    shader_location: 13,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                // Emissive
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 23]>() as wgpu::BufferAddress,
+                    shader_location: 14,
+                    format: wgpu::VertexFormat::Float32,
+                },
+                // User data
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 24]>() as wgpu::BufferAddress,
+                    shader_location: 15,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
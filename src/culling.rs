@@ -0,0 +1,199 @@
+// CPU-side visibility testing. Bounding volumes are conservative spheres derived from mesh dimensions.
+
+use crate::instance::Instance;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BoundingSphere {
+    pub center: glam::Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    pub fn new(center: glam::Vec3, radius: f32) -> Self {
+        Self { center, radius }
+    }
+
+    // Smallest sphere (around the midpoint of the inputs) that encloses all of them.
+    pub fn enclosing(spheres: &[BoundingSphere]) -> Self {
+        if spheres.is_empty() {
+            return Self::new(glam::Vec3::ZERO, 0.0);
+        }
+
+        let mut min = glam::Vec3::splat(f32::MAX);
+        let mut max = glam::Vec3::splat(f32::MIN);
+        for s in spheres {
+            min = min.min(s.center - glam::Vec3::splat(s.radius));
+            max = max.max(s.center + glam::Vec3::splat(s.radius));
+        }
+
+        let center = (min + max) * 0.5;
+        let mut radius: f32 = 0.0;
+        for s in spheres {
+            radius = radius.max(center.distance(s.center) + s.radius);
+        }
+
+        Self { center, radius }
+    }
+
+    pub fn transformed(&self, position: glam::Vec3, orientation: glam::Quat, scale: glam::Vec3) -> Self {
+        Self {
+            center: position + orientation * (scale * self.center),
+            radius: self.radius * scale.abs().max_element(),
+        }
+    }
+
+    pub fn transformed_by_instance(&self, instance: &Instance) -> Self {
+        self.transformed(
+            instance.position.into(),
+            instance.orientation,
+            instance.scale.into(),
+        )
+    }
+}
+
+// Local-space sphere for a mesh. Meshes aren't guaranteed to be centered on their origin, so we use
+// the full diagonal of the dimensions as the radius. This covers any mesh whose extents contain its origin.
+pub fn mesh_bounding_sphere(translation: glam::Vec3, scale: glam::Vec3, dimensions: glam::Vec3) -> BoundingSphere {
+    BoundingSphere::new(translation, (dimensions * scale).length())
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Frustum {
+    // Plane equations as (normal, distance). Points inside satisfy dot(normal, p) + distance >= 0
+    pub planes: [glam::Vec4; 6],
+}
+
+impl Frustum {
+    // Gribb/Hartmann plane extraction, for projections with a [0, 1] depth range (see Projection::calc_matrix)
+    pub fn from_view_projection(view_projection: &glam::Mat4) -> Self {
+        let r0 = view_projection.row(0);
+        let r1 = view_projection.row(1);
+        let r2 = view_projection.row(2);
+        let r3 = view_projection.row(3);
+
+        let mut planes = [
+            r3 + r0, // left
+            r3 - r0, // right
+            r3 + r1, // bottom
+            r3 - r1, // top
+            r2,      // near
+            r3 - r2, // far
+        ];
+
+        for p in planes.iter_mut() {
+            let len = p.truncate().length();
+            if len > 0.0 {
+                *p /= len;
+            }
+        }
+
+        Self { planes }
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        for p in &self.planes {
+            if p.truncate().dot(sphere.center) + p.w < -sphere.radius {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl Default for Frustum {
+    // A frustum that accepts everything.
+    fn default() -> Self {
+        Self {
+            planes: [glam::Vec4::new(0.0, 0.0, 0.0, 1.0); 6],
+        }
+    }
+}
+
+// Collapses sorted instance indices into (start, count) runs of consecutive instances.
+pub fn visible_runs(indices: &[u32]) -> Vec<(u32, u32)> {
+    let mut results = Vec::<(u32, u32)>::new();
+    for i in indices {
+        if let Some((start, count)) = results.last_mut() {
+            if *start + *count == *i {
+                *count += 1;
+                continue;
+            }
+        }
+        results.push((*i, 1));
+    }
+    results
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct CullingStats {
+    pub visible_instances: usize,
+    pub culled_instances: usize,
+    pub visible_characters: usize,
+    pub culled_characters: usize,
+}
+
+impl CullingStats {
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Looking down -Z from the origin, 90 degrees wide and high, so the side planes are at |x| = -z and |y| = -z.
+    fn test_frustum() -> Frustum {
+        let projection = glam::Mat4::perspective_rh(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 100.0);
+        Frustum::from_view_projection(&projection)
+    }
+
+    #[test]
+    fn sphere_inside() {
+        let frustum = test_frustum();
+        assert!(frustum.intersects_sphere(&BoundingSphere::new(glam::Vec3::new(0.0, 0.0, -10.0), 1.0)));
+        assert!(frustum.intersects_sphere(&BoundingSphere::new(glam::Vec3::new(5.0, -5.0, -50.0), 0.5)));
+    }
+
+    #[test]
+    fn sphere_outside() {
+        let frustum = test_frustum();
+        // Behind the eye, past the far plane, and off to each side.
+        assert!(!frustum.intersects_sphere(&BoundingSphere::new(glam::Vec3::new(0.0, 0.0, 10.0), 1.0)));
+        assert!(!frustum.intersects_sphere(&BoundingSphere::new(glam::Vec3::new(0.0, 0.0, -200.0), 1.0)));
+        assert!(!frustum.intersects_sphere(&BoundingSphere::new(glam::Vec3::new(-50.0, 0.0, -10.0), 1.0)));
+        assert!(!frustum.intersects_sphere(&BoundingSphere::new(glam::Vec3::new(50.0, 0.0, -10.0), 1.0)));
+        assert!(!frustum.intersects_sphere(&BoundingSphere::new(glam::Vec3::new(0.0, -50.0, -10.0), 1.0)));
+        assert!(!frustum.intersects_sphere(&BoundingSphere::new(glam::Vec3::new(0.0, 50.0, -10.0), 1.0)));
+    }
+
+    #[test]
+    fn sphere_straddling() {
+        let frustum = test_frustum();
+        // Centres just outside the left, near and far planes, within a radius of them.
+        assert!(frustum.intersects_sphere(&BoundingSphere::new(glam::Vec3::new(-10.5, 0.0, -10.0), 2.0)));
+        assert!(frustum.intersects_sphere(&BoundingSphere::new(glam::Vec3::new(0.0, 0.0, 0.5), 1.0)));
+        assert!(frustum.intersects_sphere(&BoundingSphere::new(glam::Vec3::new(0.0, 0.0, -100.5), 1.0)));
+    }
+
+    #[test]
+    fn default_frustum_accepts_everything() {
+        let frustum = Frustum::default();
+        assert!(frustum.intersects_sphere(&BoundingSphere::new(glam::Vec3::new(1e6, -1e6, 1e6), 0.0)));
+    }
+
+    #[test]
+    fn runs_empty() {
+        assert!(visible_runs(&[]).is_empty());
+    }
+
+    #[test]
+    fn runs_contiguous() {
+        assert_eq!(visible_runs(&[3, 4, 5, 6]), vec![(3, 4)]);
+    }
+
+    #[test]
+    fn runs_sparse() {
+        assert_eq!(visible_runs(&[0, 2, 3, 7, 9, 10, 11]), vec![(0, 1), (2, 2), (7, 1), (9, 3)]);
+    }
+}
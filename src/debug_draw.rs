@@ -0,0 +1,263 @@
+// Immediate-mode debug drawing. Shapes are queued from the update callback (or anywhere with the UserContext)
+// and drawn by the DebugDraw pass. By default they last a single frame, so they are simply re-queued every update.
+
+pub const RED: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+pub const GREEN: [f32; 4] = [0.0, 1.0, 0.0, 1.0];
+pub const BLUE: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
+pub const YELLOW: [f32; 4] = [1.0, 1.0, 0.0, 1.0];
+pub const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+const SPHERE_SEGMENTS: usize = 32;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DebugDrawOptions {
+    // When false the shape is drawn over the scene.
+    pub depth_test: bool,
+    // Seconds to keep the shape for. Zero means only until the next update.
+    pub duration: f32,
+}
+
+impl Default for DebugDrawOptions {
+    fn default() -> Self {
+        Self {
+            depth_test: true,
+            duration: 0.0,
+        }
+    }
+}
+
+impl DebugDrawOptions {
+    pub fn overlay() -> Self {
+        Self {
+            depth_test: false,
+            ..Default::default()
+        }
+    }
+
+    pub fn for_seconds(mut self, duration: f32) -> Self {
+        self.duration = duration;
+        self
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DebugVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+}
+
+impl DebugVertex {
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<DebugVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct DebugLine {
+    pub start: glam::Vec3,
+    pub end: glam::Vec3,
+    pub color: [f32; 4],
+    pub depth_test: bool,
+    pub remaining: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct DebugLabel {
+    pub position: glam::Vec3,
+    pub text: String,
+    pub color: [f32; 4],
+    pub remaining: f32,
+}
+
+pub struct DebugDraw {
+    pub enabled: bool,
+    pub lines: Vec<DebugLine>,
+    pub labels: Vec<DebugLabel>,
+}
+
+impl DebugDraw {
+    pub fn new() -> Self {
+        Self {
+            enabled: true,
+            lines: Vec::new(),
+            labels: Vec::new(),
+        }
+    }
+
+    // Ages everything by dt and drops what has expired. Called by the window before the update callback.
+    pub fn advance(&mut self, dt: f32) {
+        self.lines.retain_mut(|l| {
+            l.remaining -= dt;
+            l.remaining >= 0.0
+        });
+        self.labels.retain_mut(|l| {
+            l.remaining -= dt;
+            l.remaining >= 0.0
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        self.labels.clear();
+    }
+
+    pub fn line(&mut self, start: glam::Vec3, end: glam::Vec3, color: [f32; 4], options: DebugDrawOptions) {
+        self.lines.push(DebugLine {
+            start,
+            end,
+            color,
+            depth_test: options.depth_test,
+            remaining: options.duration,
+        });
+    }
+
+    pub fn aabb(&mut self, min: glam::Vec3, max: glam::Vec3, color: [f32; 4], options: DebugDrawOptions) {
+        let corner = |x: bool, y: bool, z: bool| {
+            glam::Vec3::new(
+                if x { max.x } else { min.x },
+                if y { max.y } else { min.y },
+                if z { max.z } else { min.z },
+            )
+        };
+        for a in [false, true] {
+            for b in [false, true] {
+                self.line(corner(false, a, b), corner(true, a, b), color, options);
+                self.line(corner(a, false, b), corner(a, true, b), color, options);
+                self.line(corner(a, b, false), corner(a, b, true), color, options);
+            }
+        }
+    }
+
+    // Drawn as one circle around each axis.
+    pub fn sphere(&mut self, center: glam::Vec3, radius: f32, color: [f32; 4], options: DebugDrawOptions) {
+        let axes = [
+            (glam::Vec3::X, glam::Vec3::Y),
+            (glam::Vec3::Y, glam::Vec3::Z),
+            (glam::Vec3::Z, glam::Vec3::X),
+        ];
+        for (u, v) in axes {
+            self.circle(center, u * radius, v * radius, color, options);
+        }
+    }
+
+    // A circle in the plane spanned by u and v, whose lengths are the radii.
+    pub fn circle(
+        &mut self,
+        center: glam::Vec3,
+        u: glam::Vec3,
+        v: glam::Vec3,
+        color: [f32; 4],
+        options: DebugDrawOptions,
+    ) {
+        let point = |i: usize| {
+            let angle = i as f32 / SPHERE_SEGMENTS as f32 * std::f32::consts::TAU;
+            center + u * angle.cos() + v * angle.sin()
+        };
+        for i in 0..SPHERE_SEGMENTS {
+            self.line(point(i), point(i + 1), color, options);
+        }
+    }
+
+    // The head is a four line cone, sized relative to the arrow's length.
+    pub fn arrow(&mut self, start: glam::Vec3, end: glam::Vec3, color: [f32; 4], options: DebugDrawOptions) {
+        self.line(start, end, color, options);
+
+        let shaft = end - start;
+        let length = shaft.length();
+        if length <= f32::EPSILON {
+            return;
+        }
+        let direction = shaft / length;
+        let head_length = length * 0.2;
+        let (side, up) = direction.any_orthonormal_pair();
+        let base = end - direction * head_length;
+        for offset in [side, -side, up, -up] {
+            self.line(end, base + offset * head_length * 0.5, color, options);
+        }
+    }
+
+    // X, Y and Z arrows in red, green and blue.
+    pub fn axes(&mut self, position: glam::Vec3, orientation: glam::Quat, size: f32, options: DebugDrawOptions) {
+        self.arrow(position, position + orientation * glam::Vec3::X * size, RED, options);
+        self.arrow(position, position + orientation * glam::Vec3::Y * size, GREEN, options);
+        self.arrow(position, position + orientation * glam::Vec3::Z * size, BLUE, options);
+    }
+
+    // Labels are drawn with egui, so they always end up on top of the scene.
+    pub fn text(&mut self, position: glam::Vec3, text: &str, color: [f32; 4], options: DebugDrawOptions) {
+        self.labels.push(DebugLabel {
+            position,
+            text: text.to_owned(),
+            color,
+            remaining: options.duration,
+        });
+    }
+
+    // Line list vertices of the lines with or without depth testing.
+    pub fn vertices(&self, depth_test: bool) -> Vec<DebugVertex> {
+        let mut results = Vec::<DebugVertex>::new();
+        for l in self.lines.iter().filter(|l| l.depth_test == depth_test) {
+            results.push(DebugVertex {
+                position: l.start.into(),
+                color: l.color,
+            });
+            results.push(DebugVertex {
+                position: l.end.into(),
+                color: l.color,
+            });
+        }
+        results
+    }
+
+    // Projects the labels onto the screen and paints them behind any egui windows.
+    // Must be called while an egui frame is in progress. screen_size is in points.
+    pub fn paint_labels(&self, ctx: &egui::Context, view_projection: &glam::Mat4, screen_size: egui::Vec2) {
+        if !self.enabled || self.labels.is_empty() {
+            return;
+        }
+
+        let painter = ctx.layer_painter(egui::LayerId::new(
+            egui::Order::Background,
+            egui::Id::new("debug_draw_labels"),
+        ));
+
+        for l in &self.labels {
+            let clip = *view_projection * l.position.extend(1.0);
+            if clip.w <= 0.0 {
+                continue;
+            }
+            let ndc = clip.truncate() / clip.w;
+            if ndc.z < 0.0 || ndc.z > 1.0 {
+                continue;
+            }
+            let position = egui::pos2(
+                (ndc.x * 0.5 + 0.5) * screen_size.x,
+                (0.5 - ndc.y * 0.5) * screen_size.y,
+            );
+            let [r, g, b, a] = l.color.map(|c| (c.clamp(0.0, 1.0) * 255.0) as u8);
+            painter.text(
+                position,
+                egui::Align2::CENTER_CENTER,
+                &l.text,
+                egui::FontId::proportional(14.0),
+                egui::Color32::from_rgba_unmultiplied(r, g, b, a),
+            );
+        }
+    }
+}
@@ -0,0 +1,148 @@
+// A scene's fog and sky. Uploaded each frame by passes::environment, applied by the scene shaders, particles and water
+// (see passes/environment.wgsl) and drawn behind everything by passes::sky.
+// With an atmosphere the fog takes the sky's colour in the direction of each fragment, so far geometry blends into
+// the horizon. The sky is a cheap analytic gradient with a scattering glow around the sun, not a physically based model.
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Fog {
+    pub enabled: bool,
+    // Linear. Ignored when the scene has an atmosphere, which gives its own colour.
+    pub color: glam::Vec3,
+    // Per world unit, past start_distance from the eye.
+    pub density: f32,
+    pub start_distance: f32,
+    // Thickest at base_height, thinning out above it by height_falloff per world unit.
+    pub height_density: f32,
+    pub height_falloff: f32,
+    pub base_height: f32,
+    // Keeps the farthest geometry from disappearing entirely. 0 to 1.
+    pub max_opacity: f32,
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            color: glam::Vec3::new(0.6, 0.65, 0.7),
+            density: 0.01,
+            start_distance: 0.0,
+            height_density: 0.0,
+            height_falloff: 0.1,
+            base_height: 0.0,
+            max_opacity: 1.0,
+        }
+    }
+}
+
+impl Fog {
+    // How much of a point's colour is replaced by fog, seen from the eye. Same as fog_factor in the shaders.
+    pub fn factor(&self, eye: glam::Vec3, point: glam::Vec3) -> f32 {
+        if !self.enabled {
+            return 0.0;
+        }
+        let to_point = point - eye;
+        let distance = to_point.length();
+        let mut depth = self.density * (distance - self.start_distance).max(0.0);
+        if self.height_density > 0.0 {
+            // Density integrated along the ray, through fog that falls off exponentially with height.
+            let k = self.height_falloff * to_point.y;
+            let along = if k.abs() < 0.0001 { 1.0 } else { (1.0 - (-k).exp()) / k };
+            depth += self.height_density * (-self.height_falloff * (eye.y - self.base_height)).exp() * distance * along;
+        }
+        (1.0 - (-depth).exp()).min(self.max_opacity)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Atmosphere {
+    // Towards the sun.
+    pub sun_direction: glam::Vec3,
+    // Linear, scaled by sun_intensity.
+    pub sun_color: glam::Vec3,
+    pub sun_intensity: f32,
+    // Angular radius of the disk, in radians.
+    pub sun_size: f32,
+    pub zenith_color: glam::Vec3,
+    pub horizon_color: glam::Vec3,
+    pub ground_color: glam::Vec3,
+    // Forward scattering of the glow around the sun, 0 (even) to just under 1 (tight).
+    pub mie_anisotropy: f32,
+    pub mie_strength: f32,
+}
+
+impl Default for Atmosphere {
+    fn default() -> Self {
+        Self {
+            sun_direction: glam::Vec3::new(0.3, 0.6, 0.4).normalize(),
+            sun_color: glam::Vec3::new(1.0, 0.95, 0.85),
+            sun_intensity: 20.0,
+            sun_size: 0.01,
+            zenith_color: glam::Vec3::new(0.15, 0.3, 0.65),
+            horizon_color: glam::Vec3::new(0.6, 0.7, 0.8),
+            ground_color: glam::Vec3::new(0.3, 0.28, 0.25),
+            mie_anisotropy: 0.76,
+            mie_strength: 0.02,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Environment {
+    pub fog: Fog,
+    // None keeps the forward renderer's clear colour behind the scene (or the fog colour, with fog).
+    pub atmosphere: Option<Atmosphere>,
+}
+
+impl Environment {
+    pub fn to_raw(&self) -> EnvironmentRaw {
+        let fog = &self.fog;
+        let atmosphere = self.atmosphere.unwrap_or_default();
+        EnvironmentRaw {
+            fog_color: fog.color.extend(1.0).to_array(),
+            fog_params: [
+                fog.density,
+                fog.start_distance,
+                fog.max_opacity,
+                if fog.enabled { 1.0 } else { 0.0 },
+            ],
+            height_fog: [fog.height_density, fog.height_falloff, fog.base_height, 0.0],
+            sun_direction: atmosphere
+                .sun_direction
+                .normalize_or(glam::Vec3::Y)
+                .extend(if self.atmosphere.is_some() { 1.0 } else { 0.0 })
+                .to_array(),
+            sun_color: (atmosphere.sun_color * atmosphere.sun_intensity)
+                .extend(atmosphere.sun_size.cos())
+                .to_array(),
+            zenith_color: atmosphere.zenith_color.extend(1.0).to_array(),
+            horizon_color: atmosphere.horizon_color.extend(1.0).to_array(),
+            ground_color: atmosphere.ground_color.extend(1.0).to_array(),
+            mie: [atmosphere.mie_anisotropy.clamp(0.0, 0.99), atmosphere.mie_strength, 0.0, 0.0],
+        }
+    }
+
+    // Whether the sky pass has anything to draw behind the scene.
+    pub fn draws_background(&self) -> bool {
+        self.fog.enabled || self.atmosphere.is_some()
+    }
+}
+
+// Everything is a vec4 to keep the uniform's layout simple. The w components hold flags and leftovers.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct EnvironmentRaw {
+    pub fog_color: [f32; 4],
+    // Density, start distance, max opacity, enabled.
+    pub fog_params: [f32; 4],
+    // Density, falloff, base height.
+    pub height_fog: [f32; 4],
+    // w is 1 with an atmosphere.
+    pub sun_direction: [f32; 4],
+    // w is the cosine of the disk's radius.
+    pub sun_color: [f32; 4],
+    pub zenith_color: [f32; 4],
+    pub horizon_color: [f32; 4],
+    pub ground_color: [f32; 4],
+    // Anisotropy, strength.
+    pub mie: [f32; 4],
+}
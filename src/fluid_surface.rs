@@ -0,0 +1,187 @@
+// A triangle surface around the particles of the scene's liquids, rebuilt every frame with splashsurf
+// and drawn by the Fluid pass with a refractive water material.
+
+use crate::model::Vertex;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FluidSurfaceSettings {
+    // Should match the particle radius of the liquid world.
+    pub particle_radius: f32,
+    // Kernel smoothing length, in multiples of the particle radius.
+    pub smoothing_length: f32,
+    // Marching cubes cell size, in multiples of the particle radius. Smaller is smoother and slower.
+    pub cube_size: f32,
+    pub iso_surface_threshold: f32,
+}
+
+impl FluidSurfaceSettings {
+    pub fn new(particle_radius: f32) -> Self {
+        Self {
+            particle_radius,
+            smoothing_length: 2.0,
+            cube_size: 0.5,
+            iso_surface_threshold: 0.6,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FluidMaterial {
+    // What light passing through the liquid gets multiplied by.
+    pub tint: glam::Vec3,
+    // How much of the tint is applied, from clear (0) to fully tinted (1).
+    pub opacity: f32,
+    // The color reflected at grazing angles, standing in for the environment.
+    pub reflection_color: glam::Vec3,
+    // How far the background is offset by the surface's normal, as a fraction of the screen.
+    pub refraction_strength: f32,
+    pub shininess: f32,
+    // Reflectance when looking straight at the surface. 0.02 for water.
+    pub fresnel_f0: f32,
+}
+
+impl Default for FluidMaterial {
+    fn default() -> Self {
+        Self {
+            tint: glam::Vec3::new(0.55, 0.8, 0.9),
+            opacity: 0.6,
+            reflection_color: glam::Vec3::new(0.6, 0.7, 0.8),
+            refraction_strength: 0.03,
+            shininess: 128.0,
+            fresnel_f0: 0.02,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct FluidMaterialUniform {
+    pub tint: [f32; 4],
+    pub reflection_color: [f32; 4],
+    pub refraction_strength: f32,
+    pub shininess: f32,
+    pub fresnel_f0: f32,
+    pub _padding: f32,
+}
+
+impl FluidMaterialUniform {
+    pub fn new(material: &FluidMaterial) -> Self {
+        Self {
+            tint: material.tint.extend(material.opacity).into(),
+            reflection_color: material.reflection_color.extend(1.0).into(),
+            refraction_strength: material.refraction_strength,
+            shininess: material.shininess,
+            fresnel_f0: material.fresnel_f0,
+            _padding: 0.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct FluidVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+}
+
+impl Vertex for FluidVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<FluidVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+            ],
+        }
+    }
+}
+
+pub struct FluidSurface {
+    pub settings: FluidSurfaceSettings,
+    pub material: FluidMaterial,
+    pub visible: bool,
+    pub vertices: Vec<FluidVertex>,
+    pub indices: Vec<u32>,
+}
+
+impl FluidSurface {
+    pub fn new(settings: FluidSurfaceSettings, material: FluidMaterial) -> Self {
+        Self {
+            settings,
+            material,
+            visible: true,
+            vertices: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+
+    // Replaces the mesh with the surface around the given particles. Keeps the old one if reconstruction fails.
+    pub fn reconstruct(&mut self, positions: &[glam::Vec3]) {
+        if positions.is_empty() {
+            self.vertices.clear();
+            self.indices.clear();
+            return;
+        }
+
+        let particles: Vec<splashsurf_lib::nalgebra::Vector3<f32>> = positions
+            .iter()
+            .map(|p| splashsurf_lib::nalgebra::Vector3::new(p.x, p.y, p.z))
+            .collect();
+
+        let radius = self.settings.particle_radius;
+        let parameters = splashsurf_lib::Parameters {
+            particle_radius: radius,
+            rest_density: 1000.0,
+            compact_support_radius: 2.0 * self.settings.smoothing_length * radius,
+            cube_size: self.settings.cube_size * radius,
+            iso_surface_threshold: self.settings.iso_surface_threshold,
+            particle_aabb: None,
+            enable_multi_threading: true,
+            spatial_decomposition: splashsurf_lib::SpatialDecomposition::default(),
+            global_neighborhood_list: false,
+        };
+
+        let reconstruction = match splashsurf_lib::reconstruct_surface::<i64, f32>(&particles, &parameters) {
+            Ok(val) => val,
+            Err(err) => {
+                println!("[FluidSurface] Could not reconstruct the surface: {}", err);
+                return;
+            }
+        };
+        let mesh = reconstruction.mesh();
+
+        // Area weighted vertex normals, from the triangles' cross products.
+        let mut normals = vec![glam::Vec3::ZERO; mesh.vertices.len()];
+        self.indices.clear();
+        for triangle in &mesh.triangles {
+            let [a, b, c] = triangle.map(|i| i as usize);
+            let pa = glam::Vec3::new(mesh.vertices[a].x, mesh.vertices[a].y, mesh.vertices[a].z);
+            let pb = glam::Vec3::new(mesh.vertices[b].x, mesh.vertices[b].y, mesh.vertices[b].z);
+            let pc = glam::Vec3::new(mesh.vertices[c].x, mesh.vertices[c].y, mesh.vertices[c].z);
+            let face_normal = (pb - pa).cross(pc - pa);
+            for i in [a, b, c] {
+                normals[i] += face_normal;
+                self.indices.push(i as u32);
+            }
+        }
+
+        self.vertices = mesh
+            .vertices
+            .iter()
+            .zip(normals.iter())
+            .map(|(p, n)| FluidVertex {
+                position: [p.x, p.y, p.z],
+                normal: n.normalize_or(glam::Vec3::Y).into(),
+            })
+            .collect();
+    }
+}
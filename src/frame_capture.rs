@@ -0,0 +1,338 @@
+// Saves rendered frames to PNG. Screenshots are requested from anywhere with the UserContext and taken at the end of
+// the next render, either of the scene alone or with the egui overlay on top. A sequence saves every frame while it is
+// recording and makes the window step the simulation at a fixed timestep, so the frames play back at an even rate.
+// Not available on the web, which has no threads to encode on or file system to save to. Requests are ignored there.
+
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone)]
+pub struct CaptureRequest {
+    pub path: PathBuf,
+    pub include_ui: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct FrameSequence {
+    pub directory: PathBuf,
+    pub prefix: String,
+    pub timestep: web_time::Duration,
+    pub include_ui: bool,
+    pub next_frame: u32,
+    // Stops by itself once this many frames were saved. None records until stop_sequence().
+    pub frame_limit: Option<u32>,
+}
+
+impl FrameSequence {
+    fn frame_path(&self, frame: u32) -> PathBuf {
+        self.directory.join(format!("{}_{:06}.png", self.prefix, frame))
+    }
+}
+
+pub struct FrameCapture {
+    pub requests: Vec<CaptureRequest>,
+    pub sequence: Option<FrameSequence>,
+    // Started with the first capture.
+    writer: Option<PngWriter>,
+}
+
+impl FrameCapture {
+    pub fn new() -> Self {
+        Self {
+            requests: Vec::new(),
+            sequence: None,
+            writer: None,
+        }
+    }
+
+    // Reads the frame back and hands it to the PNG writer.
+    pub fn save(&mut self, capture: PendingCapture, device: &wgpu::Device) {
+        let writer = self.writer.get_or_insert_with(PngWriter::new);
+        capture.save(device, writer);
+    }
+
+    pub fn screenshot(&mut self, path: impl Into<PathBuf>, include_ui: bool) {
+        #[cfg(target_arch = "wasm32")]
+        {
+            let _ = (path, include_ui);
+            println!("[FrameCapture] Screenshots aren't supported on the web");
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.requests.push(CaptureRequest {
+                path: path.into(),
+                include_ui,
+            });
+        }
+    }
+
+    // Named after the current time, in the working directory.
+    pub fn timestamped_screenshot(&mut self, include_ui: bool) {
+        let seconds = web_time::SystemTime::now()
+            .duration_since(web_time::UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        self.screenshot(format!("screenshot_{}.png", seconds), include_ui);
+    }
+
+    pub fn start_sequence(
+        &mut self,
+        directory: impl Into<PathBuf>,
+        prefix: &str,
+        frames_per_second: u32,
+        include_ui: bool,
+        frame_limit: Option<u32>,
+    ) {
+        #[cfg(target_arch = "wasm32")]
+        {
+            let _ = (directory, prefix, frames_per_second, include_ui, frame_limit);
+            println!("[FrameCapture] Sequences aren't supported on the web");
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let directory = directory.into();
+            if let Err(err) = std::fs::create_dir_all(&directory) {
+                println!("[FrameCapture] Could not create {}: {}", directory.display(), err);
+                return;
+            }
+            self.sequence = Some(FrameSequence {
+                directory,
+                prefix: prefix.to_owned(),
+                timestep: web_time::Duration::from_secs_f64(1.0 / frames_per_second.max(1) as f64),
+                include_ui,
+                next_frame: 0,
+                frame_limit,
+            });
+        }
+    }
+
+    pub fn stop_sequence(&mut self) {
+        self.sequence = None;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.sequence.is_some()
+    }
+
+    // The dt to step the world with instead of the wall clock one, while a sequence is recording.
+    pub fn fixed_timestep(&self) -> Option<web_time::Duration> {
+        self.sequence.as_ref().map(|s| s.timestep)
+    }
+
+    pub fn is_pending(&self) -> bool {
+        !self.requests.is_empty() || self.sequence.is_some()
+    }
+
+    // Takes this frame's captures, split into the ones without and with the egui overlay.
+    // Advances the sequence, so call once per rendered frame.
+    pub fn take_frame_requests(&mut self) -> (Vec<PathBuf>, Vec<PathBuf>) {
+        let mut scene_only = Vec::new();
+        let mut with_ui = Vec::new();
+        for r in self.requests.drain(..) {
+            if r.include_ui {
+                with_ui.push(r.path);
+            } else {
+                scene_only.push(r.path);
+            }
+        }
+
+        if let Some(sequence) = &mut self.sequence {
+            let path = sequence.frame_path(sequence.next_frame);
+            if sequence.include_ui {
+                with_ui.push(path);
+            } else {
+                scene_only.push(path);
+            }
+            sequence.next_frame += 1;
+            if sequence
+                .frame_limit
+                .is_some_and(|limit| sequence.next_frame >= limit)
+            {
+                self.sequence = None;
+            }
+        }
+
+        (scene_only, with_ui)
+    }
+}
+
+// Rows of a buffer copy have to be padded to COPY_BYTES_PER_ROW_ALIGNMENT.
+fn padded_bytes_per_row(width: u32) -> u32 {
+    let unpadded = width * 4;
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    unpadded.div_ceil(align) * align
+}
+
+// A copy of the surface recorded into the frame's encoder, read back once it was submitted.
+pub struct PendingCapture {
+    pub buffer: wgpu::Buffer,
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
+    pub paths: Vec<PathBuf>,
+}
+
+impl PendingCapture {
+    // Returns None for surfaces that can't be copied from or aren't 8 bits per channel.
+    pub fn record(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+        config: &wgpu::SurfaceConfiguration,
+        paths: Vec<PathBuf>,
+    ) -> Option<Self> {
+        if paths.is_empty() {
+            return None;
+        }
+        if !config.usage.contains(wgpu::TextureUsages::COPY_SRC) {
+            println!("[FrameCapture] The surface doesn't support being copied from");
+            return None;
+        }
+        match config.format {
+            wgpu::TextureFormat::Rgba8Unorm
+            | wgpu::TextureFormat::Rgba8UnormSrgb
+            | wgpu::TextureFormat::Bgra8Unorm
+            | wgpu::TextureFormat::Bgra8UnormSrgb => {}
+            other => {
+                println!("[FrameCapture] Can't capture surfaces of format {:?}", other);
+                return None;
+            }
+        }
+
+        let bytes_per_row = padded_bytes_per_row(config.width);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Frame Capture Buffer"),
+            size: bytes_per_row as u64 * config.height as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: Some(config.height),
+                },
+            },
+            wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        Some(Self {
+            buffer,
+            width: config.width,
+            height: config.height,
+            format: config.format,
+            paths,
+        })
+    }
+
+    // Waits for the GPU, then queues the pixels for the writer thread so the next frame isn't held up by encoding.
+    fn save(self, device: &wgpu::Device, writer: &PngWriter) {
+        let slice = self.buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        if let Err(err) = device.poll(wgpu::PollType::wait_indefinitely()) {
+            println!("[FrameCapture] Could not wait for the frame: {}", err);
+            return;
+        }
+        match receiver.recv() {
+            Ok(Ok(())) => {}
+            _ => {
+                println!("[FrameCapture] Could not read the frame back");
+                return;
+            }
+        }
+
+        let bytes_per_row = padded_bytes_per_row(self.width) as usize;
+        let row_len = self.width as usize * 4;
+        let swap_red_blue = matches!(
+            self.format,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        );
+        let mut pixels = Vec::<u8>::with_capacity(row_len * self.height as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks(bytes_per_row).take(self.height as usize) {
+                pixels.extend_from_slice(&row[..row_len]);
+            }
+        }
+        self.buffer.unmap();
+
+        if swap_red_blue {
+            for p in pixels.chunks_exact_mut(4) {
+                p.swap(0, 2);
+            }
+        }
+        // The surface is opaque as far as the screenshot is concerned.
+        for p in pixels.chunks_exact_mut(4) {
+            p[3] = 255;
+        }
+
+        writer.send(PngJob {
+            paths: self.paths,
+            width: self.width,
+            height: self.height,
+            pixels,
+        });
+    }
+}
+
+struct PngJob {
+    paths: Vec<PathBuf>,
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+// One thread encoding the PNGs in order. The queue is bounded, so a sequence that records faster than the frames can
+// be encoded waits on the writer rather than piling up frames in memory.
+struct PngWriter {
+    sender: std::sync::mpsc::SyncSender<PngJob>,
+}
+
+impl PngWriter {
+    const QUEUED_FRAMES: usize = 4;
+
+    fn new() -> Self {
+        let (sender, receiver) = std::sync::mpsc::sync_channel::<PngJob>(Self::QUEUED_FRAMES);
+        std::thread::Builder::new()
+            .name("Frame Capture Writer".to_owned())
+            .spawn(move || {
+                for job in receiver {
+                    for path in &job.paths {
+                        save_png(path, job.width, job.height, &job.pixels);
+                    }
+                }
+            })
+            .expect("Could not start the frame capture writer");
+        Self { sender }
+    }
+
+    fn send(&self, job: PngJob) {
+        if self.sender.send(job).is_err() {
+            println!("[FrameCapture] The writer thread has stopped");
+        }
+    }
+}
+
+fn save_png(path: &Path, width: u32, height: u32, pixels: &[u8]) {
+    match image::save_buffer(path, pixels, width, height, image::ExtendedColorType::Rgba8) {
+        Ok(_) => println!("[FrameCapture] Saved {}", path.display()),
+        Err(err) => println!("[FrameCapture] Could not save {}: {}", path.display(), err),
+    }
+}
@@ -1,5 +1,10 @@
 use crate::material::*;
+use crate::render_settings::*;
 use crate::texture::*;
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
 
 pub struct GraphicsContext {
     pub device: wgpu::Device,
@@ -10,6 +15,11 @@ pub struct GraphicsContext {
     pub texture_bind_group_layout_3d: wgpu::BindGroupLayout,
     pub bone_matrices_bind_group_layout: wgpu::BindGroupLayout,
     pub debug_material: Material,
+    pub render_settings: RenderSettings,
+    // MSAA sample counts usable with both the HDR and depth formats, in ascending order.
+    pub supported_sample_counts: Vec<u32>,
+    // Set by wgpu when the device goes away (driver reset, GPU switch). The window then recreates everything.
+    pub device_lost: Arc<AtomicBool>,
 }
 
 impl GraphicsContext {
@@ -30,10 +40,19 @@ impl GraphicsContext {
             .await
             .unwrap();
 
+        // Needed for 2x and 8x MSAA. Without it only the 4x guaranteed by WebGPU can be used.
+        let adapter_specific_formats = adapter
+            .features()
+            .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+
         let (device, queue) = adapter
             .request_device(&wgpu::DeviceDescriptor {
                 label: Some("Device"),
-                required_features: wgpu::Features::empty(),
+                required_features: if adapter_specific_formats {
+                    wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+                } else {
+                    wgpu::Features::empty()
+                },
                 experimental_features: wgpu::ExperimentalFeatures::disabled(),
                 // WebGL doesn't support all of wgpu's features, so if we're building for the web we'll have to disable some.
                 required_limits: if cfg!(target_arch = "wasm32") {
@@ -47,6 +66,15 @@ impl GraphicsContext {
             .await
             .unwrap();
 
+        let device_lost = Arc::new(AtomicBool::new(false));
+        {
+            let device_lost = device_lost.clone();
+            device.set_device_lost_callback(move |reason, message| {
+                println!("[GraphicsContext] Device lost ({:?}): {}", reason, message);
+                device_lost.store(true, Ordering::Relaxed);
+            });
+        }
+
         let surface_caps = surface.get_capabilities(&adapter);
         // Shader code assumes an Srgb surface texture. Using a different one will result all the colors comming out darker. If you want to support non Srgb surfaces, you'll need to account for that when drawing to the frame.
         let surface_format = surface_caps
@@ -93,6 +121,17 @@ impl GraphicsContext {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                         count: None,
                     },
+                    // material parameters (alpha mode)
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("textures bind group layout"),
             });
@@ -120,12 +159,53 @@ impl GraphicsContext {
                         },
                         count: None,
                     },
+                    // morph weights, MAX_MORPH_TARGETS per character
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // the model's morph deltas
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("Bone matrices storage buffer bind group layout"),
             });
 
+        let supported_sample_counts: Vec<u32> = [1, 2, 4, 8]
+            .into_iter()
+            .filter(|count| adapter_specific_formats || *count == 1 || *count == 4)
+            .filter(|count| {
+                [wgpu::TextureFormat::Rgba16Float, Texture::DEPTH_FORMAT]
+                    .iter()
+                    .all(|format| {
+                        adapter
+                            .get_texture_format_features(*format)
+                            .flags
+                            .sample_count_supported(*count)
+                    })
+            })
+            .collect();
+
+        // COPY_SRC lets the frame capture read the surface back, where it's supported.
+        let surface_usage = wgpu::TextureUsages::RENDER_ATTACHMENT
+            | (surface_caps.usages & wgpu::TextureUsages::COPY_SRC);
+
         let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            usage: surface_usage,
             format: surface_format,
             width: size.width,
             height: size.height,
@@ -167,6 +247,66 @@ impl GraphicsContext {
             texture_bind_group_layout_3d,
             bone_matrices_bind_group_layout,
             debug_material,
+            render_settings: RenderSettings::default(),
+            supported_sample_counts,
+            device_lost,
+        }
+    }
+
+    pub fn is_device_lost(&self) -> bool {
+        self.device_lost.load(Ordering::Relaxed)
+    }
+
+    // The sample count that will actually be used for the requested MSAA setting.
+    pub fn msaa_sample_count(&self) -> u32 {
+        self.supported_sample_counts
+            .iter()
+            .copied()
+            .filter(|count| *count <= self.render_settings.msaa_samples)
+            .max()
+            .unwrap_or(1)
+    }
+}
+
+// The parts of a pipeline that differ between the opaque, masked and transparent variants.
+#[derive(Debug, Copy, Clone)]
+pub struct RenderPipelineOptions {
+    pub topology: wgpu::PrimitiveTopology,
+    pub blend: wgpu::BlendState,
+    pub depth_write_enabled: bool,
+    pub depth_compare: wgpu::CompareFunction,
+    pub cull_mode: Option<wgpu::Face>,
+    pub sample_count: u32,
+    pub alpha_to_coverage_enabled: bool,
+    pub fragment_entry_point: &'static str,
+}
+
+impl Default for RenderPipelineOptions {
+    fn default() -> Self {
+        Self {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            blend: wgpu::BlendState {
+                alpha: wgpu::BlendComponent::REPLACE,
+                color: wgpu::BlendComponent::REPLACE,
+            },
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            cull_mode: Some(wgpu::Face::Back),
+            sample_count: 1,
+            alpha_to_coverage_enabled: false,
+            fragment_entry_point: "fs_main",
+        }
+    }
+}
+
+impl RenderPipelineOptions {
+    // Blended geometry is tested against the opaque depth but doesn't write it, so it can be seen through.
+    pub fn transparent() -> Self {
+        Self {
+            blend: wgpu::BlendState::ALPHA_BLENDING,
+            depth_write_enabled: false,
+            cull_mode: None,
+            ..Default::default()
         }
     }
 }
@@ -178,6 +318,26 @@ pub fn create_render_pipeline(
     depth_format: Option<wgpu::TextureFormat>,
     vertex_layouts: &[Option<wgpu::VertexBufferLayout>],
     shader: wgpu::ShaderModuleDescriptor,
+) -> wgpu::RenderPipeline {
+    create_render_pipeline_with_options(
+        device,
+        layout,
+        color_format,
+        depth_format,
+        vertex_layouts,
+        shader,
+        &RenderPipelineOptions::default(),
+    )
+}
+
+pub fn create_render_pipeline_with_options(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    depth_format: Option<wgpu::TextureFormat>,
+    vertex_layouts: &[Option<wgpu::VertexBufferLayout>],
+    shader: wgpu::ShaderModuleDescriptor,
+    options: &RenderPipelineOptions,
 ) -> wgpu::RenderPipeline {
     let shader = device.create_shader_module(shader);
 
@@ -192,22 +352,19 @@ pub fn create_render_pipeline(
         },
         fragment: Some(wgpu::FragmentState {
             module: &shader,
-            entry_point: Some("fs_main"),
+            entry_point: Some(options.fragment_entry_point),
             targets: &[Some(wgpu::ColorTargetState {
                 format: color_format,
-                blend: Some(wgpu::BlendState {
-                    alpha: wgpu::BlendComponent::REPLACE,
-                    color: wgpu::BlendComponent::REPLACE,
-                }),
+                blend: Some(options.blend),
                 write_mask: wgpu::ColorWrites::ALL,
             })],
             compilation_options: Default::default(),
         }),
         primitive: wgpu::PrimitiveState {
-            topology: wgpu::PrimitiveTopology::TriangleList,
+            topology: options.topology,
             strip_index_format: None,
             front_face: wgpu::FrontFace::Ccw,
-            cull_mode: Some(wgpu::Face::Back),
+            cull_mode: options.cull_mode,
             // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
             polygon_mode: wgpu::PolygonMode::Fill,
             // Requires Features::DEPTH_CLIP_CONTROL
@@ -217,16 +374,16 @@ pub fn create_render_pipeline(
         },
         depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
             format,
-            depth_write_enabled: Some(true),
-            depth_compare: Some(wgpu::CompareFunction::Less),
+            depth_write_enabled: Some(options.depth_write_enabled),
+            depth_compare: Some(options.depth_compare),
             stencil: wgpu::StencilState::default(),
             bias: wgpu::DepthBiasState::default(),
         }),
 
         multisample: wgpu::MultisampleState {
-            count: 1,
+            count: options.sample_count,
             mask: !0,
-            alpha_to_coverage_enabled: false,
+            alpha_to_coverage_enabled: options.alpha_to_coverage_enabled,
         },
         multiview_mask: None,
         cache: None,
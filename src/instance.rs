@@ -5,22 +5,49 @@ pub struct Instance {
     pub position: glam::Vec3A,
     pub orientation: glam::Quat,
     pub scale: glam::Vec3A,
+    // Multiplies the material's colour, alpha included.
+    pub tint: glam::Vec4,
+    // Adds the tinted colour on top of the lighting, unaffected by it. 0 is off.
+    pub emissive: f32,
+    // Not used by the built-in shaders. Passed through to the fragment stage for custom ones.
+    pub user_data: glam::Vec4,
 }
 
 impl Instance {
+    pub fn new(position: glam::Vec3A, orientation: glam::Quat, scale: glam::Vec3A) -> Self {
+        Self {
+            position,
+            orientation,
+            scale,
+            tint: glam::Vec4::ONE,
+            emissive: 0.0,
+            user_data: glam::Vec4::ZERO,
+        }
+    }
+
     pub fn to_raw(&self) -> InstanceRaw {
         InstanceRaw {
             model: glam::Mat4::from_scale_rotation_translation(self.scale.into(), self.orientation, self.position.into())
             .to_cols_array_2d(),
             normal: glam::Mat3::from_quat(self.orientation).to_cols_array_2d(),
+            tint: self.tint.to_array(),
+            emissive: self.emissive,
+            user_data: self.user_data.to_array(),
         }
     }
 
-    pub fn to_skinned_raw(&self) -> SkinnedInstanceRaw {
+    // skeleton_index is the slot of this instance's bones in the node's bone matrices buffer.
+    // It is passed explicitly so that culling can drop instances without breaking the mapping.
+    pub fn to_skinned_raw(&self, skeleton_index: u32) -> SkinnedInstanceRaw {
         SkinnedInstanceRaw {
             model: glam::Mat4::from_scale_rotation_translation(self.scale.into(), self.orientation, self.position.into())
             .to_cols_array_2d(),
             //normal: glam::Mat3::from_quat(self.orientation).to_cols_array_2d(),
+            skeleton_index,
+            morph: [0; 3],
+            tint: self.tint.to_array(),
+            emissive: self.emissive,
+            user_data: self.user_data.to_array(),
         }
     }
 }
@@ -31,6 +58,9 @@ impl Instance {
 pub struct InstanceRaw {
     pub model: [[f32; 4]; 4],
     pub normal: [[f32; 3]; 3],
+    pub tint: [f32; 4],
+    pub emissive: f32,
+    pub user_data: [f32; 4],
 }
 
 #[repr(C)]
@@ -39,6 +69,36 @@ pub struct InstanceRaw {
 pub struct SkinnedInstanceRaw {
     pub model: [[f32; 4]; 4],
     //pub normal: [[f32; 3]; 3],
+    pub skeleton_index: u32,
+    // The mesh's morph targets: offset into the model's deltas, vertex count and target count.
+    pub morph: [u32; 3],
+    pub tint: [f32; 4],
+    pub emissive: f32,
+    pub user_data: [f32; 4],
+}
+
+impl InstanceRaw {
+    // Places the instance's copy of a mesh, given the mesh's transform within its model.
+    pub fn composed(&self, mesh_m_mat: &glam::Mat4, mesh_n_mat: &glam::Mat3) -> Self {
+        let model_m_mat = glam::Mat4::from_cols_array_2d(&self.model);
+        let model_n_mat = glam::Mat3::from_cols_array_2d(&self.normal);
+        Self {
+            model: (model_m_mat * *mesh_m_mat).to_cols_array_2d(),
+            normal: (model_n_mat * *mesh_n_mat).to_cols_array_2d(),
+            ..*self
+        }
+    }
+}
+
+impl SkinnedInstanceRaw {
+    pub fn composed(&self, mesh_mat: &glam::Mat4, morph: [u32; 3]) -> Self {
+        let model_mat = glam::Mat4::from_cols_array_2d(&self.model);
+        Self {
+            model: (model_mat * *mesh_mat).to_cols_array_2d(),
+            morph,
+            ..*self
+        }
+    }
 }
 
 impl Vertex for InstanceRaw {
@@ -88,6 +148,24 @@ impl Vertex for InstanceRaw {
                     shader_location: 11,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                // Tint
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 25]>() as wgpu::BufferAddress,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                // Emissive
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 29]>() as wgpu::BufferAddress,
+                    shader_location: 13,
+                    format: wgpu::VertexFormat::Float32,
+                },
+                // User data
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 30]>() as wgpu::BufferAddress,
+                    shader_location: 14,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
             ],
         }
     }
@@ -121,6 +199,36 @@ impl Vertex for SkinnedInstanceRaw {
                     shader_location: 10,
                     format: wgpu::VertexFormat::Float32x4,
                 },
+                // Skeleton index
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+                // Morph targets
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 17]>() as wgpu::BufferAddress,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Uint32x3,
+                },
+                // Tint
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 20]>() as wgpu::BufferAddress,
+                    shader_location: 13,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                // Emissive
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 24]>() as wgpu::BufferAddress,
+                    shader_location: 14,
+                    format: wgpu::VertexFormat::Float32,
+                },
+                // User data. The last of the 16 vertex attributes skinned meshes can have.
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 25]>() as wgpu::BufferAddress,
+                    shader_location: 15,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
                 // // Normal matrix
                 // wgpu::VertexAttribute {
                 //     offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
@@ -141,3 +249,200 @@ impl Vertex for SkinnedInstanceRaw {
         }
     }
 }
+
+// A vertex buffer of per-instance data that is kept alive between frames and only reallocated when it needs to grow.
+pub struct InstanceBuffer {
+    pub buffer: wgpu::Buffer,
+    pub capacity: usize,
+    pub len: usize,
+    pub stride: usize,
+    label: String,
+}
+
+impl InstanceBuffer {
+    pub fn new(device: &wgpu::Device, label: &str, stride: usize, capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            buffer: Self::create_buffer(device, label, stride, capacity),
+            capacity,
+            len: 0,
+            stride,
+            label: label.to_owned(),
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, label: &str, stride: usize, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: (stride * capacity) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        })
+    }
+
+    // Makes room for at least `len` instances. Existing contents are discarded if the buffer has to grow.
+    pub fn reserve(&mut self, device: &wgpu::Device, len: usize) {
+        if len > self.capacity {
+            self.capacity = len.next_power_of_two();
+            self.buffer = Self::create_buffer(device, &self.label, self.stride, self.capacity);
+        }
+    }
+
+    pub fn write<T: bytemuck::Pod>(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, data: &[T]) {
+        self.reserve(device, data.len());
+        self.len = data.len();
+        if !data.is_empty() {
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(data));
+        }
+    }
+
+    // Like write, but recorded in the encoder, so graphs sharing the buffer in one submit each draw their own data.
+    pub fn upload<T: bytemuck::Pod>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        upload: &mut UploadBuffer,
+        data: &[T],
+    ) {
+        self.reserve(device, data.len());
+        self.len = data.len();
+        upload.copy_to(device, queue, encoder, data, &self.buffer, 0);
+    }
+
+    // Overwrites the instances from `start` on, keeping the others. The buffer must already have room for them.
+    pub fn write_range<T: bytemuck::Pod>(&mut self, queue: &wgpu::Queue, start: usize, data: &[T]) {
+        if !data.is_empty() {
+            queue.write_buffer(&self.buffer, self.offset_of(start), bytemuck::cast_slice(data));
+        }
+        self.len = self.len.max(start + data.len());
+    }
+
+    pub fn offset_of(&self, instance: usize) -> wgpu::BufferAddress {
+        (instance * self.stride) as wgpu::BufferAddress
+    }
+
+    pub fn slice(&self, count: usize) -> wgpu::BufferSlice<'_> {
+        self.buffer.slice(0..self.offset_of(count.max(1)))
+    }
+
+    pub fn slice_range(&self, start: usize, count: usize) -> wgpu::BufferSlice<'_> {
+        self.buffer
+            .slice(self.offset_of(start)..self.offset_of(start + count.max(1)))
+    }
+}
+
+// Instance data for one mesh of a node. `all` holds every instance (composed with the mesh's local transform)
+// and is only rewritten when the node changes. `visible` gets the culled subset, copied over on the GPU.
+pub struct MeshInstanceBuffers {
+    pub all: InstanceBuffer,
+    pub visible: InstanceBuffer,
+}
+
+impl MeshInstanceBuffers {
+    pub fn new(device: &wgpu::Device, stride: usize, capacity: usize) -> Self {
+        Self {
+            all: InstanceBuffer::new(device, "Instance Buffer", stride, capacity),
+            visible: InstanceBuffer::new(device, "Visible Instance Buffer", stride, capacity),
+        }
+    }
+
+    // Writes already gathered instances into `visible`, through the renderer's upload buffer.
+    pub fn upload_visible<T: bytemuck::Pod>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        upload: &mut UploadBuffer,
+        data: &[T],
+    ) {
+        self.visible.upload(device, queue, encoder, upload, data);
+    }
+
+    // Gathers the given runs of `all` (start, count) into `visible`.
+    pub fn copy_visible_runs(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        runs: &[(u32, u32)],
+        visible_count: usize,
+    ) {
+        self.visible.reserve(device, visible_count);
+        self.visible.len = visible_count;
+        let mut dst = 0;
+        for (start, count) in runs {
+            encoder.copy_buffer_to_buffer(
+                &self.all.buffer,
+                self.all.offset_of(*start as usize),
+                &self.visible.buffer,
+                self.visible.offset_of(dst),
+                self.all.offset_of(*count as usize),
+            );
+            dst += *count as usize;
+        }
+    }
+}
+
+// Scratch space for data that has to reach a shared buffer in encoder order. Queue writes all land before the
+// frame's encoder runs, so a buffer that several graphs write in one frame would only ever see the last graph's data.
+// Each renderer owns one of these: data is written here at increasing offsets and copied over with the encoder.
+pub struct UploadBuffer {
+    buffer: wgpu::Buffer,
+    capacity: wgpu::BufferAddress,
+    used: wgpu::BufferAddress,
+    label: String,
+}
+
+impl UploadBuffer {
+    pub fn new(device: &wgpu::Device, label: &str, capacity: wgpu::BufferAddress) -> Self {
+        let capacity = capacity.max(wgpu::COPY_BUFFER_ALIGNMENT);
+        Self {
+            buffer: Self::create_buffer(device, label, capacity),
+            capacity,
+            used: 0,
+            label: label.to_owned(),
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, label: &str, capacity: wgpu::BufferAddress) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: capacity,
+            usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    // Call once per frame, before the first copy. The previous frame was submitted, so its data can be overwritten.
+    pub fn begin_frame(&mut self) {
+        self.used = 0;
+    }
+
+    // Data must be a multiple of 4 bytes, as wgpu copies require.
+    pub fn copy_to<T: bytemuck::Pod>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        data: &[T],
+        dst: &wgpu::Buffer,
+        dst_offset: wgpu::BufferAddress,
+    ) {
+        let bytes: &[u8] = bytemuck::cast_slice(data);
+        let size = bytes.len() as wgpu::BufferAddress;
+        if size == 0 {
+            return;
+        }
+        // A new buffer starts empty. The old one stays alive until the copies recorded from it have run.
+        if self.used + size > self.capacity {
+            self.capacity = (self.used + size).next_power_of_two();
+            self.buffer = Self::create_buffer(device, &self.label, self.capacity);
+            self.used = 0;
+        }
+        queue.write_buffer(&self.buffer, self.used, bytes);
+        encoder.copy_buffer_to_buffer(&self.buffer, self.used, dst, dst_offset, size);
+        self.used = (self.used + size).next_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT);
+    }
+}
@@ -11,6 +11,7 @@ pub mod model_node;
 pub mod skinned_model_node;
 pub mod scene;
 pub mod light;
+pub mod light_clusters;
 pub mod user_context;
 pub mod callbacks;
 pub mod material;
@@ -20,10 +21,23 @@ pub mod skeletal_context;
 pub mod serialized_model;
 pub mod mesh_shapes;
 pub mod physics_context;
+pub mod physics_debug;
 pub mod character;
 pub mod asset_manager;
 pub mod egui_renderer;
 pub mod particle_system;
+pub mod culling;
+pub mod render_settings;
+pub mod debug_draw;
+pub mod picking;
+pub mod frame_capture;
+pub mod fluid_surface;
+pub mod terrain;
+pub mod viewport;
+pub mod render_target;
+pub mod baked_animation;
+pub mod crowd;
+pub mod environment;
 
 pub use bytemuck;
 pub use egui;
@@ -1,8 +1,20 @@
+use crate::environment::*;
+use crate::light_clusters::*;
 use wgpu::util::DeviceExt;
 
 pub struct LightContext {
+    // The first one is the main light, lighting everything on its own.
     pub light_uniforms: Vec<LightUniform>,
     pub light_buffer: wgpu::Buffer,
+    // Clustered, so there can be many of them. Uploaded every frame by the light culling pass.
+    pub point_lights: Vec<PointLight>,
+    pub point_light_buffer: wgpu::Buffer,
+    // Filled by the light culling pass of each render graph, before its scene passes. See light_clusters.
+    pub cluster_params_buffer: wgpu::Buffer,
+    pub cluster_light_counts_buffer: wgpu::Buffer,
+    pub cluster_light_indices_buffer: wgpu::Buffer,
+    // The fog and sky of the scene being drawn, filled by the environment pass of each render graph.
+    pub environment_buffer: wgpu::Buffer,
     pub light_bind_group_layout: wgpu::BindGroupLayout,
     pub light_bind_group: wgpu::BindGroup,
 }
@@ -15,37 +27,145 @@ impl LightContext {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
+        let point_light_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Point Light Buffer"),
+            size: (MAX_POINT_LIGHTS * std::mem::size_of::<PointLightRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let cluster_params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cluster Params Buffer"),
+            size: std::mem::size_of::<ClusterParams>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let cluster_light_counts_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cluster Light Counts Buffer"),
+            size: (NUM_CLUSTERS * std::mem::size_of::<u32>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let cluster_light_indices_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cluster Light Indices Buffer"),
+            size: (NUM_CLUSTERS * MAX_LIGHTS_PER_CLUSTER as usize * std::mem::size_of::<u32>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let environment_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Environment Buffer"),
+            size: std::mem::size_of::<EnvironmentRaw>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let storage_entry = |binding: u32| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
         let light_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // point lights
+                    storage_entry(1),
+                    // cluster params
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
+                    // cluster light counts
+                    storage_entry(3),
+                    // cluster light indices
+                    storage_entry(4),
+                    // environment
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
                 label: None,
             });
 
         let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &light_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: light_buffer.as_entire_binding(),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: light_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: point_light_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: cluster_params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: cluster_light_counts_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: cluster_light_indices_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: environment_buffer.as_entire_binding(),
+                },
+            ],
             label: None,
         });
 
         Self {
             light_uniforms,
             light_buffer,
+            point_lights: Vec::new(),
+            point_light_buffer,
+            cluster_params_buffer,
+            cluster_light_counts_buffer,
+            cluster_light_indices_buffer,
+            environment_buffer,
             light_bind_group_layout,
             light_bind_group,
         }
     }
+
+    // Returns its index, to change or remove it through point_lights.
+    pub fn add_point_light(&mut self, light: PointLight) -> usize {
+        if self.point_lights.len() == MAX_POINT_LIGHTS {
+            println!("[LightContext] More than {} point lights, the extra ones are ignored", MAX_POINT_LIGHTS);
+        }
+        self.point_lights.push(light);
+        self.point_lights.len() - 1
+    }
 }
 
 #[repr(C)]
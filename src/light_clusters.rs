@@ -0,0 +1,338 @@
+// Clustered forward lighting. The view frustum is cut into a grid of froxels, tiles across the screen and slices in depth
+// (exponentially spaced, so they stay roughly cube shaped), and each froxel gets the list of point lights reaching into it.
+// Fragments then only evaluate the lights of the froxel they fall in.
+// The assignment normally runs as a compute pass (passes::light_culling). assign_lights below is the same algorithm on
+// the CPU: the fallback when compute isn't wanted, and the reference to check the GPU results against.
+
+// Tiles across and down the screen, and depth slices.
+pub const CLUSTER_GRID: [u32; 3] = [16, 9, 24];
+pub const NUM_CLUSTERS: usize = (CLUSTER_GRID[0] * CLUSTER_GRID[1] * CLUSTER_GRID[2]) as usize;
+// Lights past this in a cluster are dropped. Each cluster has this many slots in the index list.
+pub const MAX_LIGHTS_PER_CLUSTER: u32 = 64;
+// Size of the point light buffer. Lights past this are ignored.
+pub const MAX_POINT_LIGHTS: usize = 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PointLight {
+    pub position: glam::Vec3,
+    // Linear.
+    pub color: glam::Vec3,
+    pub intensity: f32,
+    // Distance at which the light has faded out completely. Lights are culled by it.
+    pub range: f32,
+}
+
+impl PointLight {
+    pub fn new(position: glam::Vec3, color: glam::Vec3, intensity: f32, range: f32) -> Self {
+        Self {
+            position,
+            color,
+            intensity,
+            range,
+        }
+    }
+
+    pub fn to_raw(&self) -> PointLightRaw {
+        PointLightRaw {
+            position: self.position.to_array(),
+            range: self.range,
+            color: self.color.to_array(),
+            intensity: self.intensity,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PointLightRaw {
+    pub position: [f32; 3],
+    pub range: f32,
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+// What both the culling and the fragment shaders need to map between froxels and the view.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ClusterParams {
+    pub view: [[f32; 4]; 4],
+    pub inverse_projection: [[f32; 4]; 4],
+    // Grid size, then the number of point lights.
+    pub grid: [u32; 4],
+    // Of the targets the scene passes draw into, in pixels.
+    pub screen_size: [f32; 2],
+    // View space depths the slices span.
+    pub near: f32,
+    pub far: f32,
+}
+
+impl ClusterParams {
+    // The depth range is read back from the projection, so it works for perspective and orthographic alike.
+    pub fn new(view: glam::Mat4, projection: glam::Mat4, screen_size: (u32, u32), num_lights: usize) -> Self {
+        let inverse_projection = projection.inverse();
+        let near = -inverse_projection.project_point3(glam::Vec3::new(0.0, 0.0, 0.0)).z;
+        let far = -inverse_projection.project_point3(glam::Vec3::new(0.0, 0.0, 1.0)).z;
+        Self {
+            view: view.to_cols_array_2d(),
+            inverse_projection: inverse_projection.to_cols_array_2d(),
+            grid: [
+                CLUSTER_GRID[0],
+                CLUSTER_GRID[1],
+                CLUSTER_GRID[2],
+                num_lights.min(MAX_POINT_LIGHTS) as u32,
+            ],
+            screen_size: [screen_size.0.max(1) as f32, screen_size.1.max(1) as f32],
+            near: near.max(0.0001),
+            far: far.max(near + 0.0001),
+        }
+    }
+
+    // The cluster a fragment falls in, from its pixel position (origin top left) and view space depth.
+    // Same as cluster_index in the shaders.
+    pub fn cluster_index(&self, pixel: glam::Vec2, view_depth: f32) -> usize {
+        let [gx, gy, gz, _] = self.grid;
+        let tile_x = ((pixel.x / self.screen_size[0] * gx as f32) as i64).clamp(0, gx as i64 - 1) as u32;
+        let tile_y = ((pixel.y / self.screen_size[1] * gy as f32) as i64).clamp(0, gy as i64 - 1) as u32;
+        let slice = ((view_depth.max(self.near) / self.near).ln() / (self.far / self.near).ln() * gz as f32)
+            .clamp(0.0, gz as f32 - 1.0) as u32;
+        (tile_x + tile_y * gx + slice * gx * gy) as usize
+    }
+
+    // View space bounds of a cluster. Same as cluster_bounds in light_culling.wgsl.
+    pub fn cluster_bounds(&self, tile_x: u32, tile_y: u32, slice: u32) -> (glam::Vec3, glam::Vec3) {
+        let [gx, gy, gz, _] = self.grid;
+        let inverse_projection = glam::Mat4::from_cols_array_2d(&self.inverse_projection);
+        let ndc_x = [-1.0 + 2.0 * tile_x as f32 / gx as f32, -1.0 + 2.0 * (tile_x + 1) as f32 / gx as f32];
+        // Tiles count down from the top of the screen.
+        let ndc_y = [1.0 - 2.0 * tile_y as f32 / gy as f32, 1.0 - 2.0 * (tile_y + 1) as f32 / gy as f32];
+        let ratio = self.far / self.near;
+        let depths = [
+            self.near * ratio.powf(slice as f32 / gz as f32),
+            self.near * ratio.powf((slice + 1) as f32 / gz as f32),
+        ];
+
+        let mut min = glam::Vec3::splat(f32::MAX);
+        let mut max = glam::Vec3::splat(f32::MIN);
+        for x in ndc_x {
+            for y in ndc_y {
+                // The line through this corner of the tile, from the near plane to the far plane.
+                let a = inverse_projection.project_point3(glam::Vec3::new(x, y, 0.0));
+                let b = inverse_projection.project_point3(glam::Vec3::new(x, y, 1.0));
+                for depth in depths {
+                    let t = (-depth - a.z) / (b.z - a.z);
+                    let p = a + (b - a) * t;
+                    min = min.min(p);
+                    max = max.max(p);
+                }
+            }
+        }
+        (min, max)
+    }
+}
+
+// Per cluster light lists, laid out as in the GPU buffers: counts[cluster], and the cluster's lights at
+// indices[cluster * MAX_LIGHTS_PER_CLUSTER..][..count].
+pub struct ClusterAssignment {
+    pub counts: Vec<u32>,
+    pub indices: Vec<u32>,
+}
+
+impl ClusterAssignment {
+    pub fn lights(&self, cluster: usize) -> &[u32] {
+        let start = cluster * MAX_LIGHTS_PER_CLUSTER as usize;
+        &self.indices[start..start + self.counts[cluster] as usize]
+    }
+}
+
+// Assigns each light to the clusters its sphere overlaps, in light order.
+pub fn assign_lights(params: &ClusterParams, lights: &[PointLight]) -> ClusterAssignment {
+    let [gx, gy, gz, _] = params.grid;
+    let num_clusters = (gx * gy * gz) as usize;
+    let mut results = ClusterAssignment {
+        counts: vec![0; num_clusters],
+        indices: vec![0; num_clusters * MAX_LIGHTS_PER_CLUSTER as usize],
+    };
+
+    let view = glam::Mat4::from_cols_array_2d(&params.view);
+    let view_lights: Vec<(glam::Vec3, f32)> = lights
+        .iter()
+        .take(MAX_POINT_LIGHTS)
+        .map(|l| (view.transform_point3(l.position), l.range))
+        .collect();
+
+    for slice in 0..gz {
+        for tile_y in 0..gy {
+            for tile_x in 0..gx {
+                let cluster = (tile_x + tile_y * gx + slice * gx * gy) as usize;
+                let (min, max) = params.cluster_bounds(tile_x, tile_y, slice);
+                for (light_idx, (center, range)) in view_lights.iter().enumerate() {
+                    if !sphere_intersects_aabb(*center, *range, min, max) {
+                        continue;
+                    }
+                    let count = &mut results.counts[cluster];
+                    if *count == MAX_LIGHTS_PER_CLUSTER {
+                        break;
+                    }
+                    results.indices[cluster * MAX_LIGHTS_PER_CLUSTER as usize + *count as usize] = light_idx as u32;
+                    *count += 1;
+                }
+            }
+        }
+    }
+
+    results
+}
+
+pub fn sphere_intersects_aabb(center: glam::Vec3, radius: f32, min: glam::Vec3, max: glam::Vec3) -> bool {
+    let closest = center.clamp(min, max);
+    closest.distance_squared(center) <= radius * radius
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCREEN: (u32, u32) = (1600, 900);
+
+    fn camera() -> (glam::Mat4, glam::Mat4) {
+        let view = glam::Mat4::look_at_rh(glam::Vec3::new(0.0, 2.0, 10.0), glam::Vec3::ZERO, glam::Vec3::Y);
+        let projection = glam::Mat4::perspective_rh(60f32.to_radians(), 16.0 / 9.0, 0.1, 100.0);
+        (view, projection)
+    }
+
+    fn params(num_lights: usize) -> ClusterParams {
+        let (view, projection) = camera();
+        ClusterParams::new(view, projection, SCREEN, num_lights)
+    }
+
+    // Where a world space point lands on the screen, and its view space depth.
+    fn project(position: glam::Vec3) -> (glam::Vec2, f32) {
+        let (view, projection) = camera();
+        let view_position = view.transform_point3(position);
+        let ndc = projection.project_point3(view_position);
+        let pixel = glam::Vec2::new(
+            (ndc.x + 1.0) * 0.5 * SCREEN.0 as f32,
+            (1.0 - ndc.y) * 0.5 * SCREEN.1 as f32,
+        );
+        (pixel, -view_position.z)
+    }
+
+    // The view space line seen through a pixel, as its points on the near and far planes.
+    fn pixel_ray(params: &ClusterParams, pixel: glam::Vec2) -> (glam::Vec3, glam::Vec3) {
+        let inverse_projection = glam::Mat4::from_cols_array_2d(&params.inverse_projection);
+        let x = pixel.x / params.screen_size[0] * 2.0 - 1.0;
+        let y = 1.0 - pixel.y / params.screen_size[1] * 2.0;
+        (
+            inverse_projection.project_point3(glam::Vec3::new(x, y, 0.0)),
+            inverse_projection.project_point3(glam::Vec3::new(x, y, 1.0)),
+        )
+    }
+
+    fn lights() -> Vec<PointLight> {
+        vec![
+            PointLight::new(glam::Vec3::ZERO, glam::Vec3::ONE, 1.0, 2.0),
+            PointLight::new(glam::Vec3::new(-6.0, 1.0, -4.0), glam::Vec3::ONE, 1.0, 3.0),
+            PointLight::new(glam::Vec3::new(3.0, -1.0, 6.0), glam::Vec3::ONE, 1.0, 0.5),
+            PointLight::new(glam::Vec3::new(2.0, 3.0, -30.0), glam::Vec3::ONE, 1.0, 8.0),
+        ]
+    }
+
+    #[test]
+    fn light_is_in_the_cluster_of_its_center() {
+        let lights = lights();
+        let params = params(lights.len());
+        let assignment = assign_lights(&params, &lights);
+        for (light_idx, light) in lights.iter().enumerate() {
+            let (pixel, depth) = project(light.position);
+            let cluster = params.cluster_index(pixel, depth);
+            assert!(
+                assignment.lights(cluster).contains(&(light_idx as u32)),
+                "light {} is missing from cluster {}",
+                light_idx,
+                cluster
+            );
+        }
+    }
+
+    // Samples the view through every few pixels and at many depths. Wherever a sample is inside a light, the cluster
+    // it falls in has to have that light.
+    #[test]
+    fn every_froxel_a_light_reaches_has_it() {
+        let lights = lights();
+        let params = params(lights.len());
+        let assignment = assign_lights(&params, &lights);
+        let view = glam::Mat4::from_cols_array_2d(&params.view);
+
+        for (light_idx, light) in lights.iter().enumerate() {
+            let center = view.transform_point3(light.position);
+            // A little inside the range, so samples on a froxel's edge don't depend on rounding.
+            let radius = light.range * 0.99;
+            let nearest = (-center.z - radius).max(params.near);
+            let farthest = (-center.z + radius).min(params.far);
+            let mut samples_inside = 0;
+            for py in (0..SCREEN.1).step_by(12) {
+                for px in (0..SCREEN.0).step_by(12) {
+                    let pixel = glam::Vec2::new(px as f32 + 0.5, py as f32 + 0.5);
+                    let (a, b) = pixel_ray(&params, pixel);
+                    for step in 0..64 {
+                        let depth = nearest + (farthest - nearest) * step as f32 / 63.0;
+                        let sample = a + (b - a) * ((-depth - a.z) / (b.z - a.z));
+                        if sample.distance(center) > radius {
+                            continue;
+                        }
+                        samples_inside += 1;
+                        let cluster = params.cluster_index(pixel, depth);
+                        assert!(
+                            assignment.lights(cluster).contains(&(light_idx as u32)),
+                            "light {} reaches cluster {} but isn't in it",
+                            light_idx,
+                            cluster
+                        );
+                    }
+                }
+            }
+            assert!(samples_inside > 0, "light {} wasn't sampled", light_idx);
+        }
+    }
+
+    // And the other way around: a cluster only has the lights overlapping its bounds.
+    #[test]
+    fn clusters_only_have_lights_overlapping_them() {
+        let lights = lights();
+        let params = params(lights.len());
+        let assignment = assign_lights(&params, &lights);
+        let view = glam::Mat4::from_cols_array_2d(&params.view);
+        let [gx, gy, gz, _] = params.grid;
+        for slice in 0..gz {
+            for tile_y in 0..gy {
+                for tile_x in 0..gx {
+                    let cluster = (tile_x + tile_y * gx + slice * gx * gy) as usize;
+                    let (min, max) = params.cluster_bounds(tile_x, tile_y, slice);
+                    for (light_idx, light) in lights.iter().enumerate() {
+                        let center = view.transform_point3(light.position);
+                        let overlaps = sphere_intersects_aabb(center, light.range, min, max);
+                        assert_eq!(assignment.lights(cluster).contains(&(light_idx as u32)), overlaps);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn light_behind_the_camera_is_in_no_cluster() {
+        let lights = vec![PointLight::new(glam::Vec3::new(0.0, 2.0, 20.0), glam::Vec3::ONE, 1.0, 2.0)];
+        let assignment = assign_lights(&params(lights.len()), &lights);
+        assert!(assignment.counts.iter().all(|c| *c == 0));
+    }
+
+    #[test]
+    fn clusters_stop_at_max_lights() {
+        let lights = vec![PointLight::new(glam::Vec3::ZERO, glam::Vec3::ONE, 1.0, 1.0); MAX_LIGHTS_PER_CLUSTER as usize + 8];
+        let params = params(lights.len());
+        let assignment = assign_lights(&params, &lights);
+        let (pixel, depth) = project(glam::Vec3::ZERO);
+        let cluster = params.cluster_index(pixel, depth);
+        assert_eq!(assignment.counts[cluster], MAX_LIGHTS_PER_CLUSTER);
+        assert_eq!(assignment.lights(cluster)[0], 0);
+    }
+}
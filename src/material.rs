@@ -1,10 +1,55 @@
 use crate::texture;
+use wgpu::util::DeviceExt;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AlphaMode {
+    Opaque,
+    // Fragments with an alpha below the cutoff are discarded. Drawn with the opaque geometry.
+    Mask { cutoff: f32 },
+    // Alpha blended, drawn back to front after the opaque geometry without writing depth.
+    Blend,
+}
+
+impl Default for AlphaMode {
+    fn default() -> Self {
+        AlphaMode::Opaque
+    }
+}
+
+// Matches MaterialParams in the shaders. alpha_mode is 0 for opaque, 1 for mask and 2 for blend.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+    pub alpha_mode: u32,
+    pub alpha_cutoff: f32,
+    pub _padding: [f32; 2],
+}
+
+impl MaterialUniform {
+    pub fn new(alpha_mode: AlphaMode) -> Self {
+        let (mode, cutoff) = match alpha_mode {
+            AlphaMode::Opaque => (0, 0.0),
+            AlphaMode::Mask { cutoff } => (1, cutoff),
+            AlphaMode::Blend => (2, 0.0),
+        };
+        Self {
+            alpha_mode: mode,
+            alpha_cutoff: cutoff,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Clone)]
 pub struct Material {
     pub name: String,
     pub diffuse_texture: texture::Texture,
     pub normal_texture: texture::Texture,
+    pub alpha_mode: AlphaMode,
+    // Masked materials get smoothed edges from alpha-to-coverage when drawn into a multisampled target (eg: foliage).
+    pub alpha_to_coverage: bool,
+    pub params_buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
 }
 
@@ -16,7 +61,107 @@ impl Material {
         normal_texture: texture::Texture,
         layout: &wgpu::BindGroupLayout,
     ) -> Self {
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        let alpha_mode = AlphaMode::Opaque;
+        let params_buffer = Self::create_params_buffer(device, name, alpha_mode);
+        let bind_group = Self::create_bind_group(
+            device,
+            name,
+            &diffuse_texture,
+            &normal_texture,
+            &params_buffer,
+            layout,
+        );
+
+        Self {
+            name: String::from(name),
+            diffuse_texture,
+            normal_texture,
+            alpha_mode,
+            alpha_to_coverage: false,
+            params_buffer,
+            bind_group,
+        }
+    }
+
+    // Recreates the parameter buffer rather than writing to it, as clones of a material share their buffers.
+    pub fn set_alpha_mode(
+        &mut self,
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        alpha_mode: AlphaMode,
+    ) {
+        self.alpha_mode = alpha_mode;
+        self.params_buffer = Self::create_params_buffer(device, &self.name, alpha_mode);
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.name,
+            &self.diffuse_texture,
+            &self.normal_texture,
+            &self.params_buffer,
+            layout,
+        );
+    }
+
+    // Makes the material again on a new device, with its textures recreated from what they were made from and its
+    // alpha settings kept.
+    pub fn recreate(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        recreated: &mut texture::RecreatedTextures,
+    ) -> anyhow::Result<Self> {
+        use anyhow::Context;
+        let diffuse_texture = self
+            .diffuse_texture
+            .recreate(device, queue, recreated)
+            .with_context(|| format!("Could not recreate the diffuse texture of material {}", self.name))?;
+        let normal_texture = self
+            .normal_texture
+            .recreate(device, queue, recreated)
+            .with_context(|| format!("Could not recreate the normal texture of material {}", self.name))?;
+        let params_buffer = Self::create_params_buffer(device, &self.name, self.alpha_mode);
+        let bind_group = Self::create_bind_group(
+            device,
+            &self.name,
+            &diffuse_texture,
+            &normal_texture,
+            &params_buffer,
+            layout,
+        );
+
+        Ok(Self {
+            name: self.name.clone(),
+            diffuse_texture,
+            normal_texture,
+            alpha_mode: self.alpha_mode,
+            alpha_to_coverage: self.alpha_to_coverage,
+            params_buffer,
+            bind_group,
+        })
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        self.alpha_mode == AlphaMode::Blend
+    }
+
+    fn create_params_buffer(device: &wgpu::Device, name: &str, alpha_mode: AlphaMode) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} params", name)),
+            contents: bytemuck::cast_slice(&[MaterialUniform::new(alpha_mode)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        })
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        name: &str,
+        diffuse_texture: &texture::Texture,
+        normal_texture: &texture::Texture,
+        params_buffer: &wgpu::Buffer,
+        layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout,
             entries: &[
                 wgpu::BindGroupEntry {
@@ -35,15 +180,12 @@ impl Material {
                     binding: 3,
                     resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: params_buffer.as_entire_binding(),
+                },
             ],
             label: Some(name),
-        });
-
-        Self {
-            name: String::from(name),
-            diffuse_texture,
-            normal_texture,
-            bind_group,
-        }
+        })
     }
 }
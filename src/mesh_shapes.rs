@@ -1,155 +1,587 @@
-// use crate::serialized_model::*;
-// use glam::*;
-// use std::f64::consts::PI;
-// use mesh_graph::*;
-
-
-// pub fn cube(scale: f64) -> SerializedModel {
-//     let v = builder::vertex(Point3::new(-scale / 2.0, -scale / 2.0, -scale / 2.0));
-//     let e = builder::tsweep(&v, Vector3::new(scale, 0.0, 0.0));
-//     let f = builder::tsweep(&e, Vector3::new(0.0, scale, 0.0));
-//     let cube = builder::tsweep(&f, Vector3::new(0.0, 0.0, scale));
-
-//     get_model(&cube)
-// }
-
-// pub fn cuboid(x: f64, y: f64, z: f64) -> SerializedModel {
-//     let v = builder::vertex(Point3::new(-x / 2.0, -y / 2.0, -z / 2.0));
-//     let e = builder::tsweep(&v, Vector3::new(x, 0.0, 0.0));
-//     let f = builder::tsweep(&e, Vector3::new(0.0, y, 0.0));
-//     let cuboid = builder::tsweep(&f, Vector3::new(0.0, 0.0, z));
-
-//     get_model(&cuboid)
-// }
-
-// pub fn sphere(radius: f64) -> SerializedModel {
-//     let v0 = builder::vertex(Point3::new(0.0, radius, 0.0));
-//     let wire = builder::rsweep(&v0, Point3::origin(), Vector3::unit_x(), Rad(PI));
-//     let shell = builder::cone(&wire, Vector3::unit_y(), Rad(7.0));
-//     let sphere = Solid::new(vec![shell]);
-
-//     get_model(&sphere)
-// }
-
-// pub fn cylinder(height: f64, radius: f64) -> SerializedModel {
-//     let vertex = builder::vertex(Point3::new(0.0, -height / 2.0, radius));
-//     let circle = builder::rsweep(&vertex, Point3::origin(), Vector3::unit_y(), Rad(7.0));
-//     let disk = builder::try_attach_plane(&[circle]).unwrap();
-//     let cylinder = builder::tsweep(&disk, Vector3::new(0.0, height, 0.0));
-
-//     get_model(&cylinder)
-// }
-
-// pub fn cone(height: f64, radius: f64) -> SerializedModel {
-//     let v0 = builder::vertex(Point3::new(0.0, height / 2.0, 0.0));
-//     let v1 = builder::vertex(Point3::new(0.0, -height / 2.0, radius));
-//     let v2 = builder::vertex(Point3::new(0.0, -height / 2.0, 0.0));
-//     let wire = vec![builder::line(&v0, &v1), builder::line(&v1, &v2)].into();
-//     let shell = builder::cone(&wire, Vector3::unit_y(), Rad(7.0));
-//     let cone = Solid::new(vec![shell]);
-
-//     get_model(&cone)
-// }
-
-// pub fn capsule_y(length: f64, radius: f64) -> SerializedModel {
-//     let residual_length = length - 2.0 * radius;
-
-//     let v0 = builder::vertex(Point3::new(0.0, length / 2.0, 0.0));
-//     let v1 = builder::vertex(Point3::new(0.0, residual_length / 2.0, radius));
-//     let v2 = builder::vertex(Point3::new(0.0, -residual_length / 2.0, radius));
-//     let v3 = builder::vertex(Point3::new(0.0, -length / 2.0, 0.0));
-//     let arc1 = builder::circle_arc(
-//         &v0,
-//         &v1,
-//         Point3::new(
-//             0.0,
-//             (residual_length / 2.0) + (libm::sqrt(2.0) / 2.0) * radius,
-//             (libm::sqrt(2.0) / 2.0) * radius,
-//         ),
-//     );
-//     let arc2 = builder::circle_arc(
-//         &v2,
-//         &v3,
-//         Point3::new(
-//             0.0,
-//             -(residual_length / 2.0) - (libm::sqrt(2.0) / 2.0) * radius,
-//             libm::sqrt(2.0) / 2.0 * radius,
-//         ),
-//     );
-
-//     let wire = vec![arc1, builder::line(&v1, &v2), arc2].into();
-//     let shell = builder::cone(&wire, Vector3::unit_y(), Rad(7.0));
-//     let capsule = Solid::new(vec![shell]);
-
-//     get_model(&capsule)
-// }
-
-// pub fn get_model(solid: &Solid) -> SerializedModel {
-//     let mut polygon_mesh = solid.triangulation(0.005).to_polygon();
-//     let triangulation = polygon_mesh.triangulate();
-//     let mut result = SerializedModel::new();
-//     result.meshes.push(SerializedMesh::new());
-//     let mut max_extents = [0.0 as f32; 3];
-//     let mut min_extents = [0.0 as f32; 3];
-//     for p in triangulation.positions() {
-//         result.meshes[0]
-//             .positions
-//             .push([p.x as f32, p.y as f32, p.z as f32]);
-//         let mut i = 0;
-//         while i < 3 {
-//             let biggest: f32;
-//             if max_extents[i] > p[i] as f32 {
-//                 biggest = max_extents[i] as f32;
-//             } else {
-//                 biggest = p[i] as f32;
-//             }
-//             max_extents[i] = biggest;
-
-//             let smallest: f32;
-//             if min_extents[i] < p[i] as f32 {
-//                 smallest = min_extents[i];
-//             } else {
-//                 smallest = p[i] as f32;
-//             }
-//             min_extents[i] = smallest;
-
-//             i += 1;
-//         }
-//     }
-
-//     result.meshes[0].max_extents = max_extents;
-//     result.meshes[0].min_extents = min_extents;
-
-//     let mut dims = [0.0 as f32; 3];
-//     let mut i = 0;
-//     while i < 3 {
-//         dims[i] = max_extents[i] - min_extents[i];
-//         i += 1;
-//     }
-//     result.meshes[0].dimensions = dims;
-
-//     for n in triangulation.normals() {
-//         result.meshes[0]
-//             .normals
-//             .push([n.x as f32, n.y as f32, n.z as f32]);
-//     }
-
-//     for t in triangulation.uv_coords() {
-//         result.meshes[0].uvs.push([t.x as f32, t.y as f32])
-//     }
-
-//     let faces = triangulation.faces();
-
-//     let triangles = faces.tri_faces();
-
-//     for t in triangles {
-//         result.meshes[0].indices.push(t[0].pos as u32);
-//         result.meshes[0].indices.push(t[1].pos as u32);
-//         result.meshes[0].indices.push(t[2].pos as u32);
-//     }
-
-//     result.meshes[0].scale = [1.0, 1.0, 1.0];
-//     result.meshes[0].rotation = glam::Quat::IDENTITY.to_array();
-
-//     result
-// }
+// Primitive shapes, generated as SerializedModels so they load through load_model_from_serialized like any file would.
+// All are centered on the origin with Y up, wound counter-clockwise when seen from outside, and have no materials
+// (so they get the default one). UVs have v going up, as the loader flips them.
+
+use crate::serialized_model::*;
+use glam::*;
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+// Around the Y axis, for the round shapes.
+pub const DEFAULT_SEGMENTS: u32 = 32;
+// From pole to pole, for the sphere and capsule.
+pub const DEFAULT_RINGS: u32 = 16;
+
+pub fn cube(scale: f32) -> SerializedModel {
+    cuboid(scale, scale, scale)
+}
+
+pub fn cuboid(x: f32, y: f32, z: f32) -> SerializedModel {
+    let half = vec3(x, y, z) * 0.5;
+    let mut builder = ShapeBuilder::new("Cuboid");
+    // Each face's normal, and two axes across it such that u x v = normal.
+    let faces = [
+        (Vec3::X, Vec3::NEG_Z, Vec3::Y),
+        (Vec3::NEG_X, Vec3::Z, Vec3::Y),
+        (Vec3::Y, Vec3::X, Vec3::NEG_Z),
+        (Vec3::NEG_Y, Vec3::X, Vec3::Z),
+        (Vec3::Z, Vec3::X, Vec3::Y),
+        (Vec3::NEG_Z, Vec3::NEG_X, Vec3::Y),
+    ];
+    for (n, u, v) in faces {
+        builder.quad(n * half, u * half, v * half, n);
+    }
+    builder.build()
+}
+
+// Flat on XZ, facing up.
+pub fn plane(width: f32, depth: f32) -> SerializedModel {
+    let mut builder = ShapeBuilder::new("Plane");
+    builder.quad(
+        Vec3::ZERO,
+        vec3(width * 0.5, 0.0, 0.0),
+        vec3(0.0, 0.0, -depth * 0.5),
+        Vec3::Y,
+    );
+    builder.build()
+}
+
+pub fn sphere(radius: f32) -> SerializedModel {
+    sphere_with_segments(radius, DEFAULT_SEGMENTS, DEFAULT_RINGS)
+}
+
+pub fn sphere_with_segments(radius: f32, segments: u32, rings: u32) -> SerializedModel {
+    let rings = rings.max(2);
+    let profile: Vec<ProfilePoint> = (0..=rings)
+        .map(|r| {
+            let phi = PI * r as f32 / rings as f32;
+            let normal = vec2(phi.sin(), phi.cos());
+            ProfilePoint {
+                position: normal * radius,
+                normal,
+                v: 1.0 - r as f32 / rings as f32,
+            }
+        })
+        .collect();
+    let mut builder = ShapeBuilder::new("Sphere");
+    builder.lathe(&profile, segments);
+    builder.build()
+}
+
+pub fn cylinder(height: f32, radius: f32) -> SerializedModel {
+    let half = height * 0.5;
+    let mut builder = ShapeBuilder::new("Cylinder");
+    builder.lathe(
+        &[
+            ProfilePoint {
+                position: vec2(radius, half),
+                normal: Vec2::X,
+                v: 1.0,
+            },
+            ProfilePoint {
+                position: vec2(radius, -half),
+                normal: Vec2::X,
+                v: 0.0,
+            },
+        ],
+        DEFAULT_SEGMENTS,
+    );
+    builder.disk(half, radius, true, DEFAULT_SEGMENTS);
+    builder.disk(-half, radius, false, DEFAULT_SEGMENTS);
+    builder.build()
+}
+
+// Apex up.
+pub fn cone(height: f32, radius: f32) -> SerializedModel {
+    let half = height * 0.5;
+    // Perpendicular to the slope.
+    let normal = vec2(height, radius).normalize();
+    let mut builder = ShapeBuilder::new("Cone");
+    builder.lathe(
+        &[
+            ProfilePoint {
+                position: vec2(0.0, half),
+                normal,
+                v: 1.0,
+            },
+            ProfilePoint {
+                position: vec2(radius, -half),
+                normal,
+                v: 0.0,
+            },
+        ],
+        DEFAULT_SEGMENTS,
+    );
+    builder.disk(-half, radius, false, DEFAULT_SEGMENTS);
+    builder.build()
+}
+
+// The length is end to end, caps included, as with the physics capsules.
+pub fn capsule_y(length: f32, radius: f32) -> SerializedModel {
+    let half_residual = (length * 0.5 - radius).max(0.0);
+    let total = 2.0 * (half_residual + radius);
+    let half_rings = (DEFAULT_RINGS / 2).max(1);
+    let mut profile = Vec::<ProfilePoint>::new();
+    // Top hemisphere down to its equator, then the bottom one from its equator. The straight part is the quads between the two.
+    for (center, start) in [(half_residual, 0), (-half_residual, half_rings)] {
+        for r in start..=start + half_rings {
+            let phi = PI * r as f32 / (2 * half_rings) as f32;
+            let normal = vec2(phi.sin(), phi.cos());
+            let position = normal * radius + vec2(0.0, center);
+            profile.push(ProfilePoint {
+                position,
+                normal,
+                v: (position.y + total * 0.5) / total,
+            });
+        }
+    }
+    let mut builder = ShapeBuilder::new("Capsule");
+    builder.lathe(&profile, DEFAULT_SEGMENTS);
+    builder.build()
+}
+
+// Lying flat, around the Y axis.
+pub fn torus(major_radius: f32, minor_radius: f32) -> SerializedModel {
+    let sides = DEFAULT_SEGMENTS / 2;
+    // Around the tube, starting from its top and going outwards first, so it winds like the other lathed shapes.
+    let profile: Vec<ProfilePoint> = (0..=sides)
+        .map(|i| {
+            let angle = 2.0 * PI * i as f32 / sides as f32;
+            let normal = vec2(angle.sin(), angle.cos());
+            ProfilePoint {
+                position: vec2(major_radius, 0.0) + normal * minor_radius,
+                normal,
+                v: 1.0 - i as f32 / sides as f32,
+            }
+        })
+        .collect();
+    let mut builder = ShapeBuilder::new("Torus");
+    builder.lathe(&profile, DEFAULT_SEGMENTS);
+    builder.build()
+}
+
+// Subdivided icosahedron. Evenly spread triangles, unlike the UV sphere's. Each subdivision quadruples the triangle count.
+// Triangles across the seam have u up to 1.5, so textures on it should repeat.
+pub fn icosphere(radius: f32, subdivisions: u32) -> SerializedModel {
+    let t = (1.0 + 5.0_f32.sqrt()) * 0.5;
+    let mut points: Vec<Vec3> = [
+        vec3(-1.0, t, 0.0),
+        vec3(1.0, t, 0.0),
+        vec3(-1.0, -t, 0.0),
+        vec3(1.0, -t, 0.0),
+        vec3(0.0, -1.0, t),
+        vec3(0.0, 1.0, t),
+        vec3(0.0, -1.0, -t),
+        vec3(0.0, 1.0, -t),
+        vec3(t, 0.0, -1.0),
+        vec3(t, 0.0, 1.0),
+        vec3(-t, 0.0, -1.0),
+        vec3(-t, 0.0, 1.0),
+    ]
+    .iter()
+    .map(|p| p.normalize())
+    .collect();
+    // Turned to stand on opposite vertices, so the poles are vertices at every subdivision and the triangles around
+    // them are symmetric.
+    let turn = Quat::from_rotation_arc(points[0], Vec3::Y);
+    for p in points.iter_mut() {
+        *p = turn * *p;
+    }
+    points[0] = Vec3::Y;
+    points[3] = Vec3::NEG_Y;
+    let mut triangles: Vec<[u32; 3]> = vec![
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+
+    // Past this the index count gets out of hand.
+    for _ in 0..subdivisions.min(7) {
+        let mut midpoints = HashMap::<(u32, u32), u32>::new();
+        let mut midpoint = |a: u32, b: u32, points: &mut Vec<Vec3>| -> u32 {
+            let key = (a.min(b), a.max(b));
+            *midpoints.entry(key).or_insert_with(|| {
+                points.push(((points[a as usize] + points[b as usize]) * 0.5).normalize());
+                points.len() as u32 - 1
+            })
+        };
+        let mut subdivided = Vec::<[u32; 3]>::with_capacity(triangles.len() * 4);
+        for [a, b, c] in triangles {
+            let ab = midpoint(a, b, &mut points);
+            let bc = midpoint(b, c, &mut points);
+            let ca = midpoint(c, a, &mut points);
+            subdivided.push([a, ab, ca]);
+            subdivided.push([b, bc, ab]);
+            subdivided.push([c, ca, bc]);
+            subdivided.push([ab, bc, ca]);
+        }
+        triangles = subdivided;
+    }
+
+    let mut builder = ShapeBuilder::new("Icosphere");
+    let mut indices_by_uv = HashMap::<(u32, u32), u32>::new();
+    for tri in triangles {
+        let mut uvs = tri.map(|i| spherical_uv(points[i as usize]));
+        // A pole's u is meaningless. It's left out of the seam test, then takes the average of the other two.
+        let is_pole = tri.map(|i| i == 0 || i == 3);
+        // Triangles straddling the seam get their low side wrapped past 1, on vertices of their own.
+        let around: Vec<f32> = (0..3).filter(|i| !is_pole[*i]).map(|i| uvs[i].x).collect();
+        let max_u = around.iter().copied().fold(0.0, f32::max);
+        let min_u = around.iter().copied().fold(1.0, f32::min);
+        if max_u - min_u > 0.5 {
+            for (uv, pole) in uvs.iter_mut().zip(is_pole) {
+                if !pole && uv.x < 0.5 {
+                    uv.x += 1.0;
+                }
+            }
+        }
+        for i in 0..3 {
+            if is_pole[i] {
+                uvs[i].x = (uvs[(i + 1) % 3].x + uvs[(i + 2) % 3].x) * 0.5;
+            }
+        }
+        let corners: Vec<u32> = (0..3)
+            .map(|i| {
+                let key = (tri[i], uvs[i].x.to_bits());
+                *indices_by_uv.entry(key).or_insert_with(|| {
+                    let n = points[tri[i] as usize];
+                    builder.vertex(n * radius, n, uvs[i])
+                })
+            })
+            .collect();
+        builder.triangle(corners[0], corners[1], corners[2]);
+    }
+    builder.build()
+}
+
+// Matches the lathed sphere's: u goes around from +Z towards +X, v up from the bottom pole.
+fn spherical_uv(n: Vec3) -> Vec2 {
+    vec2(
+        (n.x.atan2(n.z) / (2.0 * PI)).rem_euclid(1.0),
+        0.5 + n.y.clamp(-1.0, 1.0).asin() / PI,
+    )
+}
+
+// A point of a shape's outline in the XY plane: distance from the Y axis and height.
+struct ProfilePoint {
+    position: Vec2,
+    normal: Vec2,
+    v: f32,
+}
+
+struct ShapeBuilder {
+    mesh: SerializedMesh,
+}
+
+impl ShapeBuilder {
+    fn new(name: &str) -> Self {
+        let mut mesh = SerializedMesh::new();
+        mesh.name = name.to_owned();
+        Self { mesh }
+    }
+
+    fn vertex(&mut self, position: Vec3, normal: Vec3, uv: Vec2) -> u32 {
+        self.mesh.positions.push(position.to_array());
+        self.mesh.normals.push(normal.to_array());
+        self.mesh.uvs.push(uv.to_array());
+        self.mesh.positions.len() as u32 - 1
+    }
+
+    fn triangle(&mut self, a: u32, b: u32, c: u32) {
+        self.mesh.indices.extend_from_slice(&[a, b, c]);
+    }
+
+    // A rectangle around center, spanned by the half extents u and v. u x v must point along the normal.
+    fn quad(&mut self, center: Vec3, u: Vec3, v: Vec3, normal: Vec3) {
+        let a = self.vertex(center - u - v, normal, vec2(0.0, 0.0));
+        let b = self.vertex(center + u - v, normal, vec2(1.0, 0.0));
+        let c = self.vertex(center + u + v, normal, vec2(1.0, 1.0));
+        let d = self.vertex(center - u + v, normal, vec2(0.0, 1.0));
+        self.triangle(a, b, c);
+        self.triangle(a, c, d);
+    }
+
+    // Sweeps the profile around the Y axis. The profile goes from top to bottom for the faces to point outwards
+    // (or, for flat parts, from the axis outwards when facing up). Points on the axis make fans instead of quads.
+    fn lathe(&mut self, profile: &[ProfilePoint], segments: u32) {
+        let segments = segments.max(3);
+        let first = self.mesh.positions.len() as u32;
+        for p in profile {
+            // The seam is duplicated so u can go all the way to 1.
+            for s in 0..=segments {
+                let theta = 2.0 * PI * s as f32 / segments as f32;
+                let (sin, cos) = theta.sin_cos();
+                self.vertex(
+                    vec3(p.position.x * sin, p.position.y, p.position.x * cos),
+                    vec3(p.normal.x * sin, p.normal.y, p.normal.x * cos).normalize_or_zero(),
+                    vec2(s as f32 / segments as f32, p.v),
+                );
+            }
+        }
+        let row = segments + 1;
+        for (i, pair) in profile.windows(2).enumerate() {
+            let upper = first + i as u32 * row;
+            let lower = upper + row;
+            for s in 0..segments {
+                let (a, b) = (lower + s, lower + s + 1);
+                let (c, d) = (upper + s + 1, upper + s);
+                if pair[1].position.x > 0.0 {
+                    self.triangle(a, b, c);
+                }
+                if pair[0].position.x > 0.0 {
+                    self.triangle(a, c, d);
+                }
+            }
+        }
+    }
+
+    // A flat cap at height y, mapped to the UV square.
+    fn disk(&mut self, y: f32, radius: f32, facing_up: bool, segments: u32) {
+        let segments = segments.max(3);
+        let normal = if facing_up { Vec3::Y } else { Vec3::NEG_Y };
+        let center = self.vertex(vec3(0.0, y, 0.0), normal, vec2(0.5, 0.5));
+        for s in 0..segments {
+            let theta = 2.0 * PI * s as f32 / segments as f32;
+            let (sin, cos) = theta.sin_cos();
+            // Seen from above, +X to the right and -Z up the texture.
+            self.vertex(
+                vec3(radius * sin, y, radius * cos),
+                normal,
+                vec2(0.5 + sin * 0.5, 0.5 - cos * 0.5),
+            );
+        }
+        for s in 0..segments {
+            let rim = center + 1 + s;
+            let next = center + 1 + (s + 1) % segments;
+            if facing_up {
+                self.triangle(center, rim, next);
+            } else {
+                self.triangle(center, next, rim);
+            }
+        }
+    }
+
+    fn build(mut self) -> SerializedModel {
+        let mut max_extents = Vec3::splat(f32::MIN);
+        let mut min_extents = Vec3::splat(f32::MAX);
+        for p in self.mesh.positions.iter() {
+            max_extents = max_extents.max(Vec3::from_array(*p));
+            min_extents = min_extents.min(Vec3::from_array(*p));
+        }
+        if self.mesh.positions.is_empty() {
+            max_extents = Vec3::ZERO;
+            min_extents = Vec3::ZERO;
+        }
+        self.mesh.max_extents = max_extents.to_array();
+        self.mesh.min_extents = min_extents.to_array();
+        self.mesh.dimensions = (max_extents - min_extents).to_array();
+
+        let mut result = SerializedModel::new();
+        result.meshes.push(self.mesh);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-4;
+
+    fn mesh(model: &SerializedModel) -> &SerializedMesh {
+        assert_eq!(model.meshes.len(), 1);
+        &model.meshes[0]
+    }
+
+    // core gives the point each vertex's normal should point away from, e.g. the centre for a sphere.
+    fn check_normals(model: &SerializedModel, core: impl Fn(Vec3) -> Vec3) {
+        let mesh = mesh(model);
+        assert_eq!(mesh.normals.len(), mesh.positions.len());
+        for (p, n) in mesh.positions.iter().zip(mesh.normals.iter()) {
+            let (p, n) = (Vec3::from_array(*p), Vec3::from_array(*n));
+            assert!(
+                (n.length() - 1.0).abs() < EPSILON,
+                "{} normal {n} at {p} isn't unit length",
+                mesh.name
+            );
+            assert!(
+                n.dot(p - core(p)) > 0.0,
+                "{} normal {n} at {p} points inwards",
+                mesh.name
+            );
+        }
+    }
+
+    // Counter-clockwise seen from outside, so each face's normal agrees with its vertices'.
+    fn check_winding(model: &SerializedModel) {
+        let mesh = mesh(model);
+        assert_eq!(mesh.indices.len() % 3, 0);
+        for tri in mesh.indices.chunks(3) {
+            let [a, b, c] =
+                [tri[0], tri[1], tri[2]].map(|i| Vec3::from_array(mesh.positions[i as usize]));
+            let face = (b - a).cross(c - a);
+            let normals: Vec3 = tri
+                .iter()
+                .map(|i| Vec3::from_array(mesh.normals[*i as usize]))
+                .sum();
+            assert!(
+                face.length() > 1e-8,
+                "{} has a degenerate triangle at {a}",
+                mesh.name
+            );
+            assert!(
+                face.dot(normals) > 0.0,
+                "{} triangle at {a} is wound clockwise",
+                mesh.name
+            );
+        }
+    }
+
+    fn check_extents(model: &SerializedModel, dimensions: Vec3) {
+        let mesh = mesh(model);
+        let (min, max) = (
+            Vec3::from_array(mesh.min_extents),
+            Vec3::from_array(mesh.max_extents),
+        );
+        assert!(
+            Vec3::from_array(mesh.dimensions).abs_diff_eq(dimensions, EPSILON),
+            "{} dimensions",
+            mesh.name
+        );
+        assert!(
+            max.abs_diff_eq(dimensions * 0.5, EPSILON),
+            "{} max extents {max}",
+            mesh.name
+        );
+        assert!(
+            min.abs_diff_eq(dimensions * -0.5, EPSILON),
+            "{} min extents {min}",
+            mesh.name
+        );
+    }
+
+    fn check_uvs(model: &SerializedModel, max_u: f32) {
+        let mesh = mesh(model);
+        assert_eq!(mesh.uvs.len(), mesh.positions.len());
+        for &[u, v] in mesh.uvs.iter() {
+            assert!(
+                (-EPSILON..=max_u + EPSILON).contains(&u),
+                "{} u {u} out of range",
+                mesh.name
+            );
+            assert!(
+                (-EPSILON..=1.0 + EPSILON).contains(&v),
+                "{} v {v} out of range",
+                mesh.name
+            );
+        }
+    }
+
+    fn check(model: &SerializedModel, dimensions: Vec3, core: impl Fn(Vec3) -> Vec3) {
+        check_normals(model, core);
+        check_winding(model);
+        check_extents(model, dimensions);
+        check_uvs(model, 1.0);
+    }
+
+    #[test]
+    fn cube() {
+        check(&super::cube(2.0), Vec3::splat(2.0), |_| Vec3::ZERO);
+    }
+
+    #[test]
+    fn cuboid() {
+        check(&super::cuboid(1.0, 2.0, 3.0), vec3(1.0, 2.0, 3.0), |_| {
+            Vec3::ZERO
+        });
+    }
+
+    #[test]
+    fn plane() {
+        let model = super::plane(4.0, 2.0);
+        check(&model, vec3(4.0, 0.0, 2.0), |p| p - Vec3::Y);
+        assert!(mesh(&model).normals.iter().all(|n| *n == [0.0, 1.0, 0.0]));
+    }
+
+    #[test]
+    fn sphere() {
+        check(&super::sphere(1.5), Vec3::splat(3.0), |_| Vec3::ZERO);
+    }
+
+    #[test]
+    fn cylinder() {
+        check(&super::cylinder(3.0, 0.5), vec3(1.0, 3.0, 1.0), |_| {
+            Vec3::ZERO
+        });
+    }
+
+    #[test]
+    fn cone() {
+        check(&super::cone(2.0, 1.0), vec3(2.0, 2.0, 2.0), |_| Vec3::ZERO);
+    }
+
+    #[test]
+    fn capsule() {
+        check(&capsule_y(3.0, 0.5), vec3(1.0, 3.0, 1.0), |_| Vec3::ZERO);
+    }
+
+    #[test]
+    fn torus() {
+        // Outwards from the middle of the tube rather than the origin.
+        check(&super::torus(2.0, 0.5), vec3(5.0, 1.0, 5.0), |p| {
+            vec3(p.x, 0.0, p.z).normalize() * 2.0
+        });
+    }
+
+    #[test]
+    fn icosphere() {
+        for subdivisions in 0..4 {
+            let model = super::icosphere(2.0, subdivisions);
+            check_normals(&model, |_| Vec3::ZERO);
+            check_winding(&model);
+            check_uvs(&model, 1.5);
+            let mesh = mesh(&model);
+            for p in mesh.positions.iter() {
+                assert!((Vec3::from_array(*p).length() - 2.0).abs() < EPSILON);
+            }
+            // Standing on its poles.
+            assert!((mesh.dimensions[1] - 4.0).abs() < EPSILON);
+            assert!(mesh.dimensions[0] <= 4.0 + EPSILON && mesh.dimensions[2] <= 4.0 + EPSILON);
+        }
+    }
+
+    #[test]
+    fn icosphere_poles_take_their_neighbours_u() {
+        let model = super::icosphere(1.0, 2);
+        let mesh = mesh(&model);
+        for tri in mesh.indices.chunks(3) {
+            for i in 0..3 {
+                let corner = tri[i] as usize;
+                if mesh.positions[corner][1].abs() > 0.9999 {
+                    let others =
+                        [tri[(i + 1) % 3], tri[(i + 2) % 3]].map(|j| mesh.uvs[j as usize][0]);
+                    assert!((mesh.uvs[corner][0] - (others[0] + others[1]) * 0.5).abs() < EPSILON);
+                    // Neighbours on either side of the seam were wrapped together.
+                    assert!((others[0] - others[1]).abs() < 0.5);
+                }
+            }
+        }
+    }
+}
@@ -1,7 +1,10 @@
 use std::ops::Range;
 
+use crate::culling::*;
 use crate::index_types::*;
 use crate::material::*;
+use crate::texture::RecreatedTextures;
+use wgpu::util::DeviceExt;
 
 pub trait Vertex {
     fn desc() -> wgpu::VertexBufferLayout<'static>;
@@ -81,6 +84,11 @@ pub struct TexturedMesh {
     pub rotation: glam::Quat,
     pub scale: glam::Vec3,
     pub dimensions: glam::Vec3,
+    // CPU copies of the geometry, kept for picking.
+    pub positions: Vec<glam::Vec3>,
+    pub indices: Vec<u32>,
+    // What the vertex buffer was filled with, kept to fill it again after a device loss.
+    pub vertices: Vec<ModelVertex>,
 }
 
 pub struct Model {
@@ -97,7 +105,60 @@ impl Model {
             name: "".to_owned(),
         }
     }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        let spheres: Vec<BoundingSphere> = self
+            .meshes
+            .iter()
+            .map(|m| mesh_bounding_sphere(m.translation, m.scale, m.dimensions))
+            .collect();
+        BoundingSphere::enclosing(&spheres)
+    }
+
+    // Makes the buffers and materials again on a new device, from the copies kept on the CPU.
+    pub fn recreate_gpu_resources(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture_layout: &wgpu::BindGroupLayout,
+        recreated: &mut RecreatedTextures,
+    ) -> anyhow::Result<()> {
+        for mesh in self.meshes.iter_mut() {
+            if mesh.vertices.is_empty() && mesh.num_elements > 0 {
+                anyhow::bail!("Mesh {} has no vertices kept to recreate it from", mesh.name);
+            }
+            (mesh.vertex_buffer, mesh.index_buffer) =
+                create_mesh_buffers(device, &format!("{:?}", mesh.name), &mesh.vertices, &mesh.indices);
+        }
+        let mut materials = Materials::new();
+        for material in self.materials.iter() {
+            materials.push(material.recreate(device, queue, texture_layout, recreated)?);
+        }
+        self.materials = materials;
+        Ok(())
+    }
 }
+
+// The vertex and index buffers of a mesh, labelled after it.
+pub fn create_mesh_buffers<V: bytemuck::Pod>(
+    device: &wgpu::Device,
+    label: &str,
+    vertices: &[V],
+    indices: &[u32],
+) -> (wgpu::Buffer, wgpu::Buffer) {
+    let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{} Vertex Buffer", label)),
+        contents: bytemuck::cast_slice(vertices),
+        usage: wgpu::BufferUsages::VERTEX,
+    });
+    let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{} Index Buffer", label)),
+        contents: bytemuck::cast_slice(indices),
+        usage: wgpu::BufferUsages::INDEX,
+    });
+    (vertex_buffer, index_buffer)
+}
+
 pub trait DrawModel<'a> {
     #[allow(unused)]
     fn draw_mesh(
@@ -1,18 +1,408 @@
-use crate::instance::Instance;
+use crate::instance::*;
+use std::ops::Range;
+
+// Refers to an instance of a ModelNode through removals, which move other instances around.
+// Stale once its instance is removed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct InstanceHandle {
+    slot: u32,
+    generation: u32,
+}
+
+struct HandleSlot {
+    generation: u32,
+    // None while free.
+    instance_idx: Option<usize>,
+}
 
 pub struct ModelNode {
     pub model_idx: usize,
+    // If you modify this directly, call mark_dirty() so the renderer re-uploads it.
+    // Adding or removing instances this way leaves handles pointing at the wrong ones; use add_instance and remove_instance.
     pub instances: Vec<Instance>,
-    // pub visible: Vec<bool>,
+    // By instance index. Shorter than instances when the last ones were never hidden.
+    pub hidden: Vec<bool>,
+    // By instance index. Shorter than instances when the last ones were never highlighted.
+    pub highlighted: Vec<bool>,
+    // Everything gets re-uploaded.
+    pub dirty: bool,
+    // Only these instances get re-uploaded, when dirty isn't set.
+    pub dirty_range: Option<Range<usize>>,
+    // One per mesh of the model. Created by the renderer on first use.
+    pub instance_buffers: Vec<MeshInstanceBuffers>,
+    handle_slots: Vec<HandleSlot>,
+    // By instance index, the slot of its handle.
+    slot_by_instance: Vec<u32>,
+    free_slots: Vec<u32>,
 }
 
 impl ModelNode {
     pub fn new(model_idx: usize, instances: Vec<Instance>) -> Self {
-        // let len = instances.len();
-        Self {
+        let mut results = Self {
             model_idx,
             instances,
-            // visible: vec![true; len],
+            hidden: Vec::new(),
+            highlighted: Vec::new(),
+            dirty: true,
+            dirty_range: None,
+            instance_buffers: Vec::new(),
+            handle_slots: Vec::new(),
+            slot_by_instance: Vec::new(),
+            free_slots: Vec::new(),
+        };
+        results.sync_handles();
+        results
+    }
+
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    // After changing one instance in place through instances.
+    pub fn mark_instance_dirty(&mut self, instance_idx: usize) {
+        self.mark_range_dirty(instance_idx..instance_idx + 1);
+    }
+
+    pub fn mark_range_dirty(&mut self, range: Range<usize>) {
+        self.dirty_range = Some(match self.dirty_range.take() {
+            Some(val) => val.start.min(range.start)..val.end.max(range.end),
+            None => range,
+        });
+    }
+
+    // Called by the renderer, which uploads what's in it.
+    pub fn take_dirty_range(&mut self) -> Option<Range<usize>> {
+        self.dirty_range.take()
+    }
+
+    pub fn instances_mut(&mut self) -> &mut Vec<Instance> {
+        self.dirty = true;
+        &mut self.instances
+    }
+
+    // Replaces every instance. Previous handles become stale.
+    pub fn set_instances(&mut self, instances: Vec<Instance>) {
+        self.instances = instances;
+        self.hidden.clear();
+        self.highlighted.clear();
+        for slot in self.slot_by_instance.drain(..) {
+            self.handle_slots[slot as usize].generation += 1;
+            self.handle_slots[slot as usize].instance_idx = None;
+            self.free_slots.push(slot);
+        }
+        self.sync_handles();
+        self.dirty = true;
+    }
+
+    pub fn add_instance(&mut self, instance: Instance) -> InstanceHandle {
+        self.sync_handles();
+        self.instances.push(instance);
+        let instance_idx = self.instances.len() - 1;
+        self.mark_instance_dirty(instance_idx);
+        let slot = self.allocate_slot(instance_idx);
+        self.slot_by_instance.push(slot);
+        self.handle_at(slot)
+    }
+
+    // Moves the last instance into the removed one's place, so only that one needs re-uploading.
+    // Its handle follows it, but plain indices to it don't.
+    pub fn remove_instance(&mut self, handle: InstanceHandle) -> Option<Instance> {
+        self.sync_handles();
+        let instance_idx = self.index_of(handle)?;
+        let last = self.instances.len() - 1;
+
+        let removed = self.instances.swap_remove(instance_idx);
+        swap_remove_sparse(&mut self.hidden, instance_idx, last);
+        swap_remove_sparse(&mut self.highlighted, instance_idx, last);
+
+        self.slot_by_instance.swap_remove(instance_idx);
+        if instance_idx < last {
+            let moved_slot = self.slot_by_instance[instance_idx];
+            self.handle_slots[moved_slot as usize].instance_idx = Some(instance_idx);
+            self.mark_instance_dirty(instance_idx);
+        }
+
+        let slot = &mut self.handle_slots[handle.slot as usize];
+        slot.generation += 1;
+        slot.instance_idx = None;
+        self.free_slots.push(handle.slot);
+
+        Some(removed)
+    }
+
+    pub fn index_of(&self, handle: InstanceHandle) -> Option<usize> {
+        let slot = self.handle_slots.get(handle.slot as usize)?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+        slot.instance_idx.filter(|idx| *idx < self.instances.len())
+    }
+
+    pub fn handle(&mut self, instance_idx: usize) -> Option<InstanceHandle> {
+        self.sync_handles();
+        let slot = *self.slot_by_instance.get(instance_idx)?;
+        Some(self.handle_at(slot))
+    }
+
+    pub fn instance(&self, handle: InstanceHandle) -> Option<&Instance> {
+        self.instances.get(self.index_of(handle)?)
+    }
+
+    // Marks just this instance for re-upload.
+    pub fn instance_mut(&mut self, handle: InstanceHandle) -> Option<&mut Instance> {
+        let instance_idx = self.index_of(handle)?;
+        self.mark_instance_dirty(instance_idx);
+        self.instances.get_mut(instance_idx)
+    }
+
+    // Hidden instances are skipped by drawing and picking. Doesn't touch the instance data, so nothing is re-uploaded.
+    // Returns false if the handle is stale.
+    pub fn set_visible(&mut self, handle: InstanceHandle, visible: bool) -> bool {
+        let instance_idx = match self.index_of(handle) {
+            Some(val) => val,
+            None => return false,
+        };
+        if instance_idx >= self.hidden.len() {
+            if visible {
+                return true;
+            }
+            self.hidden.resize(instance_idx + 1, false);
+        }
+        self.hidden[instance_idx] = !visible;
+        true
+    }
+
+    // Stale handles aren't visible.
+    pub fn is_visible(&self, handle: InstanceHandle) -> bool {
+        self.index_of(handle)
+            .is_some_and(|instance_idx| self.is_visible_at(instance_idx))
+    }
+
+    // For the renderers and picking, going through the instances in order.
+    pub(crate) fn is_visible_at(&self, instance_idx: usize) -> bool {
+        !self.hidden.get(instance_idx).copied().unwrap_or(false)
+    }
+
+    pub fn has_hidden(&self) -> bool {
+        self.hidden.iter().any(|h| *h)
+    }
+
+    pub fn show_all(&mut self) {
+        self.hidden.clear();
+    }
+
+    // Outlines the instance. Doesn't touch the instance data, so the node isn't marked dirty.
+    // Returns false if the handle is stale.
+    pub fn set_highlighted(&mut self, handle: InstanceHandle, highlighted: bool) -> bool {
+        let instance_idx = match self.index_of(handle) {
+            Some(val) => val,
+            None => return false,
+        };
+        if instance_idx >= self.highlighted.len() {
+            if !highlighted {
+                return true;
+            }
+            self.highlighted.resize(instance_idx + 1, false);
+        }
+        self.highlighted[instance_idx] = highlighted;
+        true
+    }
+
+    // Stale handles aren't highlighted.
+    pub fn is_highlighted(&self, handle: InstanceHandle) -> bool {
+        self.index_of(handle)
+            .is_some_and(|instance_idx| self.is_highlighted_at(instance_idx))
+    }
+
+    // For the outline pass, going through the instances in order.
+    pub(crate) fn is_highlighted_at(&self, instance_idx: usize) -> bool {
+        self.highlighted.get(instance_idx).copied().unwrap_or(false)
+    }
+
+    pub fn clear_highlights(&mut self) {
+        self.highlighted.clear();
+    }
+
+    fn handle_at(&self, slot: u32) -> InstanceHandle {
+        InstanceHandle {
+            slot,
+            generation: self.handle_slots[slot as usize].generation,
+        }
+    }
+
+    fn allocate_slot(&mut self, instance_idx: usize) -> u32 {
+        match self.free_slots.pop() {
+            Some(slot) => {
+                self.handle_slots[slot as usize].instance_idx = Some(instance_idx);
+                slot
+            }
+            None => {
+                self.handle_slots.push(HandleSlot {
+                    generation: 0,
+                    instance_idx: Some(instance_idx),
+                });
+                self.handle_slots.len() as u32 - 1
+            }
+        }
+    }
+
+    // Catches up with instances pushed or popped directly through instances.
+    fn sync_handles(&mut self) {
+        while self.slot_by_instance.len() > self.instances.len() {
+            let slot = self.slot_by_instance.pop().unwrap();
+            self.handle_slots[slot as usize].generation += 1;
+            self.handle_slots[slot as usize].instance_idx = None;
+            self.free_slots.push(slot);
+        }
+        while self.slot_by_instance.len() < self.instances.len() {
+            let slot = self.allocate_slot(self.slot_by_instance.len());
+            self.slot_by_instance.push(slot);
         }
     }
 }
+
+// swap_remove for the by-index flags that may be shorter than the instances.
+fn swap_remove_sparse(flags: &mut Vec<bool>, idx: usize, last: usize) {
+    if idx < flags.len() {
+        flags[idx] = flags.get(last).copied().unwrap_or(false);
+    }
+    flags.truncate(last);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Told apart by their x.
+    fn instance(x: f32) -> Instance {
+        Instance::new(
+            glam::Vec3A::new(x, 0.0, 0.0),
+            glam::Quat::IDENTITY,
+            glam::Vec3A::ONE,
+        )
+    }
+
+    fn xs(node: &ModelNode) -> Vec<f32> {
+        node.instances.iter().map(|i| i.position.x).collect()
+    }
+
+    fn node_with(count: usize) -> (ModelNode, Vec<InstanceHandle>) {
+        let mut node = ModelNode::new(0, (0..count).map(|i| instance(i as f32)).collect());
+        let handles = (0..count).map(|i| node.handle(i).unwrap()).collect();
+        (node, handles)
+    }
+
+    #[test]
+    fn remove_middle_moves_last_into_place() {
+        let (mut node, h) = node_with(3);
+        assert_eq!(node.remove_instance(h[1]).unwrap().position.x, 1.0);
+        assert_eq!(xs(&node), vec![0.0, 2.0]);
+        assert_eq!(node.index_of(h[0]), Some(0));
+        assert_eq!(node.index_of(h[2]), Some(1));
+        assert_eq!(node.instance(h[2]).unwrap().position.x, 2.0);
+        assert_eq!(node.handle(1), Some(h[2]));
+    }
+
+    #[test]
+    fn remove_last() {
+        let (mut node, h) = node_with(3);
+        assert_eq!(node.remove_instance(h[2]).unwrap().position.x, 2.0);
+        assert_eq!(xs(&node), vec![0.0, 1.0]);
+        assert_eq!(node.index_of(h[0]), Some(0));
+        assert_eq!(node.index_of(h[1]), Some(1));
+        assert_eq!(node.handle(2), None);
+    }
+
+    #[test]
+    fn removed_handle_is_stale() {
+        let (mut node, h) = node_with(3);
+        node.remove_instance(h[1]);
+        assert_eq!(node.index_of(h[1]), None);
+        assert!(node.instance(h[1]).is_none());
+        assert!(node.instance_mut(h[1]).is_none());
+        assert!(node.remove_instance(h[1]).is_none());
+        assert!(!node.set_visible(h[1], false));
+        assert!(!node.set_highlighted(h[1], true));
+        assert!(!node.is_visible(h[1]));
+        assert!(!node.is_highlighted(h[1]));
+        assert_eq!(xs(&node), vec![0.0, 2.0]);
+    }
+
+    #[test]
+    fn reused_slot_does_not_revive_stale_handle() {
+        let (mut node, h) = node_with(3);
+        node.remove_instance(h[1]);
+        let added = node.add_instance(instance(3.0));
+        assert_ne!(added, h[1]);
+        assert_eq!(node.index_of(h[1]), None);
+        assert_eq!(node.instance(added).unwrap().position.x, 3.0);
+    }
+
+    #[test]
+    fn set_instances_makes_handles_stale() {
+        let (mut node, h) = node_with(2);
+        node.set_highlighted(h[0], true);
+        node.set_instances(vec![instance(5.0), instance(6.0), instance(7.0)]);
+        assert!(h.iter().all(|handle| node.index_of(*handle).is_none()));
+        assert!(!node.is_highlighted_at(0));
+        let handle = node.handle(0).unwrap();
+        assert!(!h.contains(&handle));
+        assert_eq!(node.instance(handle).unwrap().position.x, 5.0);
+        assert!(node.dirty);
+    }
+
+    #[test]
+    fn flags_follow_moved_instance() {
+        let (mut node, h) = node_with(3);
+        node.set_visible(h[2], false);
+        node.set_highlighted(h[2], true);
+        node.remove_instance(h[0]);
+        assert_eq!(node.index_of(h[2]), Some(0));
+        assert!(!node.is_visible(h[2]));
+        assert!(node.is_highlighted(h[2]));
+        assert!(!node.is_visible_at(0));
+        assert!(node.is_highlighted_at(0));
+        assert!(node.is_visible(h[1]));
+        assert!(!node.is_highlighted(h[1]));
+    }
+
+    #[test]
+    fn removed_instance_flags_are_dropped() {
+        let (mut node, h) = node_with(3);
+        node.set_visible(h[0], false);
+        node.set_highlighted(h[0], true);
+        node.remove_instance(h[0]);
+        assert!(node.is_visible(h[2]));
+        assert!(!node.is_highlighted(h[2]));
+        assert!(!node.has_hidden());
+    }
+
+    #[test]
+    fn remove_marks_only_moved_instance_dirty() {
+        let (mut node, h) = node_with(4);
+        node.take_dirty_range();
+        node.remove_instance(h[1]);
+        assert_eq!(node.take_dirty_range(), Some(1..2));
+        // Nothing moves when the last one goes.
+        node.remove_instance(h[2]);
+        assert_eq!(node.take_dirty_range(), None);
+    }
+
+    #[test]
+    fn direct_push_and_pop() {
+        let (mut node, h) = node_with(2);
+        node.dirty = false;
+        node.instances_mut().push(instance(2.0));
+        assert!(node.dirty);
+        let pushed = node.handle(2).unwrap();
+        assert_eq!(node.instance(pushed).unwrap().position.x, 2.0);
+
+        node.instances_mut().pop();
+        assert_eq!(node.index_of(pushed), None);
+        // The popped slot gets reused without bringing its handle back.
+        let added = node.add_instance(instance(3.0));
+        assert_eq!(node.index_of(pushed), None);
+        assert_eq!(node.index_of(added), Some(2));
+        assert_eq!(node.index_of(h[1]), Some(1));
+    }
+}
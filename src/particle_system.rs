@@ -0,0 +1,528 @@
+// Particle emitters, simulated on the CPU and drawn by the Particles pass as instanced camera-facing billboards.
+// Emitters live on the Scene and are stepped with Scene::update_particles, which can also bounce the particles
+// off of the rapier colliders of the scene.
+
+use crate::model::Vertex;
+use crate::texture::Texture;
+use rapier3d::math::Vector;
+use rapier3d::pipeline::PhysicsWorld;
+use rapier3d::prelude::{QueryFilter, Ray};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum EmitterShape {
+    Point,
+    Sphere { radius: f32 },
+    Box { half_extents: glam::Vec3 },
+    // Around the emitter's local Y axis. Particles start on a disc of the given radius.
+    Cone { angle_rad: f32, radius: f32 },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ParticleBlendMode {
+    Additive,
+    Alpha,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Burst {
+    // Seconds since the start of the emitter's cycle.
+    pub time: f32,
+    pub count: u32,
+}
+
+pub trait Interpolate: Copy {
+    fn interpolate(a: Self, b: Self, t: f32) -> Self;
+}
+
+impl Interpolate for f32 {
+    fn interpolate(a: Self, b: Self, t: f32) -> Self {
+        a + (b - a) * t
+    }
+}
+
+impl Interpolate for glam::Vec4 {
+    fn interpolate(a: Self, b: Self, t: f32) -> Self {
+        a.lerp(b, t)
+    }
+}
+
+// Linearly interpolated keys over a particle's life, from 0 at birth to 1 at death. Keys must be sorted by time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Curve<T: Interpolate> {
+    pub keys: Vec<(f32, T)>,
+}
+
+impl<T: Interpolate> Curve<T> {
+    pub fn constant(value: T) -> Self {
+        Self {
+            keys: vec![(0.0, value)],
+        }
+    }
+
+    pub fn linear(start: T, end: T) -> Self {
+        Self {
+            keys: vec![(0.0, start), (1.0, end)],
+        }
+    }
+
+    pub fn sample(&self, t: f32) -> T {
+        let first = self.keys[0];
+        if t <= first.0 {
+            return first.1;
+        }
+        for pair in self.keys.windows(2) {
+            let (t0, v0) = pair[0];
+            let (t1, v1) = pair[1];
+            if t <= t1 {
+                let span = t1 - t0;
+                let local = if span > 0.0 { (t - t0) / span } else { 1.0 };
+                return T::interpolate(v0, v1, local);
+            }
+        }
+        self.keys[self.keys.len() - 1].1
+    }
+}
+
+// The texture is split into columns * rows frames, played left to right, top to bottom.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TextureAtlas {
+    pub columns: u32,
+    pub rows: u32,
+    // Zero plays all of the frames once over the particle's life.
+    pub frames_per_second: f32,
+    // Each particle starts on a random frame.
+    pub random_start: bool,
+}
+
+impl Default for TextureAtlas {
+    fn default() -> Self {
+        Self {
+            columns: 1,
+            rows: 1,
+            frames_per_second: 0.0,
+            random_start: false,
+        }
+    }
+}
+
+impl TextureAtlas {
+    pub fn frame_count(&self) -> u32 {
+        (self.columns * self.rows).max(1)
+    }
+
+    // Offset and scale of the frame in texture coordinates.
+    pub fn uv_rect(&self, frame: u32) -> [f32; 4] {
+        let columns = self.columns.max(1);
+        let rows = self.rows.max(1);
+        let frame = frame % self.frame_count();
+        let width = 1.0 / columns as f32;
+        let height = 1.0 / rows as f32;
+        [
+            (frame % columns) as f32 * width,
+            (frame / columns) as f32 * height,
+            width,
+            height,
+        ]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmitterSettings {
+    pub shape: EmitterShape,
+    // Particles per second.
+    pub rate: f32,
+    pub bursts: Vec<Burst>,
+    // Length of a cycle in seconds. Bursts repeat every cycle while looping.
+    pub duration: f32,
+    pub looping: bool,
+    pub max_particles: usize,
+    pub lifetime: (f32, f32),
+    pub speed: (f32, f32),
+    pub size_over_life: Curve<f32>,
+    pub color_over_life: Curve<glam::Vec4>,
+    // Radians per second, picked at random between -max and max.
+    pub max_angular_velocity: f32,
+    // Multiplies the physics world's gravity.
+    pub gravity_scale: f32,
+    // Fraction of velocity lost per second.
+    pub drag: f32,
+    // Simulates in world space when true, otherwise particles follow the emitter around.
+    pub world_space: bool,
+    pub collide: bool,
+    // Fraction of velocity kept along the normal when bouncing.
+    pub restitution: f32,
+    // Fraction of velocity kept along the surface when bouncing.
+    pub friction: f32,
+    pub atlas: TextureAtlas,
+    pub blend: ParticleBlendMode,
+}
+
+impl Default for EmitterSettings {
+    fn default() -> Self {
+        Self {
+            shape: EmitterShape::Cone {
+                angle_rad: 0.4,
+                radius: 0.1,
+            },
+            rate: 20.0,
+            bursts: Vec::new(),
+            duration: 5.0,
+            looping: true,
+            max_particles: 1000,
+            lifetime: (1.0, 2.0),
+            speed: (1.0, 2.0),
+            size_over_life: Curve::linear(0.2, 0.0),
+            color_over_life: Curve::linear(glam::Vec4::ONE, glam::Vec4::new(1.0, 1.0, 1.0, 0.0)),
+            max_angular_velocity: 0.0,
+            gravity_scale: 0.0,
+            drag: 0.0,
+            world_space: true,
+            collide: false,
+            restitution: 0.5,
+            friction: 0.8,
+            atlas: TextureAtlas::default(),
+            blend: ParticleBlendMode::Additive,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Particle {
+    pub position: glam::Vec3,
+    pub velocity: glam::Vec3,
+    pub rotation: f32,
+    pub angular_velocity: f32,
+    pub age: f32,
+    pub lifetime: f32,
+    pub start_frame: u32,
+}
+
+// Small xorshift generator, so emitters are deterministic given a seed.
+#[derive(Debug, Copy, Clone)]
+pub struct ParticleRng {
+    state: u32,
+}
+
+impl ParticleRng {
+    pub fn new(seed: u32) -> Self {
+        Self { state: seed.max(1) }
+    }
+
+    pub fn next_f32(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        (self.state >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    pub fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    pub fn unit_vector(&mut self) -> glam::Vec3 {
+        let z = self.range(-1.0, 1.0);
+        let angle = self.range(0.0, std::f32::consts::TAU);
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        glam::Vec3::new(r * angle.cos(), r * angle.sin(), z)
+    }
+}
+
+pub struct ParticleEmitter {
+    pub settings: EmitterSettings,
+    pub position: glam::Vec3,
+    pub orientation: glam::Quat,
+    pub particles: Vec<Particle>,
+    // Stops spawning when false. Particles already alive finish their lives.
+    pub emitting: bool,
+    pub visible: bool,
+    // Seconds into the current cycle.
+    pub time: f32,
+    pub texture: Option<Texture>,
+    // Created by the renderer on first use.
+    pub bind_group: Option<wgpu::BindGroup>,
+    spawn_accumulator: f32,
+    rng: ParticleRng,
+}
+
+impl ParticleEmitter {
+    pub fn new(settings: EmitterSettings, position: glam::Vec3, texture: Option<Texture>, seed: u32) -> Self {
+        Self {
+            particles: Vec::with_capacity(settings.max_particles),
+            settings,
+            position,
+            orientation: glam::Quat::IDENTITY,
+            emitting: true,
+            visible: true,
+            time: 0.0,
+            texture,
+            bind_group: None,
+            spawn_accumulator: 0.0,
+            rng: ParticleRng::new(seed),
+        }
+    }
+
+    // Replaces the texture. The renderer recreates the bind group.
+    pub fn set_texture(&mut self, texture: Option<Texture>) {
+        self.texture = texture;
+        self.bind_group = None;
+    }
+
+    pub fn restart(&mut self) {
+        self.particles.clear();
+        self.time = 0.0;
+        self.spawn_accumulator = 0.0;
+        self.emitting = true;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        !self.emitting && self.particles.is_empty()
+    }
+
+    pub fn burst(&mut self, count: u32) {
+        for _ in 0..count {
+            self.spawn();
+        }
+    }
+
+    fn spawn(&mut self) {
+        if self.particles.len() >= self.settings.max_particles {
+            return;
+        }
+        let s = &self.settings;
+        let rng = &mut self.rng;
+
+        let (local_position, local_direction) = match s.shape {
+            EmitterShape::Point => (glam::Vec3::ZERO, rng.unit_vector()),
+            EmitterShape::Sphere { radius } => {
+                let direction = rng.unit_vector();
+                (direction * radius * rng.next_f32().cbrt(), direction)
+            }
+            EmitterShape::Box { half_extents } => (
+                glam::Vec3::new(
+                    rng.range(-half_extents.x, half_extents.x),
+                    rng.range(-half_extents.y, half_extents.y),
+                    rng.range(-half_extents.z, half_extents.z),
+                ),
+                rng.unit_vector(),
+            ),
+            EmitterShape::Cone { angle_rad, radius } => {
+                let around = rng.range(0.0, std::f32::consts::TAU);
+                let distance = radius * rng.next_f32().sqrt();
+                let tilt = angle_rad * rng.next_f32().sqrt();
+                let position = glam::Vec3::new(around.cos() * distance, 0.0, around.sin() * distance);
+                let direction = glam::Vec3::new(
+                    tilt.sin() * around.cos(),
+                    tilt.cos(),
+                    tilt.sin() * around.sin(),
+                );
+                (position, direction)
+            }
+        };
+
+        let speed = rng.range(s.speed.0, s.speed.1);
+        let mut position = self.orientation * local_position;
+        if s.world_space {
+            position += self.position;
+        }
+        let start_frame = if s.atlas.random_start {
+            (rng.next_f32() * s.atlas.frame_count() as f32) as u32
+        } else {
+            0
+        };
+
+        self.particles.push(Particle {
+            position,
+            velocity: self.orientation * local_direction * speed,
+            rotation: rng.range(0.0, std::f32::consts::TAU),
+            angular_velocity: rng.range(-s.max_angular_velocity, s.max_angular_velocity),
+            age: 0.0,
+            lifetime: rng.range(s.lifetime.0, s.lifetime.1).max(f32::EPSILON),
+            start_frame,
+        });
+    }
+
+    fn emit(&mut self, dt: f32) {
+        if !self.emitting {
+            return;
+        }
+        let previous_time = self.time;
+        self.time += dt;
+
+        let bursts: Vec<u32> = self
+            .settings
+            .bursts
+            .iter()
+            .filter(|b| b.time >= previous_time && b.time < self.time)
+            .map(|b| b.count)
+            .collect();
+        for count in bursts {
+            self.burst(count);
+        }
+
+        self.spawn_accumulator += self.settings.rate * dt;
+        while self.spawn_accumulator >= 1.0 {
+            self.spawn_accumulator -= 1.0;
+            self.spawn();
+        }
+
+        if self.time >= self.settings.duration {
+            if self.settings.looping {
+                self.time -= self.settings.duration;
+                // Bursts at the very start of the next cycle.
+                let bursts: Vec<u32> = self
+                    .settings
+                    .bursts
+                    .iter()
+                    .filter(|b| b.time < self.time)
+                    .map(|b| b.count)
+                    .collect();
+                for count in bursts {
+                    self.burst(count);
+                }
+            } else {
+                self.emitting = false;
+            }
+        }
+    }
+
+    // Spawns and simulates. Pass the physics world to have gravity and (if enabled) collisions.
+    pub fn update(&mut self, dt: f32, world: Option<&PhysicsWorld>) {
+        self.emit(dt);
+
+        let gravity = match world {
+            Some(w) => glam::Vec3::new(w.gravity.x, w.gravity.y, w.gravity.z) * self.settings.gravity_scale,
+            None => glam::Vec3::ZERO,
+        };
+        let damping = (1.0 - self.settings.drag * dt).max(0.0);
+
+        for p in self.particles.iter_mut() {
+            p.age += dt;
+            p.velocity = (p.velocity + gravity * dt) * damping;
+            p.rotation += p.angular_velocity * dt;
+        }
+        self.particles.retain(|p| p.age < p.lifetime);
+
+        let collision_world = if self.settings.collide { world } else { None };
+        match collision_world {
+            Some(w) => self.move_colliding(dt, w),
+            None => {
+                for p in self.particles.iter_mut() {
+                    p.position += p.velocity * dt;
+                }
+            }
+        }
+    }
+
+    // Casts a ray along each particle's motion and bounces it off of whatever it hits first.
+    fn move_colliding(&mut self, dt: f32, world: &PhysicsWorld) {
+        let query_pipeline = world.broad_phase.as_query_pipeline(
+            world.narrow_phase.query_dispatcher(),
+            &world.bodies,
+            &world.colliders,
+            QueryFilter::default(),
+        );
+        // Collisions are tested in world space.
+        let offset = if self.settings.world_space {
+            glam::Vec3::ZERO
+        } else {
+            self.position
+        };
+        let restitution = self.settings.restitution;
+        let friction = self.settings.friction;
+
+        for p in self.particles.iter_mut() {
+            let motion = p.velocity * dt;
+            let from = p.position + offset;
+            let ray = Ray::new(
+                Vector::new(from.x, from.y, from.z),
+                Vector::new(motion.x, motion.y, motion.z),
+            );
+            match query_pipeline.cast_ray_and_get_normal(&ray, 1.0, true) {
+                Some((_, hit)) => {
+                    let normal = glam::Vec3::new(hit.normal.x, hit.normal.y, hit.normal.z);
+                    let normal_velocity = normal * p.velocity.dot(normal);
+                    let tangent_velocity = p.velocity - normal_velocity;
+                    // Stop just short of the surface, so the next cast doesn't start inside of it.
+                    p.position += motion * hit.time_of_impact + normal * 0.001;
+                    p.velocity = tangent_velocity * friction - normal_velocity * restitution;
+                }
+                None => p.position += motion,
+            }
+        }
+    }
+
+    // The emitter's particles as billboard instances.
+    pub fn instances(&self) -> Vec<ParticleInstanceRaw> {
+        let offset = if self.settings.world_space {
+            glam::Vec3::ZERO
+        } else {
+            self.position
+        };
+        let atlas = &self.settings.atlas;
+        self.particles
+            .iter()
+            .map(|p| {
+                let life = p.age / p.lifetime;
+                let frame = if atlas.frames_per_second > 0.0 {
+                    (p.age * atlas.frames_per_second) as u32
+                } else {
+                    (life * atlas.frame_count() as f32) as u32
+                };
+                ParticleInstanceRaw {
+                    position: (p.position + offset).into(),
+                    size: self.settings.size_over_life.sample(life),
+                    color: self.settings.color_over_life.sample(life).into(),
+                    uv_rect: atlas.uv_rect(p.start_frame + frame),
+                    rotation: p.rotation,
+                }
+            })
+            .collect()
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ParticleInstanceRaw {
+    pub position: [f32; 3],
+    pub size: f32,
+    pub color: [f32; 4],
+    pub uv_rect: [f32; 4],
+    pub rotation: f32,
+}
+
+impl Vertex for ParticleInstanceRaw {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<ParticleInstanceRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32,
+                },
+            ],
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use crate::graphics::*;
+use crate::passes::render_graph::*;
+use std::any::Any;
+
+pub const BLOOM_PASS: &str = "bloom";
+pub const BLOOM_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+// Length of the downsample chain. The bloom texture is declared at half resolution with this many mips.
+pub const BLOOM_MIP_LEVELS: u32 = 6;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomUniform {
+    threshold: f32,
+    knee: f32,
+    _padding: [f32; 2],
+}
+
+pub struct BloomPass {
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub prefilter_pipeline: wgpu::RenderPipeline,
+    pub downsample_pipeline: wgpu::RenderPipeline,
+    pub upsample_pipeline: wgpu::RenderPipeline,
+    pub sampler: wgpu::Sampler,
+    pub params_buffer: wgpu::Buffer,
+    pub input: String,
+    pub output: String,
+}
+
+impl RenderNode for BloomPass {
+    fn name(&self) -> &str {
+        BLOOM_PASS
+    }
+
+    fn reads(&self) -> Vec<String> {
+        vec![self.input.clone()]
+    }
+
+    fn writes(&self) -> Vec<String> {
+        vec![self.output.clone()]
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    // When bloom is disabled the tonemapper ignores the bloom texture, so nothing is drawn.
+    fn execute(
+        &mut self,
+        frame: &mut FrameContext,
+        resources: &GraphResources,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        let settings = frame.settings;
+        if !settings.bloom_enabled {
+            return;
+        }
+
+        frame.queue.write_buffer(
+            &self.params_buffer,
+            0,
+            bytemuck::cast_slice(&[BloomUniform {
+                threshold: settings.bloom_threshold,
+                knee: settings.bloom_knee.max(0.0001),
+                _padding: [0.0; 2],
+            }]),
+        );
+
+        let bloom = &resources.texture(&self.output).texture;
+        let views: Vec<wgpu::TextureView> = (0..bloom.mip_level_count())
+            .map(|mip| {
+                bloom.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Bloom Mip"),
+                    base_mip_level: mip,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        for (mip, target) in views.iter().enumerate() {
+            if mip == 0 {
+                self.draw(
+                    frame.device,
+                    encoder,
+                    &self.prefilter_pipeline,
+                    resources.view(&self.input),
+                    target,
+                    wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                );
+            } else {
+                self.draw(
+                    frame.device,
+                    encoder,
+                    &self.downsample_pipeline,
+                    &views[mip - 1],
+                    target,
+                    wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                );
+            }
+        }
+
+        for mip in (1..views.len()).rev() {
+            self.draw(
+                frame.device,
+                encoder,
+                &self.upsample_pipeline,
+                &views[mip],
+                &views[mip - 1],
+                wgpu::LoadOp::Load,
+            );
+        }
+    }
+}
+
+impl BloomPass {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("Bloom Bind Group Layout"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom Pipeline Layout"),
+            bind_group_layouts: &[Some(&bind_group_layout)],
+            immediate_size: 0,
+        });
+
+        let create_pipeline = |entry_point: &'static str, blend: wgpu::BlendState| {
+            let shader = wgpu::ShaderModuleDescriptor {
+                label: Some("Bloom Shader"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("bloom.wgsl").into()),
+            };
+            create_render_pipeline_with_options(
+                device,
+                &layout,
+                BLOOM_FORMAT,
+                None,
+                &[],
+                shader,
+                &RenderPipelineOptions {
+                    blend,
+                    cull_mode: None,
+                    fragment_entry_point: entry_point,
+                    ..Default::default()
+                },
+            )
+        };
+
+        let additive = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent::REPLACE,
+        };
+
+        let prefilter_pipeline = create_pipeline("fs_prefilter", wgpu::BlendState::REPLACE);
+        let downsample_pipeline = create_pipeline("fs_downsample", wgpu::BlendState::REPLACE);
+        let upsample_pipeline = create_pipeline("fs_upsample", additive);
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Bloom Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Bloom Params"),
+            size: std::mem::size_of::<BloomUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            bind_group_layout,
+            prefilter_pipeline,
+            downsample_pipeline,
+            upsample_pipeline,
+            sampler,
+            params_buffer,
+            input: HDR.to_owned(),
+            output: BLOOM.to_owned(),
+        }
+    }
+
+    pub fn transient_desc() -> TransientTextureDesc {
+        TransientTextureDesc {
+            mip_level_count: BLOOM_MIP_LEVELS,
+            scale: 0.5,
+            ..TransientTextureDesc::new(BLOOM_FORMAT)
+        }
+    }
+
+    fn draw(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::RenderPipeline,
+        source: &wgpu::TextureView,
+        target: &wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+    ) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.params_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("Bloom Bind Group"),
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Bloom Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+            multiview_mask: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,91 @@
+// Bloom chain, after the method used in Call of Duty: Advanced Warfare (Jimenez 2014).
+// The bright parts of the HDR scene are downsampled into successive mips, then upsampled back up with a tent
+// filter and added together.
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+}
+
+// A single triangle that covers the screen.
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.tex_coords = uv;
+    return out;
+}
+
+struct BloomParams {
+    threshold: f32,
+    knee: f32,
+}
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> params: BloomParams;
+
+fn luminance(c: vec3<f32>) -> f32 {
+    return dot(c, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// 13 tap downsample filter
+fn downsample(uv: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_source));
+    let a = textureSample(t_source, s_source, uv + texel * vec2<f32>(-2.0, 2.0)).rgb;
+    let b = textureSample(t_source, s_source, uv + texel * vec2<f32>(0.0, 2.0)).rgb;
+    let c = textureSample(t_source, s_source, uv + texel * vec2<f32>(2.0, 2.0)).rgb;
+    let d = textureSample(t_source, s_source, uv + texel * vec2<f32>(-2.0, 0.0)).rgb;
+    let e = textureSample(t_source, s_source, uv).rgb;
+    let f = textureSample(t_source, s_source, uv + texel * vec2<f32>(2.0, 0.0)).rgb;
+    let g = textureSample(t_source, s_source, uv + texel * vec2<f32>(-2.0, -2.0)).rgb;
+    let h = textureSample(t_source, s_source, uv + texel * vec2<f32>(0.0, -2.0)).rgb;
+    let i = textureSample(t_source, s_source, uv + texel * vec2<f32>(2.0, -2.0)).rgb;
+    let j = textureSample(t_source, s_source, uv + texel * vec2<f32>(-1.0, 1.0)).rgb;
+    let k = textureSample(t_source, s_source, uv + texel * vec2<f32>(1.0, 1.0)).rgb;
+    let l = textureSample(t_source, s_source, uv + texel * vec2<f32>(-1.0, -1.0)).rgb;
+    let m = textureSample(t_source, s_source, uv + texel * vec2<f32>(1.0, -1.0)).rgb;
+
+    var result = e * 0.125;
+    result += (a + c + g + i) * 0.03125;
+    result += (b + d + f + h) * 0.0625;
+    result += (j + k + l + m) * 0.125;
+    return result;
+}
+
+// First downsample from the scene. Keeps only what is above the threshold.
+@fragment
+fn fs_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = downsample(in.tex_coords);
+    let brightness = luminance(color);
+    let soft = clamp(brightness - params.threshold + params.knee, 0.0, 2.0 * params.knee);
+    let soft_curve = soft * soft / (4.0 * params.knee + 0.00001);
+    let contribution = max(soft_curve, brightness - params.threshold) / max(brightness, 0.00001);
+    return vec4<f32>(color * contribution, 1.0);
+}
+
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(downsample(in.tex_coords), 1.0);
+}
+
+// 3x3 tent filter. Added onto the next larger mip with additive blending.
+@fragment
+fn fs_upsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_source));
+    let uv = in.tex_coords;
+    var result = textureSample(t_source, s_source, uv).rgb * 4.0;
+    result += textureSample(t_source, s_source, uv + texel * vec2<f32>(-1.0, 0.0)).rgb * 2.0;
+    result += textureSample(t_source, s_source, uv + texel * vec2<f32>(1.0, 0.0)).rgb * 2.0;
+    result += textureSample(t_source, s_source, uv + texel * vec2<f32>(0.0, -1.0)).rgb * 2.0;
+    result += textureSample(t_source, s_source, uv + texel * vec2<f32>(0.0, 1.0)).rgb * 2.0;
+    result += textureSample(t_source, s_source, uv + texel * vec2<f32>(-1.0, -1.0)).rgb;
+    result += textureSample(t_source, s_source, uv + texel * vec2<f32>(1.0, -1.0)).rgb;
+    result += textureSample(t_source, s_source, uv + texel * vec2<f32>(-1.0, 1.0)).rgb;
+    result += textureSample(t_source, s_source, uv + texel * vec2<f32>(1.0, 1.0)).rgb;
+    return vec4<f32>(result / 16.0, 1.0);
+}
@@ -0,0 +1,59 @@
+// The light bind group as LightContext lays it out, at group 2, and the helpers to walk a fragment's point lights.
+// The environment at binding 5 is in environment.wgsl. Prepended to the scene shaders with concat!, so they have to
+// bind the lights at group 2.
+
+struct Light {
+    position: vec3<f32>,
+    color: vec3<f32>,
+}
+@group(2) @binding(0)
+var<uniform> light: Light;
+
+// Point lights, by the cluster the fragment falls in. See light_clusters.rs.
+struct PointLight {
+    position: vec3<f32>,
+    range: f32,
+    color: vec3<f32>,
+    intensity: f32,
+}
+struct ClusterParams {
+    view: mat4x4<f32>,
+    inverse_projection: mat4x4<f32>,
+    grid: vec4<u32>,
+    screen_size: vec2<f32>,
+    near: f32,
+    far: f32,
+}
+@group(2) @binding(1)
+var<storage, read> point_lights: array<PointLight>;
+@group(2) @binding(2)
+var<uniform> clusters: ClusterParams;
+@group(2) @binding(3)
+var<storage, read> cluster_light_counts: array<u32>;
+@group(2) @binding(4)
+var<storage, read> cluster_light_indices: array<u32>;
+
+const MAX_LIGHTS_PER_CLUSTER: u32 = 64u;
+
+fn cluster_index(frag_coord: vec2<f32>, world_position: vec3<f32>) -> u32 {
+    let grid = clusters.grid.xyz;
+    let view_depth = -(clusters.view * vec4<f32>(world_position, 1.0)).z;
+    let tile = min(vec2<u32>(max(frag_coord / clusters.screen_size * vec2<f32>(grid.xy), vec2<f32>(0.0))), grid.xy - 1u);
+    let slice = log(max(view_depth, clusters.near) / clusters.near) / log(clusters.far / clusters.near) * f32(grid.z);
+    return tile.x + tile.y * grid.x + min(u32(max(slice, 0.0)), grid.z - 1u) * grid.x * grid.y;
+}
+
+fn cluster_light_count(cluster: u32) -> u32 {
+    return min(cluster_light_counts[cluster], MAX_LIGHTS_PER_CLUSTER);
+}
+
+// The cluster's ith light, below cluster_light_count.
+fn cluster_light(cluster: u32, i: u32) -> PointLight {
+    return point_lights[cluster_light_indices[cluster * MAX_LIGHTS_PER_CLUSTER + i]];
+}
+
+// Inverse square, windowed to reach zero at the light's range.
+fn point_light_attenuation(light_distance: f32, range: f32) -> f32 {
+    let window = saturate(1.0 - pow(light_distance / range, 4.0));
+    return window * window / (light_distance * light_distance + 1.0);
+}
@@ -0,0 +1,298 @@
+// Draws the Scene's crowds after the opaque pass, skinned from their baked animation textures.
+// Members outside the view are skipped. Transparent materials aren't drawn.
+
+use crate::crowd::*;
+use crate::culling::*;
+use crate::graphics::*;
+use crate::instance::*;
+use crate::model::Vertex;
+use crate::passes::render_graph::*;
+use crate::skinned_model::*;
+use crate::texture::*;
+use rayon::prelude::*;
+use std::any::Any;
+
+pub const CROWD: &str = "crowd";
+
+pub struct CrowdRenderer {
+    pub pipeline: wgpu::RenderPipeline,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub frustum: Frustum,
+    pub culling_enabled: bool,
+    // Members drawn last frame, out of how many.
+    pub visible_members: usize,
+    pub total_members: usize,
+    pub color_target: String,
+    pub depth_target: String,
+    // The crowds' instance buffers are shared by every graph drawing the scene, so they are filled through here.
+    upload: UploadBuffer,
+}
+
+impl RenderNode for CrowdRenderer {
+    fn name(&self) -> &str {
+        CROWD
+    }
+
+    fn reads(&self) -> Vec<String> {
+        vec![
+            CAMERA_BUFFER.to_owned(),
+            LIGHT_BUFFER.to_owned(),
+            self.color_target.clone(),
+            self.depth_target.clone(),
+        ]
+    }
+
+    fn writes(&self) -> Vec<String> {
+        vec![self.color_target.clone(), self.depth_target.clone()]
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn execute(
+        &mut self,
+        frame: &mut FrameContext,
+        resources: &GraphResources,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        let crowds = &mut frame.scene.crowds;
+        let skinned_models = frame.skinned_models;
+        if crowds.iter().all(|c| !c.visible || c.members.is_empty()) {
+            return;
+        }
+
+        self.frustum = if self.culling_enabled {
+            Frustum::from_view_projection(&frame.view_projection)
+        } else {
+            Frustum::default()
+        };
+        self.visible_members = 0;
+        self.total_members = 0;
+        self.upload.begin_frame();
+
+        // Instance counts, by crowd.
+        let mut counts = vec![0u32; crowds.len()];
+        for (crowd, count) in crowds.iter_mut().zip(counts.iter_mut()) {
+            if !crowd.visible || crowd.members.is_empty() {
+                continue;
+            }
+            let model = match skinned_models.get(crowd.skinned_model_idx) {
+                Some(val) => val,
+                None => continue,
+            };
+
+            if crowd.bind_group.is_none() {
+                let texture = self.create_texture(frame.device, frame.queue, crowd);
+                crowd.bind_group = Some(self.create_bind_group(frame.device, &texture));
+                crowd.texture = Some(texture);
+            }
+
+            let bounds = model.bounding_sphere();
+            let frustum = &self.frustum;
+            let animation = &*crowd.animation;
+            let raws: Vec<CrowdInstanceRaw> = crowd
+                .members
+                .par_iter()
+                .filter(|m| frustum.intersects_sphere(&bounds.transformed_by_instance(&m.instance)))
+                .map(|m| m.to_raw(animation))
+                .collect();
+            self.total_members += crowd.members.len();
+            self.visible_members += raws.len();
+            *count = raws.len() as u32;
+            if raws.is_empty() {
+                continue;
+            }
+
+            if crowd.instance_buffers.len() != model.meshes.len() {
+                crowd.instance_buffers = model
+                    .meshes
+                    .iter()
+                    .map(|_| {
+                        InstanceBuffer::new(
+                            frame.device,
+                            "Crowd Instance Buffer",
+                            std::mem::size_of::<CrowdInstanceRaw>(),
+                            crowd.members.len(),
+                        )
+                    })
+                    .collect();
+            }
+            for (mesh, buffer) in model.meshes.iter().zip(crowd.instance_buffers.iter_mut()) {
+                let mesh_mat = glam::Mat4::from_scale_rotation_translation(mesh.scale, mesh.rotation, mesh.translation);
+                let mesh_instances: Vec<CrowdInstanceRaw> = raws.par_iter().map(|r| r.composed(&mesh_mat)).collect();
+                buffer.upload(frame.device, frame.queue, encoder, &mut self.upload, &mesh_instances);
+            }
+        }
+
+        if self.visible_members == 0 {
+            return;
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Crowd Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: resources.view(&self.color_target),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: resources.view(&self.depth_target),
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+            multiview_mask: None,
+        });
+
+        render_pass.set_pipeline(&self.pipeline);
+
+        for (crowd, count) in crowds.iter().zip(counts) {
+            if count == 0 {
+                continue;
+            }
+            let model = &skinned_models[crowd.skinned_model_idx];
+            for (mesh, buffer) in model.meshes.iter().zip(crowd.instance_buffers.iter()) {
+                let material = &model.materials[mesh.material];
+                if material.is_transparent() {
+                    continue;
+                }
+                render_pass.set_vertex_buffer(1, buffer.slice(count as usize));
+                render_pass.draw_skinned_mesh_instanced(
+                    mesh,
+                    material,
+                    0..count,
+                    resources.bind_group(CAMERA_BUFFER),
+                    resources.bind_group(LIGHT_BUFFER),
+                    crowd.bind_group.as_ref().unwrap(),
+                );
+            }
+        }
+    }
+}
+
+impl CrowdRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        light_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            }],
+            label: Some("Crowd Bind Group Layout"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Crowd Pipeline Layout"),
+            bind_group_layouts: &[
+                Some(texture_bind_group_layout),
+                Some(camera_bind_group_layout),
+                Some(light_bind_group_layout),
+                Some(&bind_group_layout),
+            ],
+            immediate_size: 0,
+        });
+
+        let pipeline = {
+            let shader = wgpu::ShaderModuleDescriptor {
+                label: Some("Crowd Shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    concat!(
+                        include_str!("clustered_lighting.wgsl"),
+                        include_str!("environment.wgsl"),
+                        include_str!("crowd.wgsl"),
+                    )
+                    .into(),
+                ),
+            };
+            create_render_pipeline_with_options(
+                device,
+                &layout,
+                color_format,
+                Some(Texture::DEPTH_FORMAT),
+                &[Some(SkinnedModelVertex::desc()), Some(CrowdInstanceRaw::desc())],
+                shader,
+                &RenderPipelineOptions {
+                    sample_count,
+                    ..Default::default()
+                },
+            )
+        };
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            frustum: Frustum::default(),
+            culling_enabled: true,
+            visible_members: 0,
+            total_members: 0,
+            color_target: HDR.to_owned(),
+            depth_target: DEPTH.to_owned(),
+            upload: UploadBuffer::new(device, "Crowd Upload Buffer", 0),
+        }
+    }
+
+    fn create_texture(&self, device: &wgpu::Device, queue: &wgpu::Queue, crowd: &Crowd) -> Texture {
+        let data = &crowd.animation;
+        let num_frames = data.num_frames();
+        let texture = Texture::create_mat4f_texture(device, "Baked Animation Texture", data.num_bones, num_frames);
+        if !data.texels.is_empty() {
+            let width = data.num_bones * 3;
+            queue.write_texture(
+                wgpu::TexelCopyTextureInfo {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                bytemuck::cast_slice(&data.texels),
+                wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(width * std::mem::size_of::<[f32; 4]>() as u32),
+                    rows_per_image: Some(num_frames),
+                },
+                wgpu::Extent3d {
+                    width,
+                    height: num_frames,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+        texture
+    }
+
+    fn create_bind_group(&self, device: &wgpu::Device, texture: &Texture) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            }],
+            label: Some("Crowd Bind Group"),
+        })
+    }
+}
@@ -0,0 +1,185 @@
+// Skinned meshes of a crowd, their bones read from a baked animation texture instead of a storage buffer.
+
+// Vertex shader
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(1) @binding(0)
+var<uniform> camera: Camera;
+
+// Baked skinning matrices, one frame per row, each bone as the top three rows of its matrix. See baked_animation.rs.
+@group(3) @binding(0)
+var baked_bones: texture_2d<f32>;
+
+fn baked_bone(frame: u32, bone: u32) -> mat4x4<f32> {
+    let x = i32(bone * 3u);
+    let y = i32(frame);
+    return transpose(mat4x4<f32>(
+        textureLoad(baked_bones, vec2<i32>(x, y), 0),
+        textureLoad(baked_bones, vec2<i32>(x + 1, y), 0),
+        textureLoad(baked_bones, vec2<i32>(x + 2, y), 0),
+        vec4<f32>(0.0, 0.0, 0.0, 1.0),
+    ));
+}
+
+fn blended_bone(frames: vec2<u32>, blend: f32, bone: u32) -> mat4x4<f32> {
+    return baked_bone(frames.x, bone) * (1.0 - blend) + baked_bone(frames.y, bone) * blend;
+}
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec3<f32>,
+    @location(4) bitangent: vec3<f32>,
+    @location(5) bone_indices: vec4<u32>,
+    @location(6) bone_weights: vec4<f32>,
+}
+
+struct InstanceInput {
+    @location(7) model_matrix_0: vec4<f32>,
+    @location(8) model_matrix_1: vec4<f32>,
+    @location(9) model_matrix_2: vec4<f32>,
+    @location(10) model_matrix_3: vec4<f32>,
+    // Rows of the baked texture to blend between, and how far along.
+    @location(11) frames: vec2<u32>,
+    @location(12) blend: f32,
+    @location(13) tint: vec4<f32>,
+    @location(14) emissive: f32,
+    @location(15) user_data: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) tangent_position: vec3<f32>,
+    @location(2) tangent_light_position: vec3<f32>,
+    @location(3) tangent_view_position: vec3<f32>,
+    @location(4) @interpolate(flat) tint: vec4<f32>,
+    @location(5) @interpolate(flat) emissive: f32,
+    // For custom shaders, the instance's user data.
+    @location(6) @interpolate(flat) user_data: vec4<f32>,
+    // For the point lights, which are brought into tangent space per fragment.
+    @location(7) world_position: vec3<f32>,
+    @location(8) world_tangent: vec3<f32>,
+    @location(9) world_bitangent: vec3<f32>,
+    @location(10) world_normal: vec3<f32>,
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+ 
+    let bone_transform = blended_bone(instance.frames, instance.blend, model.bone_indices.x) * model.bone_weights.x
+        + blended_bone(instance.frames, instance.blend, model.bone_indices.y) * model.bone_weights.y
+        + blended_bone(instance.frames, instance.blend, model.bone_indices.z) * model.bone_weights.z
+        + blended_bone(instance.frames, instance.blend, model.bone_indices.w) * model.bone_weights.w;
+
+    let world_matrix = model_matrix * bone_transform;
+    let world_position = world_matrix * vec4<f32>(model.position, 1.0);
+    let skinned_normal = normalize(mat3x3<f32>(world_matrix[0].xyz, world_matrix[1].xyz, world_matrix[2].xyz) * model.normal);
+    let transformed_tangent = bone_transform * vec4<f32>(model.tangent, 0.0);
+    let skinned_tangent = normalize(transformed_tangent.xyz);
+    let skinned_bitangent = cross(skinned_normal, skinned_tangent);
+    let tbn_matrix = transpose(mat3x3<f32>(
+        skinned_tangent,
+        skinned_bitangent,
+        skinned_normal,
+    ));
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world_position;
+    out.tex_coords = model.tex_coords;
+    out.tangent_position = tbn_matrix * world_position.xyz;
+    out.tangent_view_position = tbn_matrix * camera.view_pos.xyz;
+    out.tangent_light_position = tbn_matrix * light.position;
+    out.tint = instance.tint;
+    out.emissive = instance.emissive;
+    out.user_data = instance.user_data;
+    out.world_position = world_position.xyz;
+    out.world_tangent = skinned_tangent;
+    out.world_bitangent = skinned_bitangent;
+    out.world_normal = skinned_normal;
+    return out;
+}
+
+// Fragment shader
+
+@group(0) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0)@binding(1)
+var s_diffuse: sampler;
+@group(0)@binding(2)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(3)
+var s_normal: sampler;
+
+struct MaterialParams {
+    alpha_mode: u32,
+    alpha_cutoff: f32,
+}
+@group(0) @binding(4)
+var<uniform> material: MaterialParams;
+
+const ALPHA_MODE_MASK: u32 = 1u;
+
+fn shade(in: VertexOutput) -> vec4<f32> {
+    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords) * in.tint;
+    let object_normal: vec4<f32> = textureSample(t_normal, s_normal, in.tex_coords);
+    
+    // We don't need (or want) much ambient light, so 0.1 is fine
+    let ambient_strength = 0.1;
+    let ambient_color = light.color * ambient_strength;
+
+    // Create the lighting vectors
+    let tangent_normal = normalize(object_normal.xyz) * 2.0 - 1.0;
+    let light_dir = normalize(in.tangent_light_position - in.tangent_position);
+    let view_dir = normalize(in.tangent_view_position - in.tangent_position);
+    let half_dir = normalize(view_dir + light_dir);
+
+    let diffuse_strength = max(dot(tangent_normal, light_dir), 0.0);
+    let diffuse_color = light.color * diffuse_strength;
+
+    let specular_strength = pow(max(dot(tangent_normal, half_dir), 0.0), 32.0);
+    let specular_color = specular_strength * light.color;
+
+    var point_color = vec3<f32>(0.0);
+    let cluster = cluster_index(in.clip_position.xy, in.world_position);
+    let num_point_lights = cluster_light_count(cluster);
+    for (var i = 0u; i < num_point_lights; i++) {
+        let point_light = cluster_light(cluster, i);
+        let to_light = point_light.position - in.world_position;
+        let point_dir = normalize(vec3<f32>(
+            dot(in.world_tangent, to_light),
+            dot(in.world_bitangent, to_light),
+            dot(in.world_normal, to_light),
+        ));
+        let point_half_dir = normalize(view_dir + point_dir);
+        let radiance = point_light.color * point_light.intensity
+            * point_light_attenuation(length(to_light), point_light.range);
+        point_color += radiance * (max(dot(tangent_normal, point_dir), 0.0)
+            + pow(max(dot(tangent_normal, point_half_dir), 0.0), 32.0));
+    }
+
+    let result = (ambient_color + diffuse_color + specular_color + point_color + in.emissive) * object_color.xyz;
+
+    return vec4<f32>(apply_fog(result, camera.view_pos.xyz, in.world_position), object_color.a);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = shade(in);
+    if (material.alpha_mode == ALPHA_MODE_MASK && color.a < material.alpha_cutoff) {
+        discard;
+    }
+    return color;
+}
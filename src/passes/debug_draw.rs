@@ -0,0 +1,154 @@
+// Draws the lines queued on the DebugDraw into the scene target, so they are antialiased and tonemapped along
+// with the scene. Text labels are painted by egui instead, see DebugDraw::paint_labels.
+
+use crate::debug_draw::*;
+use crate::graphics::*;
+use crate::instance::InstanceBuffer;
+use crate::passes::render_graph::*;
+use crate::texture::*;
+use std::any::Any;
+
+pub const DEBUG_DRAW: &str = "debug_draw";
+
+pub struct DebugDrawPass {
+    pub depth_tested_pipeline: wgpu::RenderPipeline,
+    pub overlay_pipeline: wgpu::RenderPipeline,
+    pub vertex_buffer: InstanceBuffer,
+    pub color_target: String,
+    pub depth_target: String,
+}
+
+impl RenderNode for DebugDrawPass {
+    fn name(&self) -> &str {
+        DEBUG_DRAW
+    }
+
+    fn reads(&self) -> Vec<String> {
+        vec![
+            CAMERA_BUFFER.to_owned(),
+            self.color_target.clone(),
+            self.depth_target.clone(),
+        ]
+    }
+
+    fn writes(&self) -> Vec<String> {
+        vec![self.color_target.clone()]
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn execute(
+        &mut self,
+        frame: &mut FrameContext,
+        resources: &GraphResources,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        let debug_draw = frame.debug_draw;
+        if !debug_draw.enabled || debug_draw.lines.is_empty() {
+            return;
+        }
+
+        // Depth tested lines first, then the overlay ones, in one buffer.
+        let mut vertices = debug_draw.vertices(true);
+        let depth_tested_count = vertices.len() as u32;
+        vertices.extend(debug_draw.vertices(false));
+        let total = vertices.len() as u32;
+
+        self.vertex_buffer
+            .write(frame.device, frame.queue, &vertices);
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Debug Draw Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: resources.view(&self.color_target),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: resources.view(&self.depth_target),
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+            multiview_mask: None,
+        });
+
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(total as usize));
+        render_pass.set_bind_group(0, resources.bind_group(CAMERA_BUFFER), &[]);
+
+        if depth_tested_count > 0 {
+            render_pass.set_pipeline(&self.depth_tested_pipeline);
+            render_pass.draw(0..depth_tested_count, 0..1);
+        }
+        if total > depth_tested_count {
+            render_pass.set_pipeline(&self.overlay_pipeline);
+            render_pass.draw(depth_tested_count..total, 0..1);
+        }
+    }
+}
+
+impl DebugDrawPass {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug Draw Pipeline Layout"),
+            bind_group_layouts: &[Some(camera_bind_group_layout)],
+            immediate_size: 0,
+        });
+
+        let create_pipeline = |depth_compare: wgpu::CompareFunction| {
+            let shader = wgpu::ShaderModuleDescriptor {
+                label: Some("Debug Draw Shader"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("debug.wgsl").into()),
+            };
+            create_render_pipeline_with_options(
+                device,
+                &layout,
+                color_format,
+                Some(Texture::DEPTH_FORMAT),
+                &[Some(DebugVertex::desc())],
+                shader,
+                &RenderPipelineOptions {
+                    topology: wgpu::PrimitiveTopology::LineList,
+                    blend: wgpu::BlendState::ALPHA_BLENDING,
+                    depth_write_enabled: false,
+                    depth_compare,
+                    cull_mode: None,
+                    sample_count,
+                    ..Default::default()
+                },
+            )
+        };
+
+        Self {
+            depth_tested_pipeline: create_pipeline(wgpu::CompareFunction::LessEqual),
+            overlay_pipeline: create_pipeline(wgpu::CompareFunction::Always),
+            vertex_buffer: InstanceBuffer::new(
+                device,
+                "Debug Draw Vertex Buffer",
+                std::mem::size_of::<DebugVertex>(),
+                1024,
+            ),
+            color_target: HDR.to_owned(),
+            depth_target: DEPTH.to_owned(),
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use crate::environment::EnvironmentRaw;
+use crate::passes::render_graph::*;
+use std::any::Any;
+
+pub const ENVIRONMENT: &str = "environment";
+
+// Uploads the fog and sky of the scene this graph draws into the LightContext's environment buffer, read by the
+// scene shaders and the sky. Graphs share that buffer and may draw different scenes, so it's filled in encoder order
+// (see UploadBuffer).
+pub struct EnvironmentPass {
+    staging_buffer: wgpu::Buffer,
+}
+
+impl RenderNode for EnvironmentPass {
+    fn name(&self) -> &str {
+        ENVIRONMENT
+    }
+
+    fn reads(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn writes(&self) -> Vec<String> {
+        vec![LIGHT_BUFFER.to_owned()]
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn execute(
+        &mut self,
+        frame: &mut FrameContext,
+        _resources: &GraphResources,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        let environment = frame.scene.environment.to_raw();
+        frame
+            .queue
+            .write_buffer(&self.staging_buffer, 0, bytemuck::cast_slice(&[environment]));
+        encoder.copy_buffer_to_buffer(
+            &self.staging_buffer,
+            0,
+            &frame.lights.environment_buffer,
+            0,
+            self.staging_buffer.size(),
+        );
+    }
+}
+
+impl EnvironmentPass {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Environment Staging Buffer"),
+            size: std::mem::size_of::<EnvironmentRaw>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self { staging_buffer }
+    }
+}
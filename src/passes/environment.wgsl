@@ -0,0 +1,71 @@
+// The environment uniform from the light bind group, at group 2 like the rest of it, and the fog and sky helpers.
+// Prepended to the scene shaders with concat!, after clustered_lighting.wgsl.
+
+// The scene's fog and sky. See environment.rs.
+struct Environment {
+    fog_color: vec4<f32>,
+    // Density, start distance, max opacity, enabled.
+    fog_params: vec4<f32>,
+    // Density, falloff, base height.
+    height_fog: vec4<f32>,
+    // w is 1 with an atmosphere.
+    sun_direction: vec4<f32>,
+    // w is the cosine of the disk's radius.
+    sun_color: vec4<f32>,
+    zenith_color: vec4<f32>,
+    horizon_color: vec4<f32>,
+    ground_color: vec4<f32>,
+    // Anisotropy, strength.
+    mie: vec4<f32>,
+}
+@group(2) @binding(5)
+var<uniform> environment: Environment;
+
+const PI: f32 = 3.14159265;
+
+// Ground, horizon and zenith colours, plus the glow of light scattered forward around the sun.
+fn sky_color(direction: vec3<f32>) -> vec3<f32> {
+    let up = direction.y;
+    var color = mix(environment.horizon_color.rgb, environment.zenith_color.rgb, sqrt(max(up, 0.0)));
+    color = mix(color, environment.ground_color.rgb, saturate(-up * 8.0));
+    // Dimmer as the sun sets.
+    let daylight = saturate(environment.sun_direction.y * 4.0 + 0.2);
+    let g = environment.mie.x;
+    let cos_theta = dot(direction, environment.sun_direction.xyz);
+    let phase = (1.0 - g * g) / (4.0 * PI * pow(1.0 + g * g - 2.0 * g * cos_theta, 1.5));
+    return color * daylight + environment.sun_color.rgb * phase * environment.mie.y;
+}
+
+// Distance fog past the start distance, plus height fog integrated along the view ray. Same as Fog::factor.
+fn fog_factor(eye: vec3<f32>, world_position: vec3<f32>) -> f32 {
+    if (environment.fog_params.w == 0.0) {
+        return 0.0;
+    }
+    let to_point = world_position - eye;
+    let fog_distance = length(to_point);
+    var depth = environment.fog_params.x * max(fog_distance - environment.fog_params.y, 0.0);
+    let height_density = environment.height_fog.x;
+    if (height_density > 0.0) {
+        let falloff = environment.height_fog.y;
+        let k = falloff * to_point.y;
+        var along = 1.0;
+        if (abs(k) >= 0.0001) {
+            along = (1.0 - exp(-k)) / k;
+        }
+        depth += height_density * exp(-falloff * (eye.y - environment.height_fog.z)) * fog_distance * along;
+    }
+    return min(1.0 - exp(-depth), environment.fog_params.z);
+}
+
+// With an atmosphere the fog is the sky behind the fragment, so distant geometry fades into the horizon.
+fn apply_fog(color: vec3<f32>, eye: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let factor = fog_factor(eye, world_position);
+    if (factor <= 0.0) {
+        return color;
+    }
+    var fog_color = environment.fog_color.rgb;
+    if (environment.sun_direction.w > 0.0) {
+        fog_color = sky_color(normalize(world_position - eye));
+    }
+    return mix(color, fog_color, factor);
+}
@@ -0,0 +1,305 @@
+// Draws the Scene's fluid surface after the transparent pass. The scene drawn so far is first copied (or resolved,
+// with MSAA) into SCENE_COLOR, which the water samples to show what's behind it. The surface writes depth,
+// so particles and debug lines drawn afterwards are hidden by it.
+
+use crate::fluid_surface::*;
+use crate::graphics::*;
+use crate::model::Vertex;
+use crate::passes::render_graph::*;
+use crate::texture::*;
+use std::any::Any;
+
+pub const FLUID: &str = "fluid";
+
+pub struct FluidRenderer {
+    pub pipeline: wgpu::RenderPipeline,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub material_buffer: wgpu::Buffer,
+    pub sampler: wgpu::Sampler,
+    pub vertex_buffer: wgpu::Buffer,
+    pub vertex_capacity: usize,
+    pub index_buffer: wgpu::Buffer,
+    pub index_capacity: usize,
+    pub sample_count: u32,
+    pub color_target: String,
+    pub depth_target: String,
+}
+
+impl RenderNode for FluidRenderer {
+    fn name(&self) -> &str {
+        FLUID
+    }
+
+    fn reads(&self) -> Vec<String> {
+        vec![
+            CAMERA_BUFFER.to_owned(),
+            LIGHT_BUFFER.to_owned(),
+            self.color_target.clone(),
+            self.depth_target.clone(),
+        ]
+    }
+
+    fn writes(&self) -> Vec<String> {
+        vec![self.color_target.clone(), SCENE_COLOR.to_owned()]
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn execute(
+        &mut self,
+        frame: &mut FrameContext,
+        resources: &GraphResources,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        let surface = match &frame.scene.fluid_surface {
+            Some(val) if val.visible && !val.indices.is_empty() => val,
+            _ => return,
+        };
+
+        if surface.vertices.len() > self.vertex_capacity {
+            self.vertex_capacity = surface.vertices.len().next_power_of_two();
+            self.vertex_buffer = Self::create_vertex_buffer(frame.device, self.vertex_capacity);
+        }
+        frame
+            .queue
+            .write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&surface.vertices));
+        if surface.indices.len() > self.index_capacity {
+            self.index_capacity = surface.indices.len().next_power_of_two();
+            self.index_buffer = Self::create_index_buffer(frame.device, self.index_capacity);
+        }
+        frame
+            .queue
+            .write_buffer(&self.index_buffer, 0, bytemuck::cast_slice(&surface.indices));
+        frame.queue.write_buffer(
+            &self.material_buffer,
+            0,
+            bytemuck::cast_slice(&[FluidMaterialUniform::new(&surface.material)]),
+        );
+
+        let color = resources.texture(&self.color_target);
+        let scene_color = resources.texture(SCENE_COLOR);
+        if self.sample_count > 1 {
+            // Multisampled textures can't be copied, so an empty pass resolves it instead.
+            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Fluid Scene Color Resolve"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &color.view,
+                    resolve_target: Some(&scene_color.view),
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+                multiview_mask: None,
+            });
+        } else {
+            encoder.copy_texture_to_texture(
+                color.texture.as_image_copy(),
+                scene_color.texture.as_image_copy(),
+                color.texture.size(),
+            );
+        }
+
+        let bind_group = frame.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&scene_color.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.material_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("Fluid Bind Group"),
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Fluid Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &color.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: resources.view(&self.depth_target),
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+            multiview_mask: None,
+        });
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.set_bind_group(1, resources.bind_group(CAMERA_BUFFER), &[]);
+        render_pass.set_bind_group(2, resources.bind_group(LIGHT_BUFFER), &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.draw_indexed(0..surface.indices.len() as u32, 0, 0..1);
+    }
+}
+
+impl FluidRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        light_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("Fluid Bind Group Layout"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Fluid Pipeline Layout"),
+            bind_group_layouts: &[
+                Some(&bind_group_layout),
+                Some(camera_bind_group_layout),
+                Some(light_bind_group_layout),
+            ],
+            immediate_size: 0,
+        });
+
+        let pipeline = {
+            let shader = wgpu::ShaderModuleDescriptor {
+                label: Some("Fluid Shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    concat!(
+                        include_str!("clustered_lighting.wgsl"),
+                        include_str!("environment.wgsl"),
+                        include_str!("fluid.wgsl"),
+                    )
+                    .into(),
+                ),
+            };
+            create_render_pipeline_with_options(
+                device,
+                &layout,
+                color_format,
+                Some(Texture::DEPTH_FORMAT),
+                &[Some(FluidVertex::desc())],
+                shader,
+                &RenderPipelineOptions {
+                    // The surface is seen from inside as well.
+                    cull_mode: None,
+                    sample_count,
+                    ..Default::default()
+                },
+            )
+        };
+
+        let material_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Fluid Material Buffer"),
+            size: std::mem::size_of::<FluidMaterialUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Fluid Scene Color Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let vertex_capacity = 1024;
+        let index_capacity = 1024;
+        Self {
+            pipeline,
+            bind_group_layout,
+            material_buffer,
+            sampler,
+            vertex_buffer: Self::create_vertex_buffer(device, vertex_capacity),
+            vertex_capacity,
+            index_buffer: Self::create_index_buffer(device, index_capacity),
+            index_capacity,
+            sample_count,
+            color_target: HDR.to_owned(),
+            depth_target: DEPTH.to_owned(),
+        }
+    }
+
+    // The scene color copy this pass samples. Its usage allows both a copy and a resolve into it.
+    pub fn scene_color_desc() -> TransientTextureDesc {
+        TransientTextureDesc {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST,
+            ..TransientTextureDesc::new(HDR_FORMAT)
+        }
+    }
+
+    fn create_vertex_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Fluid Vertex Buffer"),
+            size: (capacity * std::mem::size_of::<FluidVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_index_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Fluid Index Buffer"),
+            size: (capacity * std::mem::size_of::<u32>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+}
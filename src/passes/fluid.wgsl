@@ -0,0 +1,68 @@
+// Water surface. The scene behind it is sampled with an offset along the normal, tinted, and blended
+// with a reflection color by a Schlick fresnel term, then fogged like the rest of the scene.
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(1) @binding(0)
+var<uniform> camera: Camera;
+
+struct FluidMaterial {
+    tint: vec4<f32>,
+    reflection_color: vec4<f32>,
+    refraction_strength: f32,
+    shininess: f32,
+    fresnel_f0: f32,
+}
+@group(0) @binding(0)
+var t_scene: texture_2d<f32>;
+@group(0) @binding(1)
+var s_scene: sampler;
+@group(0) @binding(2)
+var<uniform> material: FluidMaterial;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) world_normal: vec3<f32>,
+}
+
+@vertex
+fn vs_main(model: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(model.position, 1.0);
+    out.world_position = model.position;
+    out.world_normal = model.normal;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
+    var normal = normalize(in.world_normal);
+    if (!front_facing) {
+        normal = -normal;
+    }
+    let view_dir = normalize(camera.view_pos.xyz - in.world_position);
+
+    let screen_size = vec2<f32>(textureDimensions(t_scene));
+    let screen_uv = in.clip_position.xy / screen_size;
+    let refracted_uv = clamp(screen_uv - normal.xz * material.refraction_strength, vec2<f32>(0.0), vec2<f32>(1.0));
+    let background = textureSampleLevel(t_scene, s_scene, refracted_uv, 0.0).rgb;
+    let refraction = mix(background, background * material.tint.rgb, material.tint.a);
+
+    let cos_theta = clamp(dot(normal, view_dir), 0.0, 1.0);
+    let fresnel = material.fresnel_f0 + (1.0 - material.fresnel_f0) * pow(1.0 - cos_theta, 5.0);
+
+    let light_dir = normalize(light.position - in.world_position);
+    let half_dir = normalize(view_dir + light_dir);
+    let specular = pow(max(dot(normal, half_dir), 0.0), material.shininess) * light.color;
+
+    let color = mix(refraction, material.reflection_color.rgb, fresnel) + specular;
+    return vec4<f32>(apply_fog(color, camera.view_pos.xyz, in.world_position), 1.0);
+}
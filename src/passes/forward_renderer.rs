@@ -1,61 +1,119 @@
-use crate::graphics::create_render_pipeline;
+use crate::culling::*;
+use crate::graphics::*;
 //use crate::index_types::*;
 use crate::instance::*;
+use crate::material::*;
 use crate::model::*;
 use crate::model_node::*;
-use crate::passes::Pass;
+use crate::passes::render_graph::*;
 use crate::scene::CharactersContext;
 use crate::skinned_model::*;
 // use crate::skinned_model_node::*;
 use crate::texture::*;
 // use crate::character::Character;
-use wgpu::util::DeviceExt;
+use rayon::prelude::*;
+use std::any::Any;
+
+pub const FORWARD: &str = "forward";
 
 pub struct ForwardRenderer {
     pub render_pipeline_layout: wgpu::PipelineLayout,
     pub skinned_render_pipeline_layout: wgpu::PipelineLayout,
-    pub light_bind_group: wgpu::BindGroup,
-    pub camera_bind_group: wgpu::BindGroup,
     pub render_pipeline: wgpu::RenderPipeline,
     pub skinned_render_pipeline: wgpu::RenderPipeline,
+    // Only created for multisampled targets, where masked materials can use alpha-to-coverage.
+    pub alpha_to_coverage_pipeline: Option<wgpu::RenderPipeline>,
+    pub skinned_alpha_to_coverage_pipeline: Option<wgpu::RenderPipeline>,
     pub light_render_pipeline: wgpu::RenderPipeline,
     pub bone_matrices_bind_group_layout: wgpu::BindGroupLayout,
+    // Set from the active camera before drawing. Instances outside of it are not submitted.
+    pub frustum: Frustum,
+    pub culling_enabled: bool,
+    pub stats: CullingStats,
+    pub color_target: String,
+    pub depth_target: String,
+    pub clear_color: wgpu::Color,
+    // Compacted visible instances go through here, see prepare_model_nodes.
+    upload: UploadBuffer,
 }
 
-impl Pass for ForwardRenderer {
-    fn draw(
+// Past this many runs of visible instances, copying each run costs more than gathering them on the CPU.
+const MAX_COPIED_RUNS: usize = 32;
+
+// A node that has instances in view, and whether they have to be read from the compacted buffers.
+struct NodeDraw {
+    node_idx: usize,
+    count: u32,
+    from_visible: bool,
+}
+
+impl RenderNode for ForwardRenderer {
+    fn name(&self) -> &str {
+        FORWARD
+    }
+
+    fn reads(&self) -> Vec<String> {
+        vec![CAMERA_BUFFER.to_owned(), LIGHT_BUFFER.to_owned()]
+    }
+
+    fn writes(&self) -> Vec<String> {
+        vec![self.color_target.clone(), self.depth_target.clone()]
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn execute(
         &mut self,
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        models: &Vec<Model>,
-        skinned_models: &Vec<SkinnedModel>,
-        model_nodes: &Vec<ModelNode>,
-        characters_contexts: &Vec<CharactersContext>,
-        depth_texture_view: &wgpu::TextureView,
-        view: &wgpu::TextureView,
+        frame: &mut FrameContext,
+        resources: &GraphResources,
+        encoder: &mut wgpu::CommandEncoder,
     ) {
-        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-            label: Some("Render Encoder"),
-        });
+        self.frustum = Frustum::from_view_projection(&frame.view_projection);
+        self.stats.clear();
+        self.upload.begin_frame();
+
+        let node_draws = self.prepare_model_nodes(
+            frame.device,
+            frame.queue,
+            encoder,
+            frame.models,
+            &mut frame.scene.model_nodes,
+        );
+        let character_draws = self.prepare_characters(
+            frame.device,
+            frame.queue,
+            encoder,
+            frame.skinned_models,
+            &mut frame.scene.characters_contexts,
+        );
+
+        let models = frame.models;
+        let skinned_models = frame.skinned_models;
+        let model_nodes = &frame.scene.model_nodes;
+        let characters_contexts = &frame.scene.characters_contexts;
+        let camera_bind_group = resources.bind_group(CAMERA_BUFFER);
+        let light_bind_group = resources.bind_group(LIGHT_BUFFER);
+
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
+                    view: resources.view(&self.color_target),
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
+                        load: wgpu::LoadOp::Clear(self.clear_color),
                         store: wgpu::StoreOp::Store,
                     },
                     depth_slice: None,
                 })],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: depth_texture_view,
+                    view: resources.view(&self.depth_target),
                     depth_ops: Some(wgpu::Operations {
                         load: wgpu::LoadOp::Clear(1.0),
                         store: wgpu::StoreOp::Store,
@@ -73,142 +131,376 @@ impl Pass for ForwardRenderer {
             //     &self.camera_bind_group,
             //     &self.light_bind_group,
             // );
+            // Blended meshes are left to the transparent pass.
+            let mut alpha_to_coverage = false;
             render_pass.set_pipeline(&self.render_pipeline);
 
-            for m in model_nodes.iter() {
-                let mut count = 0;
-                let mut model_instance_data = Vec::<InstanceRaw>::new();
+            for d in &node_draws {
+                let m = &model_nodes[d.node_idx];
                 let model = &models[m.model_idx];
-                for i in &m.instances {
-                    model_instance_data.push(i.to_raw());
-                    count += 1;
-                }
-
-                for mesh in model.meshes.iter() {
-                    let mut mesh_instance_data = Vec::<InstanceRaw>::new();
 
-                    let mesh_m_mat = glam::Mat4::from_scale_rotation_translation(
-                        mesh.scale,
-                        mesh.rotation,
-                        mesh.translation,
-                    );
+                for (mesh, buffers) in model.meshes.iter().zip(m.instance_buffers.iter()) {
+                    let material = &model.materials[mesh.material];
+                    if material.is_transparent() {
+                        continue;
+                    }
 
-                    for instance in &model_instance_data {
-                        let mesh_n_mat = glam::Mat3::from_quat(mesh.rotation);
-                        let model_m_mat = glam::Mat4::from_cols_array_2d(&instance.model);
-                        let model_n_mat = glam::Mat3::from_cols_array_2d(&instance.normal);
-                        let temp = InstanceRaw {
-                            model: (model_m_mat * mesh_m_mat).to_cols_array_2d(),
-                            normal: (model_n_mat * mesh_n_mat).to_cols_array_2d(),
-                        };
-
-                        mesh_instance_data.push(temp);
+                    if let Some(pipeline) = &self.alpha_to_coverage_pipeline {
+                        let wanted = uses_alpha_to_coverage(material);
+                        if wanted != alpha_to_coverage {
+                            alpha_to_coverage = wanted;
+                            render_pass.set_pipeline(if wanted {
+                                pipeline
+                            } else {
+                                &self.render_pipeline
+                            });
+                        }
                     }
 
-                    let instance_buffer =
-                        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                            label: Some("Instance Buffer"),
-                            contents: bytemuck::cast_slice(&mesh_instance_data),
-                            usage: wgpu::BufferUsages::VERTEX,
-                        });
+                    let instance_buffer = if d.from_visible {
+                        &buffers.visible
+                    } else {
+                        &buffers.all
+                    };
 
-                    render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+                    render_pass.set_vertex_buffer(1, instance_buffer.slice(d.count as usize));
 
                     render_pass.draw_mesh_instanced(
                         &mesh,
-                        &model.materials[mesh.material],
-                        0..count,
-                        &self.camera_bind_group,
-                        &self.light_bind_group,
+                        material,
+                        0..d.count,
+                        camera_bind_group,
+                        light_bind_group,
                     );
                 }
             }
 
+            alpha_to_coverage = false;
             render_pass.set_pipeline(&self.skinned_render_pipeline);
 
-            for c in characters_contexts.iter() {
-                let mut count = 0;
-                let mut model_instances = Vec::<SkinnedInstanceRaw>::new();
+            for d in &character_draws {
+                let c = &characters_contexts[d.node_idx];
                 let model = &skinned_models[c.skinned_model_node.skinned_model_idx];
 
-                for i in &c.skinned_model_node.instances {
-                    model_instances.push(i.to_skinned_raw());
-                    count += 1;
+                for (mesh, buffers) in model
+                    .meshes
+                    .iter()
+                    .zip(c.skinned_model_node.instance_buffers.iter())
+                {
+                    let material = &model.materials[mesh.material];
+                    if material.is_transparent() {
+                        continue;
+                    }
+
+                    if let Some(pipeline) = &self.skinned_alpha_to_coverage_pipeline {
+                        let wanted = uses_alpha_to_coverage(material);
+                        if wanted != alpha_to_coverage {
+                            alpha_to_coverage = wanted;
+                            render_pass.set_pipeline(if wanted {
+                                pipeline
+                            } else {
+                                &self.skinned_render_pipeline
+                            });
+                        }
+                    }
+
+                    let instance_buffer = if d.from_visible {
+                        &buffers.visible
+                    } else {
+                        &buffers.all
+                    };
+
+                    render_pass.set_vertex_buffer(1, instance_buffer.slice(d.count as usize));
+
+                    render_pass.draw_skinned_mesh_instanced(
+                        mesh,
+                        material,
+                        0..d.count,
+                        camera_bind_group,
+                        light_bind_group,
+                        &c.skinned_model_node.bind_group,
+                    );
                 }
+            }
+        }
+    }
+}
 
-                for mesh in model.meshes.iter() {
-                    let mut mesh_instances = Vec::<SkinnedInstanceRaw>::new();
+fn uses_alpha_to_coverage(material: &Material) -> bool {
+    material.alpha_to_coverage && matches!(material.alpha_mode, AlphaMode::Mask { .. })
+}
 
-                    let mesh_mat = glam::Mat4::from_scale_rotation_translation(
+// Returns the indices of the instances that pass the frustum test, in order.
+pub fn visible_instances(
+    instances: &Vec<Instance>,
+    bounds: &BoundingSphere,
+    frustum: &Frustum,
+) -> Vec<u32> {
+    instances
+        .par_iter()
+        .enumerate()
+        .filter(|(_, i)| frustum.intersects_sphere(&bounds.transformed_by_instance(i)))
+        .map(|(idx, _)| idx as u32)
+        .collect()
+}
+
+impl ForwardRenderer {
+    // Uploads instance data for nodes that changed and gathers the visible instances of the rest.
+    fn prepare_model_nodes(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        models: &Vec<Model>,
+        model_nodes: &mut Vec<ModelNode>,
+    ) -> Vec<NodeDraw> {
+        let mut results = Vec::<NodeDraw>::new();
+
+        for (node_idx, m) in model_nodes.iter_mut().enumerate() {
+            let model = &models[m.model_idx];
+
+            if m.instance_buffers.len() != model.meshes.len() {
+                m.instance_buffers = model
+                    .meshes
+                    .iter()
+                    .map(|_| {
+                        MeshInstanceBuffers::new(
+                            device,
+                            std::mem::size_of::<InstanceRaw>(),
+                            m.instances.len(),
+                        )
+                    })
+                    .collect();
+                m.dirty = true;
+            }
+
+            let total = m.instances.len();
+            // Growing a buffer loses its contents, so everything goes up again then.
+            let full = m.dirty || m.instance_buffers.iter().any(|b| b.all.capacity < total);
+            let upload = if full {
+                Some(0..total)
+            } else {
+                m.take_dirty_range()
+                    .map(|r| r.start.min(total)..r.end.min(total))
+            };
+
+            if let Some(range) = upload {
+                let model_instance_data: Vec<InstanceRaw> =
+                    m.instances[range.clone()].par_iter().map(|i| i.to_raw()).collect();
+
+                for (mesh, buffers) in model.meshes.iter().zip(m.instance_buffers.iter_mut()) {
+                    let mesh_m_mat = glam::Mat4::from_scale_rotation_translation(
                         mesh.scale,
                         mesh.rotation,
                         mesh.translation,
                     );
+                    let mesh_n_mat = glam::Mat3::from_quat(mesh.rotation);
+
+                    let mesh_instance_data: Vec<InstanceRaw> = model_instance_data
+                        .par_iter()
+                        .map(|instance| instance.composed(&mesh_m_mat, &mesh_n_mat))
+                        .collect();
+
+                    if full {
+                        buffers.all.write(device, queue, &mesh_instance_data);
+                    } else {
+                        buffers.all.write_range(queue, range.start, &mesh_instance_data);
+                    }
+                }
+                m.dirty = false;
+                m.dirty_range = None;
+            }
+            // Removals shrink the node without necessarily leaving anything to upload.
+            for buffers in m.instance_buffers.iter_mut() {
+                buffers.all.len = total;
+            }
+
+            let has_hidden = m.has_hidden();
+            let mut visible = if self.culling_enabled {
+                visible_instances(&m.instances, &model.bounding_sphere(), &self.frustum)
+            } else if has_hidden {
+                (0..total as u32).collect()
+            } else {
+                Vec::new()
+            };
+            if has_hidden {
+                visible.retain(|idx| m.is_visible_at(*idx as usize));
+            }
+            let count = if self.culling_enabled || has_hidden { visible.len() } else { total };
 
-                    for instance_raw in &model_instances {
-                        let instance_model_mat =
-                            glam::Mat4::from_cols_array_2d(&instance_raw.model);
+            self.stats.visible_instances += count;
+            self.stats.culled_instances += total - count;
 
-                        let temp = SkinnedInstanceRaw {
-                            model: (instance_model_mat * mesh_mat).to_cols_array_2d(),
-                        };
+            if count == 0 {
+                continue;
+            }
 
-                        mesh_instances.push(temp);
+            let from_visible = count < total;
+            if from_visible {
+                let runs = visible_runs(&visible);
+                if runs.len() > MAX_COPIED_RUNS {
+                    let visible_data: Vec<InstanceRaw> =
+                        visible.par_iter().map(|idx| m.instances[*idx as usize].to_raw()).collect();
+                    for (mesh, buffers) in model.meshes.iter().zip(m.instance_buffers.iter_mut()) {
+                        let mesh_m_mat = glam::Mat4::from_scale_rotation_translation(
+                            mesh.scale,
+                            mesh.rotation,
+                            mesh.translation,
+                        );
+                        let mesh_n_mat = glam::Mat3::from_quat(mesh.rotation);
+                        let mesh_data: Vec<InstanceRaw> = visible_data
+                            .par_iter()
+                            .map(|instance| instance.composed(&mesh_m_mat, &mesh_n_mat))
+                            .collect();
+                        buffers.upload_visible(device, queue, encoder, &mut self.upload, &mesh_data);
+                    }
+                } else {
+                    for buffers in m.instance_buffers.iter_mut() {
+                        buffers.copy_visible_runs(device, encoder, &runs, count);
                     }
+                }
+            }
 
-                    let instance_buffer =
-                        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                            label: Some("Instance Buffer"),
-                            contents: bytemuck::cast_slice(&mesh_instances),
-                            usage: wgpu::BufferUsages::VERTEX,
-                        });
+            results.push(NodeDraw {
+                node_idx,
+                count: count as u32,
+                from_visible,
+            });
+        }
 
-                    render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+        results
+    }
 
-                    render_pass.draw_skinned_mesh_instanced(
-                        mesh,
-                        &model.materials[mesh.material],
-                        0..count,
-                        &self.camera_bind_group,
-                        &self.light_bind_group,
-                        &c.skinned_model_node.bind_group,
+    // Characters move every frame, so their instance data is always re-uploaded into the persistent buffers.
+    fn prepare_characters(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        skinned_models: &Vec<SkinnedModel>,
+        characters_contexts: &mut Vec<CharactersContext>,
+    ) -> Vec<NodeDraw> {
+        let mut results = Vec::<NodeDraw>::new();
+
+        for (node_idx, c) in characters_contexts.iter_mut().enumerate() {
+            let node = &mut c.skinned_model_node;
+            let model = &skinned_models[node.skinned_model_idx];
+
+            if !node.morph_targets_bound {
+                node.bind_morph_targets(device, &self.bone_matrices_bind_group_layout, model);
+            }
+
+            if node.instance_buffers.len() != model.meshes.len() {
+                node.instance_buffers = model
+                    .meshes
+                    .iter()
+                    .map(|_| {
+                        MeshInstanceBuffers::new(
+                            device,
+                            std::mem::size_of::<SkinnedInstanceRaw>(),
+                            node.instances.len(),
+                        )
+                    })
+                    .collect();
+                node.dirty = true;
+            }
+
+            if node.dirty {
+                let model_instances: Vec<SkinnedInstanceRaw> = node
+                    .instances
+                    .par_iter()
+                    .enumerate()
+                    .map(|(idx, i)| i.to_skinned_raw(idx as u32))
+                    .collect();
+
+                for (mesh, buffers) in model.meshes.iter().zip(node.instance_buffers.iter_mut()) {
+                    let mesh_mat = glam::Mat4::from_scale_rotation_translation(
+                        mesh.scale,
+                        mesh.rotation,
+                        mesh.translation,
                     );
+
+                    let mesh_instances: Vec<SkinnedInstanceRaw> = model_instances
+                        .par_iter()
+                        .map(|instance_raw| instance_raw.composed(&mesh_mat, mesh.morph_params()))
+                        .collect();
+
+                    buffers.all.write(device, queue, &mesh_instances);
                 }
+                node.dirty = false;
+            }
+
+            let total = node.instances.len();
+            let visible = if self.culling_enabled {
+                visible_instances(&node.instances, &model.bounding_sphere(), &self.frustum)
+            } else {
+                Vec::new()
+            };
+            let count = if self.culling_enabled { visible.len() } else { total };
+
+            self.stats.visible_characters += count;
+            self.stats.culled_characters += total - count;
+
+            if count == 0 {
+                continue;
             }
+
+            let from_visible = count < total;
+            if from_visible {
+                let runs = visible_runs(&visible);
+                if runs.len() > MAX_COPIED_RUNS {
+                    let visible_data: Vec<SkinnedInstanceRaw> = visible
+                        .par_iter()
+                        .map(|idx| node.instances[*idx as usize].to_skinned_raw(*idx))
+                        .collect();
+                    for (mesh, buffers) in model.meshes.iter().zip(node.instance_buffers.iter_mut()) {
+                        let mesh_mat = glam::Mat4::from_scale_rotation_translation(
+                            mesh.scale,
+                            mesh.rotation,
+                            mesh.translation,
+                        );
+                        let mesh_data: Vec<SkinnedInstanceRaw> = visible_data
+                            .par_iter()
+                            .map(|instance_raw| instance_raw.composed(&mesh_mat, mesh.morph_params()))
+                            .collect();
+                        buffers.upload_visible(device, queue, encoder, &mut self.upload, &mesh_data);
+                    }
+                } else {
+                    for buffers in node.instance_buffers.iter_mut() {
+                        buffers.copy_visible_runs(device, encoder, &runs, count);
+                    }
+                }
+            }
+
+            results.push(NodeDraw {
+                node_idx,
+                count: count as u32,
+                from_visible,
+            });
         }
 
-        queue.submit([encoder.finish()]);
+        results
     }
-}
 
-impl ForwardRenderer {
     pub fn new(
         device: &wgpu::Device,
-        light_buffer: &wgpu::Buffer,
-        camera_buffer: &wgpu::Buffer,
         texture_bind_group_layout: &wgpu::BindGroupLayout,
         camera_bind_group_layout: &wgpu::BindGroupLayout,
         light_bind_group_layout: &wgpu::BindGroupLayout,
         bone_matrices_bind_group_layout: &wgpu::BindGroupLayout,
-        config: &wgpu::SurfaceConfiguration,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
     ) -> Self {
-        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &light_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: light_buffer.as_entire_binding(),
-            }],
-            label: None,
-        });
-
-        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &camera_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            }],
-            label: Some("camera_bind_group"),
-        });
+        let options = RenderPipelineOptions {
+            sample_count,
+            ..Default::default()
+        };
+        let alpha_to_coverage_options = RenderPipelineOptions {
+            sample_count,
+            alpha_to_coverage_enabled: true,
+            fragment_entry_point: "fs_alpha_to_coverage",
+            ..Default::default()
+        };
 
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -236,34 +528,96 @@ impl ForwardRenderer {
         let render_pipeline = {
             let shader = wgpu::ShaderModuleDescriptor {
                 label: Some("Normal Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
+                source: wgpu::ShaderSource::Wgsl(
+                    concat!(
+                        include_str!("clustered_lighting.wgsl"),
+                        include_str!("environment.wgsl"),
+                        include_str!("shader.wgsl"),
+                    )
+                    .into(),
+                ),
             };
 
-            create_render_pipeline(
+            create_render_pipeline_with_options(
                 &device,
                 &render_pipeline_layout,
-                config.format,
+                color_format,
                 Some(Texture::DEPTH_FORMAT),
                 &[Some(ModelVertex::desc()), Some(InstanceRaw::desc())],
                 shader,
+                &options,
             )
         };
 
+        let alpha_to_coverage_pipeline = (sample_count > 1).then(|| {
+            let shader = wgpu::ShaderModuleDescriptor {
+                label: Some("Normal Shader (alpha to coverage)"),
+                source: wgpu::ShaderSource::Wgsl(
+                    concat!(
+                        include_str!("clustered_lighting.wgsl"),
+                        include_str!("environment.wgsl"),
+                        include_str!("shader.wgsl"),
+                    )
+                    .into(),
+                ),
+            };
+            create_render_pipeline_with_options(
+                &device,
+                &render_pipeline_layout,
+                color_format,
+                Some(Texture::DEPTH_FORMAT),
+                &[Some(ModelVertex::desc()), Some(InstanceRaw::desc())],
+                shader,
+                &alpha_to_coverage_options,
+            )
+        });
+
         let skinned_render_pipeline = {
             let shader = wgpu::ShaderModuleDescriptor {
                 label: Some("Skinned Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("skinned.wgsl").into()),
+                source: wgpu::ShaderSource::Wgsl(
+                    concat!(
+                        include_str!("clustered_lighting.wgsl"),
+                        include_str!("environment.wgsl"),
+                        include_str!("skinned.wgsl"),
+                    )
+                    .into(),
+                ),
             };
-            create_render_pipeline(
+            create_render_pipeline_with_options(
                 &device,
                 &skinned_render_pipeline_layout,
-                config.format,
+                color_format,
                 Some(Texture::DEPTH_FORMAT),
                 &[Some(SkinnedModelVertex::desc()), Some(SkinnedInstanceRaw::desc())],
                 shader,
+                &options,
             )
         };
 
+        let skinned_alpha_to_coverage_pipeline = (sample_count > 1).then(|| {
+            let shader = wgpu::ShaderModuleDescriptor {
+                label: Some("Skinned Shader (alpha to coverage)"),
+                source: wgpu::ShaderSource::Wgsl(
+                    concat!(
+                        include_str!("clustered_lighting.wgsl"),
+                        include_str!("environment.wgsl"),
+                        include_str!("skinned.wgsl"),
+                    )
+                    .into(),
+                ),
+            };
+            create_render_pipeline_with_options(
+                &device,
+                &skinned_render_pipeline_layout,
+                color_format,
+                Some(Texture::DEPTH_FORMAT),
+                &[Some(SkinnedModelVertex::desc()), Some(SkinnedInstanceRaw::desc())],
+                shader,
+                &alpha_to_coverage_options,
+            )
+        });
+
         let light_render_pipeline = {
             let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Light Pipeline Layout"),
@@ -278,25 +632,38 @@ impl ForwardRenderer {
                 source: wgpu::ShaderSource::Wgsl(include_str!("light.wgsl").into()),
             };
 
-            create_render_pipeline(
+            create_render_pipeline_with_options(
                 &device,
                 &layout,
-                config.format,
+                color_format,
                 Some(Texture::DEPTH_FORMAT),
                 &[Some(ModelVertex::desc())],
                 shader,
+                &options,
             )
         };
 
         Self {
             render_pipeline_layout,
             skinned_render_pipeline_layout,
-            light_bind_group,
-            camera_bind_group,
             render_pipeline,
             skinned_render_pipeline,
+            alpha_to_coverage_pipeline,
+            skinned_alpha_to_coverage_pipeline,
             light_render_pipeline,
             bone_matrices_bind_group_layout: bone_matrices_bind_group_layout.clone(),
+            frustum: Frustum::default(),
+            culling_enabled: true,
+            stats: CullingStats::default(),
+            color_target: HDR.to_owned(),
+            depth_target: DEPTH.to_owned(),
+            clear_color: wgpu::Color {
+                r: 0.1,
+                g: 0.2,
+                b: 0.3,
+                a: 1.0,
+            },
+            upload: UploadBuffer::new(device, "Forward Upload Buffer", 0),
         }
     }
 }
@@ -0,0 +1,191 @@
+use crate::light::LightContext;
+use crate::light_clusters::*;
+use crate::passes::render_graph::*;
+use std::any::Any;
+
+pub const LIGHT_CULLING: &str = "light_culling";
+
+const WORKGROUP_SIZE: u32 = 64;
+
+// Uploads the point lights and fills the LightContext's cluster buffers for this graph's camera, on the GPU or,
+// when the settings say so, with the CPU reference. Graphs share those buffers, so they're filled in encoder order
+// (see UploadBuffer).
+pub struct LightCullingPass {
+    pub pipeline: wgpu::ComputePipeline,
+    pub bind_group: wgpu::BindGroup,
+    params_buffer: wgpu::Buffer,
+    // Counts then indices, for the CPU path. Created on first use.
+    cpu_buffers: Option<(wgpu::Buffer, wgpu::Buffer)>,
+}
+
+impl RenderNode for LightCullingPass {
+    fn name(&self) -> &str {
+        LIGHT_CULLING
+    }
+
+    fn reads(&self) -> Vec<String> {
+        vec![CAMERA_BUFFER.to_owned()]
+    }
+
+    fn writes(&self) -> Vec<String> {
+        vec![LIGHT_BUFFER.to_owned()]
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn execute(
+        &mut self,
+        frame: &mut FrameContext,
+        _resources: &GraphResources,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        let lights = frame.lights;
+        let point_lights = &lights.point_lights[..lights.point_lights.len().min(MAX_POINT_LIGHTS)];
+        let raw: Vec<PointLightRaw> = point_lights.iter().map(|l| l.to_raw()).collect();
+        if !raw.is_empty() {
+            frame
+                .queue
+                .write_buffer(&lights.point_light_buffer, 0, bytemuck::cast_slice(&raw));
+        }
+
+        let projection = frame.view_projection * frame.view.inverse();
+        let params = ClusterParams::new(frame.view, projection, frame.size, raw.len());
+        frame
+            .queue
+            .write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[params]));
+        encoder.copy_buffer_to_buffer(
+            &self.params_buffer,
+            0,
+            &lights.cluster_params_buffer,
+            0,
+            std::mem::size_of::<ClusterParams>() as wgpu::BufferAddress,
+        );
+
+        if raw.is_empty() {
+            encoder.clear_buffer(&lights.cluster_light_counts_buffer, 0, None);
+            return;
+        }
+
+        if frame.settings.gpu_light_culling {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Light Culling Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &self.bind_group, &[]);
+            compute_pass.dispatch_workgroups((NUM_CLUSTERS as u32).div_ceil(WORKGROUP_SIZE), 1, 1);
+        } else {
+            let assignment = assign_lights(&params, point_lights);
+            let (counts, indices) = self.cpu_buffers.get_or_insert_with(|| {
+                (
+                    create_staging_buffer(frame.device, "Cluster Light Counts Staging", &lights.cluster_light_counts_buffer),
+                    create_staging_buffer(frame.device, "Cluster Light Indices Staging", &lights.cluster_light_indices_buffer),
+                )
+            });
+            frame
+                .queue
+                .write_buffer(counts, 0, bytemuck::cast_slice(&assignment.counts));
+            frame
+                .queue
+                .write_buffer(indices, 0, bytemuck::cast_slice(&assignment.indices));
+            encoder.copy_buffer_to_buffer(counts, 0, &lights.cluster_light_counts_buffer, 0, counts.size());
+            encoder.copy_buffer_to_buffer(indices, 0, &lights.cluster_light_indices_buffer, 0, indices.size());
+        }
+    }
+}
+
+fn create_staging_buffer(device: &wgpu::Device, label: &str, like: &wgpu::Buffer) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size: like.size(),
+        usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+impl LightCullingPass {
+    pub fn new(device: &wgpu::Device, light_ctx: &LightContext) -> Self {
+        let buffer_entry = |binding: u32, ty: wgpu::BufferBindingType| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                buffer_entry(0, wgpu::BufferBindingType::Uniform),
+                buffer_entry(1, wgpu::BufferBindingType::Storage { read_only: true }),
+                buffer_entry(2, wgpu::BufferBindingType::Storage { read_only: false }),
+                buffer_entry(3, wgpu::BufferBindingType::Storage { read_only: false }),
+            ],
+            label: Some("Light Culling Bind Group Layout"),
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: light_ctx.cluster_params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: light_ctx.point_light_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: light_ctx.cluster_light_counts_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: light_ctx.cluster_light_indices_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("Light Culling Bind Group"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Light Culling Pipeline Layout"),
+            bind_group_layouts: &[Some(&bind_group_layout)],
+            immediate_size: 0,
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Light Culling Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("light_culling.wgsl").into()),
+        });
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Light Culling Pipeline"),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: Some("cs_main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light Culling Params"),
+            size: std::mem::size_of::<ClusterParams>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            pipeline,
+            bind_group,
+            params_buffer,
+            cpu_buffers: None,
+        }
+    }
+}
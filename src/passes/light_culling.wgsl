@@ -0,0 +1,91 @@
+// Assigns point lights to froxels, one invocation per cluster. Mirrors light_clusters::assign_lights.
+
+struct ClusterParams {
+    view: mat4x4<f32>,
+    inverse_projection: mat4x4<f32>,
+    // Grid size, then the number of point lights.
+    grid: vec4<u32>,
+    screen_size: vec2<f32>,
+    near: f32,
+    far: f32,
+}
+
+struct PointLight {
+    position: vec3<f32>,
+    range: f32,
+    color: vec3<f32>,
+    intensity: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: ClusterParams;
+@group(0) @binding(1)
+var<storage, read> point_lights: array<PointLight>;
+@group(0) @binding(2)
+var<storage, read_write> cluster_light_counts: array<u32>;
+@group(0) @binding(3)
+var<storage, read_write> cluster_light_indices: array<u32>;
+
+const MAX_LIGHTS_PER_CLUSTER: u32 = 64u;
+
+fn unproject(ndc: vec3<f32>) -> vec3<f32> {
+    let p = params.inverse_projection * vec4<f32>(ndc, 1.0);
+    return p.xyz / p.w;
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let grid = params.grid.xyz;
+    let cluster = id.x;
+    if (cluster >= grid.x * grid.y * grid.z) {
+        return;
+    }
+    let tile_x = cluster % grid.x;
+    let tile_y = (cluster / grid.x) % grid.y;
+    let slice = cluster / (grid.x * grid.y);
+
+    let ndc_x = vec2<f32>(
+        -1.0 + 2.0 * f32(tile_x) / f32(grid.x),
+        -1.0 + 2.0 * f32(tile_x + 1u) / f32(grid.x),
+    );
+    // Tiles count down from the top of the screen.
+    let ndc_y = vec2<f32>(
+        1.0 - 2.0 * f32(tile_y) / f32(grid.y),
+        1.0 - 2.0 * f32(tile_y + 1u) / f32(grid.y),
+    );
+    let ratio = params.far / params.near;
+    let depths = vec2<f32>(
+        params.near * pow(ratio, f32(slice) / f32(grid.z)),
+        params.near * pow(ratio, f32(slice + 1u) / f32(grid.z)),
+    );
+
+    // View space bounds of the froxel, from where the lines through the tile's corners cross the slice's depths.
+    var min_bound = vec3<f32>(3.4e38);
+    var max_bound = vec3<f32>(-3.4e38);
+    for (var corner = 0u; corner < 4u; corner++) {
+        let ndc = vec2<f32>(ndc_x[corner & 1u], ndc_y[corner >> 1u]);
+        let a = unproject(vec3<f32>(ndc, 0.0));
+        let b = unproject(vec3<f32>(ndc, 1.0));
+        for (var d = 0u; d < 2u; d++) {
+            let t = (-depths[d] - a.z) / (b.z - a.z);
+            let p = a + (b - a) * t;
+            min_bound = min(min_bound, p);
+            max_bound = max(max_bound, p);
+        }
+    }
+
+    var count = 0u;
+    for (var i = 0u; i < params.grid.w; i++) {
+        let light = point_lights[i];
+        let center = (params.view * vec4<f32>(light.position, 1.0)).xyz;
+        let offset = clamp(center, min_bound, max_bound) - center;
+        if (dot(offset, offset) <= light.range * light.range) {
+            cluster_light_indices[cluster * MAX_LIGHTS_PER_CLUSTER + count] = i;
+            count++;
+            if (count == MAX_LIGHTS_PER_CLUSTER) {
+                break;
+            }
+        }
+    }
+    cluster_light_counts[cluster] = count;
+}
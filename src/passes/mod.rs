@@ -1,9 +1,16 @@
-use crate::model_node::ModelNode;
-use crate::model::*;
-use crate::scene::CharactersContext;
-use crate::skinned_model::*;
+pub mod bloom;
+pub mod crowd;
+pub mod debug_draw;
+pub mod environment;
+pub mod fluid;
 pub mod forward_renderer;
-
-pub trait Pass {
-    fn draw(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, models: &Vec<Model>, skinned_models: &Vec<SkinnedModel>, nodes: &Vec<ModelNode>, skinned_model_nodes: &Vec<CharactersContext>, depth_texture_view: &wgpu::TextureView, view: &wgpu::TextureView );
-}
+pub mod fxaa;
+pub mod light_culling;
+pub mod outline;
+pub mod particles;
+pub mod render_graph;
+pub mod resolve;
+pub mod sky;
+pub mod terrain;
+pub mod tonemap;
+pub mod transparent;
@@ -0,0 +1,371 @@
+// Outlines the highlighted model instances and characters, after the opaque passes. Their silhouettes are drawn
+// into a mask, without depth testing so a selection stays visible behind walls, then a fullscreen pass draws
+// the outline color over the scene wherever a pixel outside the mask is within the outline's width of it.
+
+use crate::graphics::*;
+use crate::instance::*;
+use crate::model::*;
+use crate::passes::render_graph::*;
+use crate::skinned_model::*;
+use std::any::Any;
+
+pub const OUTLINE: &str = "outline";
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct OutlineUniform {
+    color: [f32; 4],
+    width: f32,
+    _padding: [f32; 3],
+}
+
+// A run of instances in the pass' buffer, drawn with one mesh.
+struct MaskDraw<'a, M> {
+    mesh: &'a M,
+    start: usize,
+    count: usize,
+}
+
+pub struct OutlinePass {
+    pub mask_pipeline: wgpu::RenderPipeline,
+    pub skinned_mask_pipeline: wgpu::RenderPipeline,
+    pub outline_pipeline: wgpu::RenderPipeline,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub params_buffer: wgpu::Buffer,
+    // The highlighted instances only, composed with each mesh's transform.
+    pub instances: InstanceBuffer,
+    pub skinned_instances: InstanceBuffer,
+    pub color_target: String,
+    pub mask: String,
+}
+
+impl RenderNode for OutlinePass {
+    fn name(&self) -> &str {
+        OUTLINE
+    }
+
+    fn reads(&self) -> Vec<String> {
+        vec![CAMERA_BUFFER.to_owned(), self.color_target.clone()]
+    }
+
+    fn writes(&self) -> Vec<String> {
+        vec![self.color_target.clone(), self.mask.clone()]
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn execute(
+        &mut self,
+        frame: &mut FrameContext,
+        resources: &GraphResources,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        let models = frame.models;
+        let skinned_models = frame.skinned_models;
+        let model_nodes = &frame.scene.model_nodes;
+        let characters_contexts = &frame.scene.characters_contexts;
+
+        let mut instance_data = Vec::<InstanceRaw>::new();
+        let mut draws = Vec::<MaskDraw<TexturedMesh>>::new();
+        for node in model_nodes.iter() {
+            let highlighted: Vec<InstanceRaw> = node
+                .instances
+                .iter()
+                .enumerate()
+                .filter(|(idx, _)| node.is_highlighted_at(*idx) && node.is_visible_at(*idx))
+                .map(|(_, i)| i.to_raw())
+                .collect();
+            if highlighted.is_empty() {
+                continue;
+            }
+            for mesh in models[node.model_idx].meshes.iter() {
+                let mesh_m_mat =
+                    glam::Mat4::from_scale_rotation_translation(mesh.scale, mesh.rotation, mesh.translation);
+                let mesh_n_mat = glam::Mat3::from_quat(mesh.rotation);
+                draws.push(MaskDraw {
+                    mesh,
+                    start: instance_data.len(),
+                    count: highlighted.len(),
+                });
+                instance_data.extend(highlighted.iter().map(|i| i.composed(&mesh_m_mat, &mesh_n_mat)));
+            }
+        }
+
+        let mut skinned_instance_data = Vec::<SkinnedInstanceRaw>::new();
+        let mut skinned_draws = Vec::<(&wgpu::BindGroup, MaskDraw<SkinnedTexturedMesh>)>::new();
+        for c in characters_contexts.iter() {
+            let node = &c.skinned_model_node;
+            // update_characters gives each character the instance (and bone slot) at its own index.
+            let highlighted: Vec<SkinnedInstanceRaw> = c
+                .characters
+                .iter()
+                .zip(node.instances.iter())
+                .enumerate()
+                .filter(|(_, (character, _))| character.highlighted)
+                .map(|(idx, (_, i))| i.to_skinned_raw(idx as u32))
+                .collect();
+            if highlighted.is_empty() {
+                continue;
+            }
+            for mesh in skinned_models[node.skinned_model_idx].meshes.iter() {
+                let mesh_mat =
+                    glam::Mat4::from_scale_rotation_translation(mesh.scale, mesh.rotation, mesh.translation);
+                skinned_draws.push((
+                    &node.bind_group,
+                    MaskDraw {
+                        mesh,
+                        start: skinned_instance_data.len(),
+                        count: highlighted.len(),
+                    },
+                ));
+                let morph = mesh.morph_params();
+                skinned_instance_data.extend(highlighted.iter().map(|i| i.composed(&mesh_mat, morph)));
+            }
+        }
+
+        if draws.is_empty() && skinned_draws.is_empty() {
+            return;
+        }
+
+        self.instances.write(frame.device, frame.queue, &instance_data);
+        self.skinned_instances
+            .write(frame.device, frame.queue, &skinned_instance_data);
+
+        let settings = frame.settings;
+        frame.queue.write_buffer(
+            &self.params_buffer,
+            0,
+            bytemuck::cast_slice(&[OutlineUniform {
+                color: settings.outline_color.into(),
+                width: settings.outline_width,
+                _padding: [0.0; 3],
+            }]),
+        );
+
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Outline Mask Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: resources.view(&self.mask),
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+                multiview_mask: None,
+            });
+
+            render_pass.set_pipeline(&self.mask_pipeline);
+            render_pass.set_bind_group(0, resources.bind_group(CAMERA_BUFFER), &[]);
+            for d in &draws {
+                render_pass.set_vertex_buffer(0, d.mesh.vertex_buffer.slice(..));
+                render_pass.set_vertex_buffer(1, self.instances.slice_range(d.start, d.count));
+                render_pass.set_index_buffer(d.mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.draw_indexed(0..d.mesh.num_elements, 0, 0..d.count as u32);
+            }
+
+            render_pass.set_pipeline(&self.skinned_mask_pipeline);
+            render_pass.set_bind_group(0, resources.bind_group(CAMERA_BUFFER), &[]);
+            for (bones, d) in &skinned_draws {
+                render_pass.set_bind_group(1, *bones, &[]);
+                render_pass.set_vertex_buffer(0, d.mesh.vertex_buffer.slice(..));
+                render_pass.set_vertex_buffer(1, self.skinned_instances.slice_range(d.start, d.count));
+                render_pass.set_index_buffer(d.mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.draw_indexed(0..d.mesh.num_elements, 0, 0..d.count as u32);
+            }
+        }
+
+        let bind_group = frame.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(resources.view(&self.mask)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.params_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("Outline Bind Group"),
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Outline Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: resources.view(&self.color_target),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+            multiview_mask: None,
+        });
+        render_pass.set_pipeline(&self.outline_pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+impl OutlinePass {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        bone_matrices_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let mask_options = RenderPipelineOptions {
+            cull_mode: None,
+            ..Default::default()
+        };
+
+        let mask_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Outline Mask Pipeline Layout"),
+            bind_group_layouts: &[Some(camera_bind_group_layout)],
+            immediate_size: 0,
+        });
+        let mask_pipeline = {
+            let shader = wgpu::ShaderModuleDescriptor {
+                label: Some("Outline Mask Shader"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("outline_mask.wgsl").into()),
+            };
+            create_render_pipeline_with_options(
+                device,
+                &mask_layout,
+                Self::MASK_FORMAT,
+                None,
+                &[Some(ModelVertex::desc()), Some(InstanceRaw::desc())],
+                shader,
+                &mask_options,
+            )
+        };
+
+        let skinned_mask_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skinned Outline Mask Pipeline Layout"),
+            bind_group_layouts: &[
+                Some(camera_bind_group_layout),
+                Some(bone_matrices_bind_group_layout),
+            ],
+            immediate_size: 0,
+        });
+        let skinned_mask_pipeline = {
+            let shader = wgpu::ShaderModuleDescriptor {
+                label: Some("Skinned Outline Mask Shader"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("outline_mask_skinned.wgsl").into()),
+            };
+            create_render_pipeline_with_options(
+                device,
+                &skinned_mask_layout,
+                Self::MASK_FORMAT,
+                None,
+                &[Some(SkinnedModelVertex::desc()), Some(SkinnedInstanceRaw::desc())],
+                shader,
+                &mask_options,
+            )
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("Outline Bind Group Layout"),
+        });
+
+        let outline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Outline Pipeline Layout"),
+            bind_group_layouts: &[Some(&bind_group_layout)],
+            immediate_size: 0,
+        });
+        let outline_pipeline = {
+            let shader = wgpu::ShaderModuleDescriptor {
+                label: Some("Outline Shader"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("outline.wgsl").into()),
+            };
+            create_render_pipeline_with_options(
+                device,
+                &outline_layout,
+                color_format,
+                None,
+                &[],
+                shader,
+                &RenderPipelineOptions {
+                    blend: wgpu::BlendState::ALPHA_BLENDING,
+                    cull_mode: None,
+                    sample_count,
+                    ..Default::default()
+                },
+            )
+        };
+
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Outline Params"),
+            size: std::mem::size_of::<OutlineUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            mask_pipeline,
+            skinned_mask_pipeline,
+            outline_pipeline,
+            bind_group_layout,
+            params_buffer,
+            instances: InstanceBuffer::new(
+                device,
+                "Outline Instance Buffer",
+                std::mem::size_of::<InstanceRaw>(),
+                16,
+            ),
+            skinned_instances: InstanceBuffer::new(
+                device,
+                "Outline Skinned Instance Buffer",
+                std::mem::size_of::<SkinnedInstanceRaw>(),
+                16,
+            ),
+            color_target: HDR.to_owned(),
+            mask: OUTLINE_MASK.to_owned(),
+        }
+    }
+
+    pub const MASK_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+    pub fn mask_desc() -> TransientTextureDesc {
+        TransientTextureDesc::new(Self::MASK_FORMAT)
+    }
+}
@@ -0,0 +1,59 @@
+// Draws the outline color around the pixels marked in the mask, blended over the scene.
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+}
+
+// A single triangle that covers the screen.
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    return out;
+}
+
+struct OutlineParams {
+    color: vec4<f32>,
+    width: f32,
+}
+
+@group(0) @binding(0)
+var t_mask: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> params: OutlineParams;
+
+const MAX_WIDTH: i32 = 8;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let size = vec2<i32>(textureDimensions(t_mask));
+    let pixel = vec2<i32>(in.clip_position.xy);
+    // The inside of a highlighted object is left as it is.
+    if (textureLoad(t_mask, pixel, 0).r > 0.5) {
+        discard;
+    }
+
+    // Distance to the closest marked pixel within the outline's width.
+    let width = clamp(params.width, 1.0, f32(MAX_WIDTH));
+    let reach = i32(ceil(width));
+    var closest = width + 1.0;
+    for (var y = -MAX_WIDTH; y <= MAX_WIDTH; y++) {
+        for (var x = -MAX_WIDTH; x <= MAX_WIDTH; x++) {
+            if (abs(x) > reach || abs(y) > reach) {
+                continue;
+            }
+            let p = clamp(pixel + vec2<i32>(x, y), vec2<i32>(0), size - 1);
+            if (textureLoad(t_mask, p, 0).r > 0.5) {
+                closest = min(closest, length(vec2<f32>(f32(x), f32(y))));
+            }
+        }
+    }
+
+    // Antialiased over the last pixel.
+    let coverage = clamp(width + 0.5 - closest, 0.0, 1.0);
+    if (coverage <= 0.0) {
+        discard;
+    }
+    return vec4<f32>(params.color.rgb, params.color.a * coverage);
+}
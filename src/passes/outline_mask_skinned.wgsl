@@ -0,0 +1,84 @@
+// Marks the pixels covered by highlighted characters.
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct BoneMatrix {
+    data: array<mat4x4<f32>>,
+};
+
+@group(1) @binding(0)
+var<storage, read> bone_matrices: BoneMatrix;
+@group(1) @binding(1)
+var<uniform> num_bones: u32;
+
+// Blend shapes. MAX_MORPH_TARGETS weights per character, and the model's deltas laid out
+// target by target, starting at the mesh's offset.
+struct MorphDelta {
+    position: vec4<f32>,
+    normal: vec4<f32>,
+}
+const MAX_MORPH_TARGETS: u32 = 32u;
+@group(1) @binding(2)
+var<storage, read> morph_weights: array<f32>;
+@group(1) @binding(3)
+var<storage, read> morph_deltas: array<MorphDelta>;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(5) bone_indices: vec4<u32>,
+    @location(6) bone_weights: vec4<f32>,
+}
+
+struct InstanceInput {
+    @location(7) model_matrix_0: vec4<f32>,
+    @location(8) model_matrix_1: vec4<f32>,
+    @location(9) model_matrix_2: vec4<f32>,
+    @location(10) model_matrix_3: vec4<f32>,
+    @location(11) skeleton_index: u32,
+    // Offset into morph_deltas, vertex count and target count of the mesh.
+    @location(12) morph: vec3<u32>,
+}
+
+fn apply_morphs(vertex_index: u32, skeleton_index: u32, morph: vec3<u32>, position: vec3<f32>, normal: vec3<f32>) -> MorphDelta {
+    var result = MorphDelta(vec4<f32>(position, 1.0), vec4<f32>(normal, 0.0));
+    for (var t = 0u; t < min(morph.z, MAX_MORPH_TARGETS); t++) {
+        let weight = morph_weights[skeleton_index * MAX_MORPH_TARGETS + t];
+        if weight == 0.0 {
+            continue;
+        }
+        let delta = morph_deltas[morph.x + t * morph.y + vertex_index];
+        result.position += vec4<f32>(delta.position.xyz * weight, 0.0);
+        result.normal += vec4<f32>(delta.normal.xyz * weight, 0.0);
+    }
+    return result;
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32, model: VertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+
+    let offset = num_bones * instance.skeleton_index;
+    let bone_transform = (bone_matrices.data[offset + model.bone_indices.x] * model.bone_weights.x)
+        + (bone_matrices.data[offset + model.bone_indices.y] * model.bone_weights.y)
+        + (bone_matrices.data[offset + model.bone_indices.z] * model.bone_weights.z)
+        + (bone_matrices.data[offset + model.bone_indices.w] * model.bone_weights.w);
+
+    let morphed = apply_morphs(vertex_index, instance.skeleton_index, instance.morph, model.position, vec3<f32>(0.0));
+
+    return camera.view_proj * model_matrix * bone_transform * morphed.position;
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4<f32>(1.0);
+}
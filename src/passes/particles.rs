@@ -0,0 +1,300 @@
+// Draws the Scene's particle emitters after the transparent pass. Emitters are sorted back to front by their position,
+// and alpha blended ones have their particles sorted too. Additive ones don't need it.
+// Every emitter's particles go into one per-frame instance buffer and are drawn with one call per emitter.
+
+use crate::graphics::*;
+use crate::instance::InstanceBuffer;
+use crate::model::Vertex;
+use crate::particle_system::*;
+use crate::passes::render_graph::*;
+use crate::texture::*;
+use std::any::Any;
+
+pub const PARTICLES: &str = "particles";
+
+const DEFAULT_TEXTURE_SIZE: u32 = 64;
+
+pub struct ParticleRenderer {
+    pub additive_pipeline: wgpu::RenderPipeline,
+    pub alpha_pipeline: wgpu::RenderPipeline,
+    pub texture_bind_group_layout: wgpu::BindGroupLayout,
+    // A soft round dot, for emitters without a texture of their own.
+    pub default_bind_group: wgpu::BindGroup,
+    pub instance_buffer: InstanceBuffer,
+    pub color_target: String,
+    pub depth_target: String,
+}
+
+impl RenderNode for ParticleRenderer {
+    fn name(&self) -> &str {
+        PARTICLES
+    }
+
+    fn reads(&self) -> Vec<String> {
+        vec![
+            CAMERA_BUFFER.to_owned(),
+            LIGHT_BUFFER.to_owned(),
+            self.color_target.clone(),
+            self.depth_target.clone(),
+        ]
+    }
+
+    fn writes(&self) -> Vec<String> {
+        vec![self.color_target.clone()]
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn execute(
+        &mut self,
+        frame: &mut FrameContext,
+        resources: &GraphResources,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        let eye = frame.eye;
+        let emitters = &mut frame.scene.particle_emitters;
+
+        let mut order: Vec<usize> = (0..emitters.len())
+            .filter(|i| emitters[*i].visible && !emitters[*i].particles.is_empty())
+            .collect();
+        if order.is_empty() {
+            return;
+        }
+        order.sort_by(|a, b| {
+            let da = eye.distance_squared(emitters[*a].position);
+            let db = eye.distance_squared(emitters[*b].position);
+            db.total_cmp(&da)
+        });
+
+        // (emitter, start, count)
+        let mut batches = Vec::<(usize, u32, u32)>::new();
+        let mut instances = Vec::<ParticleInstanceRaw>::new();
+        for idx in order.iter().copied() {
+            let emitter = &mut emitters[idx];
+            let mut emitter_instances = emitter.instances();
+            if emitter.settings.blend == ParticleBlendMode::Alpha {
+                emitter_instances.sort_by(|a, b| {
+                    let da = eye.distance_squared(glam::Vec3::from(a.position));
+                    let db = eye.distance_squared(glam::Vec3::from(b.position));
+                    db.total_cmp(&da)
+                });
+            }
+            batches.push((idx, instances.len() as u32, emitter_instances.len() as u32));
+            instances.extend(emitter_instances);
+
+            if emitter.bind_group.is_none() {
+                if let Some(texture) = &emitter.texture {
+                    emitter.bind_group = Some(create_particle_bind_group(
+                        frame.device,
+                        &self.texture_bind_group_layout,
+                        texture,
+                        "Particle Bind Group",
+                    ));
+                }
+            }
+        }
+
+        self.instance_buffer
+            .write(frame.device, frame.queue, &instances);
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Particles Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: resources.view(&self.color_target),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: resources.view(&self.depth_target),
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+            multiview_mask: None,
+        });
+
+        render_pass.set_bind_group(0, resources.bind_group(CAMERA_BUFFER), &[]);
+        render_pass.set_bind_group(2, resources.bind_group(LIGHT_BUFFER), &[]);
+
+        for (idx, start, count) in batches {
+            let emitter = &emitters[idx];
+            render_pass.set_pipeline(match emitter.settings.blend {
+                ParticleBlendMode::Additive => &self.additive_pipeline,
+                ParticleBlendMode::Alpha => &self.alpha_pipeline,
+            });
+            render_pass.set_bind_group(
+                1,
+                emitter.bind_group.as_ref().unwrap_or(&self.default_bind_group),
+                &[],
+            );
+            render_pass.set_vertex_buffer(
+                0,
+                self.instance_buffer
+                    .slice_range(start as usize, count as usize),
+            );
+            render_pass.draw(0..6, 0..count);
+        }
+    }
+}
+
+impl ParticleRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        light_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let texture_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("Particle Texture Bind Group Layout"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particle Pipeline Layout"),
+            bind_group_layouts: &[
+                Some(camera_bind_group_layout),
+                Some(&texture_bind_group_layout),
+                Some(light_bind_group_layout),
+            ],
+            immediate_size: 0,
+        });
+
+        let create_pipeline = |blend: wgpu::BlendState, fragment_entry_point: &'static str| {
+            let shader = wgpu::ShaderModuleDescriptor {
+                label: Some("Particle Shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    concat!(
+                        include_str!("clustered_lighting.wgsl"),
+                        include_str!("environment.wgsl"),
+                        include_str!("particles.wgsl"),
+                    )
+                    .into(),
+                ),
+            };
+            create_render_pipeline_with_options(
+                device,
+                &layout,
+                color_format,
+                Some(Texture::DEPTH_FORMAT),
+                &[Some(ParticleInstanceRaw::desc())],
+                shader,
+                &RenderPipelineOptions {
+                    blend,
+                    sample_count,
+                    fragment_entry_point,
+                    ..RenderPipelineOptions::transparent()
+                },
+            )
+        };
+
+        let additive = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::SrcAlpha,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::Zero,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+        };
+
+        let default_texture = Self::create_default_texture(device, queue);
+        let default_bind_group = create_particle_bind_group(
+            device,
+            &texture_bind_group_layout,
+            &default_texture,
+            "Default Particle Bind Group",
+        );
+
+        Self {
+            additive_pipeline: create_pipeline(additive, "fs_additive"),
+            alpha_pipeline: create_pipeline(wgpu::BlendState::ALPHA_BLENDING, "fs_main"),
+            texture_bind_group_layout,
+            default_bind_group,
+            instance_buffer: InstanceBuffer::new(
+                device,
+                "Particle Instance Buffer",
+                std::mem::size_of::<ParticleInstanceRaw>(),
+                1024,
+            ),
+            color_target: HDR.to_owned(),
+            depth_target: DEPTH.to_owned(),
+        }
+    }
+
+    fn create_default_texture(device: &wgpu::Device, queue: &wgpu::Queue) -> Texture {
+        let size = DEFAULT_TEXTURE_SIZE;
+        let img = image::RgbaImage::from_fn(size, size, |x, y| {
+            let half = size as f32 * 0.5;
+            let dx = (x as f32 + 0.5 - half) / half;
+            let dy = (y as f32 + 0.5 - half) / half;
+            let falloff = (1.0 - (dx * dx + dy * dy).sqrt()).clamp(0.0, 1.0);
+            image::Rgba([255, 255, 255, (falloff * falloff * 255.0) as u8])
+        });
+        Texture::from_image(
+            device,
+            queue,
+            &image::DynamicImage::ImageRgba8(img),
+            Some("Default Particle Texture"),
+            false,
+        )
+        .unwrap()
+    }
+}
+
+fn create_particle_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    texture: &Texture,
+    label: &str,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&texture.sampler),
+            },
+        ],
+        label: Some(label),
+    })
+}
@@ -0,0 +1,77 @@
+// Camera-facing particle billboards. The quad's corners come from the vertex index, six per instance.
+// Fogged like the rest of the scene: blended particles towards the fog colour, additive ones towards nothing.
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+@group(1) @binding(0)
+var t_particle: texture_2d<f32>;
+@group(1) @binding(1)
+var s_particle: sampler;
+
+struct InstanceInput {
+    @location(0) position: vec3<f32>,
+    @location(1) size: f32,
+    @location(2) color: vec4<f32>,
+    @location(3) uv_rect: vec4<f32>,
+    @location(4) rotation: f32,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) world_position: vec3<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32, instance: InstanceInput) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-0.5, -0.5),
+        vec2<f32>(0.5, -0.5),
+        vec2<f32>(0.5, 0.5),
+        vec2<f32>(-0.5, -0.5),
+        vec2<f32>(0.5, 0.5),
+        vec2<f32>(-0.5, 0.5),
+    );
+    let corner = corners[vertex_index];
+
+    // Facing the eye rather than the view plane, so particles don't shear at the edges of the screen.
+    let forward = normalize(instance.position - camera.view_pos.xyz);
+    var world_up = vec3<f32>(0.0, 1.0, 0.0);
+    if (abs(forward.y) > 0.999) {
+        world_up = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    let right = normalize(cross(forward, world_up));
+    let up = cross(right, forward);
+
+    let c = cos(instance.rotation);
+    let s = sin(instance.rotation);
+    let rotated = vec2<f32>(corner.x * c - corner.y * s, corner.x * s + corner.y * c) * instance.size;
+    let world_position = instance.position + right * rotated.x + up * rotated.y;
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    let uv = vec2<f32>(corner.x + 0.5, 0.5 - corner.y);
+    out.tex_coords = instance.uv_rect.xy + uv * instance.uv_rect.zw;
+    out.color = instance.color;
+    out.world_position = world_position;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_particle, s_particle, in.tex_coords) * in.color;
+    return vec4<f32>(apply_fog(color.rgb, camera.view_pos.xyz, in.world_position), color.a);
+}
+
+@fragment
+fn fs_additive(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_particle, s_particle, in.tex_coords) * in.color;
+    let fog = fog_factor(camera.view_pos.xyz, in.world_position);
+    return vec4<f32>(color.rgb * (1.0 - fog), color.a);
+}
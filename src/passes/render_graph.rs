@@ -0,0 +1,544 @@
+// A small render graph. Nodes declare the named resources (attachments and buffers) they read and write,
+// the graph orders them from those declarations and owns the transient textures that only live between nodes.
+//
+// Ordering rules, for a resource R:
+// - A node that only reads R runs after every node that writes R.
+// - A node that reads and writes R (e.g. loads an attachment and draws on top) runs after the nodes that only
+//   write R, and after the other read-write nodes of R that were added before it.
+// - Nodes that only write R run in the order they were added.
+// Ties are broken by insertion order, so a graph built in the intended order stays in that order.
+
+use crate::debug_draw::DebugDraw;
+use crate::light::LightContext;
+use crate::model::Model;
+use crate::render_settings::RenderSettings;
+use crate::scene::Scene;
+use crate::skinned_model::SkinnedModel;
+use crate::viewport::PixelRect;
+use anyhow::*;
+use std::any::Any;
+use std::collections::{BTreeSet, HashMap};
+
+// Resources imported by the window every frame.
+pub const SURFACE: &str = "surface";
+pub const DEPTH: &str = "depth";
+pub const CAMERA_BUFFER: &str = "camera";
+pub const LIGHT_BUFFER: &str = "lights";
+// Transients declared by the window's graph.
+pub const HDR: &str = "hdr";
+pub const BLOOM: &str = "bloom";
+// Multisampled scene attachments, only declared when MSAA is on. HDR gets the resolved image.
+pub const HDR_MSAA: &str = "hdr_msaa";
+pub const DEPTH_MSAA: &str = "depth_msaa";
+// Tonemapped image, when a post anti-aliasing pass sits between the tonemapper and the surface.
+pub const LDR: &str = "ldr";
+// A single sampled copy of the scene so far, for passes that draw what is seen through them.
+pub const SCENE_COLOR: &str = "scene_color";
+// Silhouettes of the highlighted instances, for the outline pass.
+pub const OUTLINE_MASK: &str = "outline_mask";
+
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TransientTextureDesc {
+    pub format: wgpu::TextureFormat,
+    pub usage: wgpu::TextureUsages,
+    pub sample_count: u32,
+    pub mip_level_count: u32,
+    // Size relative to the surface.
+    pub scale: f32,
+}
+
+impl TransientTextureDesc {
+    pub fn new(format: wgpu::TextureFormat) -> Self {
+        Self {
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            sample_count: 1,
+            mip_level_count: 1,
+            scale: 1.0,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct GraphTexture {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+}
+
+pub struct GraphResources {
+    textures: HashMap<String, GraphTexture>,
+    buffers: HashMap<String, wgpu::Buffer>,
+    // How passes bind the imported buffers, by the buffer's name.
+    bind_groups: HashMap<String, wgpu::BindGroup>,
+}
+
+impl GraphResources {
+    pub fn new() -> Self {
+        Self {
+            textures: HashMap::new(),
+            buffers: HashMap::new(),
+            bind_groups: HashMap::new(),
+        }
+    }
+
+    // The graph validates reads when it compiles, so a missing resource here is a bug in a node's declarations.
+    pub fn texture(&self, name: &str) -> &GraphTexture {
+        self.textures
+            .get(name)
+            .unwrap_or_else(|| panic!("[RenderGraph] No texture named {}", name))
+    }
+
+    pub fn view(&self, name: &str) -> &wgpu::TextureView {
+        &self.texture(name).view
+    }
+
+    pub fn buffer(&self, name: &str) -> &wgpu::Buffer {
+        self.buffers
+            .get(name)
+            .unwrap_or_else(|| panic!("[RenderGraph] No buffer named {}", name))
+    }
+
+    pub fn bind_group(&self, name: &str) -> &wgpu::BindGroup {
+        self.bind_groups
+            .get(name)
+            .unwrap_or_else(|| panic!("[RenderGraph] No bind group for {}", name))
+    }
+
+    pub fn has_texture(&self, name: &str) -> bool {
+        self.textures.contains_key(name)
+    }
+}
+
+// Everything a node may need from the engine for the current frame.
+pub struct FrameContext<'a> {
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+    pub config: &'a wgpu::SurfaceConfiguration,
+    pub models: &'a Vec<Model>,
+    pub skinned_models: &'a Vec<SkinnedModel>,
+    pub scene: &'a mut Scene,
+    // For the passes that fill the light buffers. Others bind LIGHT_BUFFER from the graph's resources.
+    pub lights: &'a LightContext,
+    pub settings: &'a RenderSettings,
+    pub debug_draw: &'a DebugDraw,
+    pub view: glam::Mat4,
+    pub view_projection: glam::Mat4,
+    pub eye: glam::Vec3,
+    // What transient textures are sized relative to. The surface's size unless drawing a viewport.
+    pub size: (u32, u32),
+    // Where on the surface the final pass draws. None is all of it.
+    pub viewport: Option<PixelRect>,
+}
+
+impl FrameContext<'_> {
+    // The rectangle a pass writing to target should draw into, if it is only part of it.
+    // The surface is cleared once before the viewports are drawn, so passes shouldn't clear it themselves.
+    pub fn output_viewport(&self, target: &str) -> Option<PixelRect> {
+        if target == SURFACE { self.viewport } else { None }
+    }
+}
+
+pub trait RenderNode {
+    fn name(&self) -> &str;
+    fn reads(&self) -> Vec<String> {
+        Vec::new()
+    }
+    fn writes(&self) -> Vec<String> {
+        Vec::new()
+    }
+    fn execute(
+        &mut self,
+        frame: &mut FrameContext,
+        resources: &GraphResources,
+        encoder: &mut wgpu::CommandEncoder,
+    );
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+pub struct RenderGraph {
+    nodes: Vec<Box<dyn RenderNode>>,
+    order: Vec<usize>,
+    compiled: bool,
+    transient_descs: HashMap<String, TransientTextureDesc>,
+    imported: BTreeSet<String>,
+    resources: GraphResources,
+    allocated_size: (u32, u32),
+    needs_allocation: bool,
+}
+
+impl RenderGraph {
+    pub fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            order: Vec::new(),
+            compiled: false,
+            transient_descs: HashMap::new(),
+            imported: BTreeSet::new(),
+            resources: GraphResources::new(),
+            allocated_size: (0, 0),
+            needs_allocation: true,
+        }
+    }
+
+    pub fn add_node(&mut self, node: impl RenderNode + 'static) {
+        self.nodes.push(Box::new(node));
+        self.compiled = false;
+    }
+
+    pub fn remove_node(&mut self, name: &str) -> bool {
+        let len = self.nodes.len();
+        self.nodes.retain(|n| n.name() != name);
+        self.compiled = false;
+        self.nodes.len() != len
+    }
+
+    pub fn node<T: 'static>(&self, name: &str) -> Option<&T> {
+        self.nodes
+            .iter()
+            .find(|n| n.name() == name)
+            .and_then(|n| n.as_any().downcast_ref::<T>())
+    }
+
+    // Nodes may change their declared resources when modified, so the graph is re-ordered before the next frame.
+    pub fn node_mut<T: 'static>(&mut self, name: &str) -> Option<&mut T> {
+        self.compiled = false;
+        self.nodes
+            .iter_mut()
+            .find(|n| n.name() == name)
+            .and_then(|n| n.as_any_mut().downcast_mut::<T>())
+    }
+
+    pub fn node_names(&self) -> Vec<String> {
+        self.order
+            .iter()
+            .map(|i| self.nodes[*i].name().to_owned())
+            .collect()
+    }
+
+    pub fn declare_texture(&mut self, name: &str, desc: TransientTextureDesc) {
+        if self.transient_descs.get(name) != Some(&desc) {
+            self.transient_descs.insert(name.to_owned(), desc);
+            self.needs_allocation = true;
+        }
+        self.compiled = false;
+    }
+
+    pub fn import_texture(&mut self, name: &str, texture: wgpu::Texture, view: wgpu::TextureView) {
+        if self.imported.insert(name.to_owned()) {
+            self.compiled = false;
+        }
+        self.resources
+            .textures
+            .insert(name.to_owned(), GraphTexture { texture, view });
+    }
+
+    pub fn import_buffer(&mut self, name: &str, buffer: wgpu::Buffer) {
+        if self.imported.insert(name.to_owned()) {
+            self.compiled = false;
+        }
+        self.resources.buffers.insert(name.to_owned(), buffer);
+    }
+
+    // Imports a buffer along with the bind group passes read it through. Passes should bind it from the
+    // resources, so that the buffer's declared reads and writes are what orders them.
+    pub fn import_buffer_with_bind_group(&mut self, name: &str, buffer: wgpu::Buffer, bind_group: wgpu::BindGroup) {
+        self.import_buffer(name, buffer);
+        self.resources.bind_groups.insert(name.to_owned(), bind_group);
+    }
+
+    pub fn resources(&self) -> &GraphResources {
+        &self.resources
+    }
+
+    pub fn compile(&mut self) -> anyhow::Result<()> {
+        let count = self.nodes.len();
+        let reads: Vec<Vec<String>> = self.nodes.iter().map(|n| n.reads()).collect();
+        let writes: Vec<Vec<String>> = self.nodes.iter().map(|n| n.writes()).collect();
+
+        for (i, node_reads) in reads.iter().enumerate() {
+            for r in node_reads {
+                let provided = self.imported.contains(r)
+                    || self.transient_descs.contains_key(r)
+                    || writes.iter().any(|w| w.contains(r));
+                if !provided {
+                    return Err(anyhow!(
+                        "[RenderGraph] Node {} reads {}, which is neither imported, declared nor written by any node",
+                        self.nodes[i].name(),
+                        r
+                    ));
+                }
+            }
+        }
+
+        for (i, node_writes) in writes.iter().enumerate() {
+            for w in node_writes {
+                if !self.imported.contains(w) && !self.transient_descs.contains_key(w) {
+                    return Err(anyhow!(
+                        "[RenderGraph] Node {} writes {}, which is neither imported nor declared",
+                        self.nodes[i].name(),
+                        w
+                    ));
+                }
+            }
+        }
+
+        // dependencies[b] holds the nodes that must run before b
+        let mut dependencies = vec![BTreeSet::<usize>::new(); count];
+        for b in 0..count {
+            for a in 0..count {
+                if a == b {
+                    continue;
+                }
+                for r in &writes[a] {
+                    let a_reads = reads[a].contains(r);
+                    let b_reads = reads[b].contains(r);
+                    let b_writes = writes[b].contains(r);
+
+                    let before = if b_reads && !b_writes {
+                        true
+                    } else if b_reads && b_writes {
+                        !a_reads || a < b
+                    } else if b_writes {
+                        !a_reads && a < b
+                    } else {
+                        false
+                    };
+
+                    if before {
+                        dependencies[b].insert(a);
+                    }
+                }
+            }
+        }
+
+        let mut remaining: Vec<usize> = dependencies.iter().map(|d| d.len()).collect();
+        let mut ready: BTreeSet<usize> = (0..count).filter(|i| remaining[*i] == 0).collect();
+        let mut order = Vec::<usize>::new();
+
+        while let Some(i) = ready.pop_first() {
+            order.push(i);
+            for (b, deps) in dependencies.iter().enumerate() {
+                if deps.contains(&i) {
+                    remaining[b] -= 1;
+                    if remaining[b] == 0 {
+                        ready.insert(b);
+                    }
+                }
+            }
+        }
+
+        if order.len() != count {
+            return Err(anyhow!(
+                "[RenderGraph] Cycle between the nodes' resource declarations"
+            ));
+        }
+
+        self.order = order;
+        self.compiled = true;
+        Ok(())
+    }
+
+    fn allocate(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        for (name, desc) in &self.transient_descs {
+            let size = wgpu::Extent3d {
+                width: ((width as f32 * desc.scale) as u32).max(1),
+                height: ((height as f32 * desc.scale) as u32).max(1),
+                depth_or_array_layers: 1,
+            };
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(name),
+                size,
+                // Small surfaces can't hold the whole mip chain.
+                mip_level_count: desc
+                    .mip_level_count
+                    .min(size.max_mips(wgpu::TextureDimension::D2)),
+                sample_count: desc.sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format: desc.format,
+                usage: desc.usage,
+                view_formats: &[],
+            });
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+            self.resources
+                .textures
+                .insert(name.clone(), GraphTexture { texture, view });
+        }
+        self.allocated_size = (width, height);
+        self.needs_allocation = false;
+    }
+
+    pub fn execute(
+        &mut self,
+        frame: &mut FrameContext,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> anyhow::Result<()> {
+        if !self.compiled {
+            self.compile()?;
+        }
+
+        let size = (frame.size.0.max(1), frame.size.1.max(1));
+        if self.needs_allocation || size != self.allocated_size {
+            self.allocate(frame.device, size.0, size.1);
+        }
+
+        let nodes = &mut self.nodes;
+        let resources = &self.resources;
+        for i in &self.order {
+            nodes[*i].execute(frame, resources, encoder);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct StubNode {
+        name: &'static str,
+        reads: Vec<&'static str>,
+        writes: Vec<&'static str>,
+    }
+
+    impl RenderNode for StubNode {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn reads(&self) -> Vec<String> {
+            self.reads.iter().map(|r| r.to_string()).collect()
+        }
+
+        fn writes(&self) -> Vec<String> {
+            self.writes.iter().map(|w| w.to_string()).collect()
+        }
+
+        fn execute(&mut self, _: &mut FrameContext, _: &GraphResources, _: &mut wgpu::CommandEncoder) {
+            unreachable!()
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+    }
+
+    fn node(name: &'static str, reads: &[&'static str], writes: &[&'static str]) -> StubNode {
+        StubNode {
+            name,
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+        }
+    }
+
+    // Resources are only declared, so no device is needed. Nothing is allocated before execute.
+    fn graph(resources: &[&str], nodes: Vec<StubNode>) -> RenderGraph {
+        let mut graph = RenderGraph::new();
+        for r in resources {
+            graph.declare_texture(r, TransientTextureDesc::new(HDR_FORMAT));
+        }
+        for n in nodes {
+            graph.add_node(n);
+        }
+        graph
+    }
+
+    fn order(resources: &[&str], nodes: Vec<StubNode>) -> Vec<String> {
+        let mut graph = graph(resources, nodes);
+        graph.compile().unwrap();
+        graph.node_names()
+    }
+
+    #[test]
+    fn writers_run_before_readers() {
+        let names = order(
+            &["a"],
+            vec![node("reader", &["a"], &[]), node("writer", &[], &["a"])],
+        );
+        assert_eq!(names, ["writer", "reader"]);
+    }
+
+    #[test]
+    fn read_write_nodes_run_after_writers_and_in_declaration_order() {
+        let names = order(
+            &["a"],
+            vec![
+                node("first", &["a"], &["a"]),
+                node("writer", &[], &["a"]),
+                node("second", &["a"], &["a"]),
+                node("reader", &["a"], &[]),
+            ],
+        );
+        assert_eq!(names, ["writer", "first", "second", "reader"]);
+    }
+
+    #[test]
+    fn write_only_nodes_run_in_declaration_order() {
+        let names = order(
+            &["a"],
+            vec![node("second", &[], &["a"]), node("first", &[], &["a"]), node("third", &[], &["a"])],
+        );
+        assert_eq!(names, ["second", "first", "third"]);
+    }
+
+    #[test]
+    fn independent_nodes_keep_declaration_order() {
+        let names = order(
+            &["a", "b"],
+            vec![node("b", &[], &["b"]), node("a", &[], &["a"])],
+        );
+        assert_eq!(names, ["b", "a"]);
+    }
+
+    // Like the window's graph: lights, then the scene passes loading HDR, then the post passes.
+    #[test]
+    fn chain_of_passes() {
+        let names = order(
+            &["lights", "hdr", "depth", "surface"],
+            vec![
+                node("tonemap", &["hdr"], &["surface"]),
+                node("forward", &["lights", "hdr", "depth"], &["hdr", "depth"]),
+                node("light_culling", &[], &["lights"]),
+                node("sky", &["lights", "hdr", "depth"], &["hdr", "depth"]),
+                node("environment", &[], &["lights"]),
+            ],
+        );
+        assert_eq!(names, ["light_culling", "environment", "forward", "sky", "tonemap"]);
+    }
+
+    #[test]
+    fn cycle_is_an_error() {
+        let mut graph = graph(
+            &["a", "b"],
+            vec![node("x", &["a"], &["b"]), node("y", &["b"], &["a"])],
+        );
+        assert!(graph.compile().is_err());
+    }
+
+    #[test]
+    fn undeclared_read_is_an_error() {
+        let mut graph = graph(&[], vec![node("reader", &["missing"], &[])]);
+        assert!(graph.compile().is_err());
+    }
+
+    #[test]
+    fn imported_resources_are_declared() {
+        let mut graph = graph(&[], vec![node("writer", &[], &[SURFACE]), node("reader", &[SURFACE], &[])]);
+        graph.imported.insert(SURFACE.to_owned());
+        graph.compile().unwrap();
+        assert_eq!(graph.node_names(), ["writer", "reader"]);
+    }
+
+    #[test]
+    fn undeclared_write_is_an_error() {
+        let mut graph = graph(&[], vec![node("writer", &[], &["missing"])]);
+        assert!(graph.compile().is_err());
+    }
+}
@@ -0,0 +1,65 @@
+use crate::passes::render_graph::*;
+use std::any::Any;
+
+pub const RESOLVE: &str = "resolve";
+
+// Resolves a multisampled attachment into a single-sampled one. The pass has no draws, only the resolve target.
+pub struct ResolvePass {
+    pub input: String,
+    pub output: String,
+}
+
+impl ResolvePass {
+    pub fn new(input: &str, output: &str) -> Self {
+        Self {
+            input: input.to_owned(),
+            output: output.to_owned(),
+        }
+    }
+}
+
+impl RenderNode for ResolvePass {
+    fn name(&self) -> &str {
+        RESOLVE
+    }
+
+    fn reads(&self) -> Vec<String> {
+        vec![self.input.clone()]
+    }
+
+    fn writes(&self) -> Vec<String> {
+        vec![self.output.clone()]
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn execute(
+        &mut self,
+        _frame: &mut FrameContext,
+        resources: &GraphResources,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Resolve Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: resources.view(&self.input),
+                resolve_target: Some(resources.view(&self.output)),
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+            multiview_mask: None,
+        });
+    }
+}
@@ -7,13 +7,6 @@ struct Camera {
 @group(1) @binding(0)
 var<uniform> camera: Camera;
 
-struct Light {
-    position: vec3<f32>,
-    color: vec3<f32>,
-}
-@group(2) @binding(0)
-var<uniform> light: Light;
-
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
@@ -29,6 +22,9 @@ struct InstanceInput {
     @location(9) normal_matrix_0: vec3<f32>,
     @location(10) normal_matrix_1: vec3<f32>,
     @location(11) normal_matrix_2: vec3<f32>,
+    @location(12) tint: vec4<f32>,
+    @location(13) emissive: f32,
+    @location(14) user_data: vec4<f32>,
 }
 
 struct VertexOutput {
@@ -37,6 +33,15 @@ struct VertexOutput {
     @location(1) tangent_position: vec3<f32>,
     @location(2) tangent_light_position: vec3<f32>,
     @location(3) tangent_view_position: vec3<f32>,
+    @location(4) @interpolate(flat) tint: vec4<f32>,
+    @location(5) @interpolate(flat) emissive: f32,
+    // For custom shaders, the instance's user data.
+    @location(6) @interpolate(flat) user_data: vec4<f32>,
+    // For the point lights, which are brought into tangent space per fragment.
+    @location(7) world_position: vec3<f32>,
+    @location(8) world_tangent: vec3<f32>,
+    @location(9) world_bitangent: vec3<f32>,
+    @location(10) world_normal: vec3<f32>,
 }
 
 @vertex
@@ -74,6 +79,13 @@ fn vs_main(
     out.tangent_position = tangent_matrix * world_position.xyz;
     out.tangent_view_position = tangent_matrix * camera.view_pos.xyz;
     out.tangent_light_position = tangent_matrix * light.position;
+    out.tint = instance.tint;
+    out.emissive = instance.emissive;
+    out.user_data = instance.user_data;
+    out.world_position = world_position.xyz;
+    out.world_tangent = world_tangent;
+    out.world_bitangent = world_bitangent;
+    out.world_normal = world_normal;
     return out;
 }
 
@@ -88,9 +100,17 @@ var t_normal: texture_2d<f32>;
 @group(0) @binding(3)
 var s_normal: sampler;
 
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+struct MaterialParams {
+    alpha_mode: u32,
+    alpha_cutoff: f32,
+}
+@group(0) @binding(4)
+var<uniform> material: MaterialParams;
+
+const ALPHA_MODE_MASK: u32 = 1u;
+
+fn shade(in: VertexOutput) -> vec4<f32> {
+    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords) * in.tint;
     let object_normal: vec4<f32> = textureSample(t_normal, s_normal, in.tex_coords);
     
     // We don't need (or want) much ambient light, so 0.1 is fine
@@ -109,7 +129,43 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let specular_strength = pow(max(dot(tangent_normal, half_dir), 0.0), 32.0);
     let specular_color = specular_strength * light.color;
 
-    let result = (ambient_color + diffuse_color + specular_color) * object_color.xyz;
+    var point_color = vec3<f32>(0.0);
+    let cluster = cluster_index(in.clip_position.xy, in.world_position);
+    let num_point_lights = cluster_light_count(cluster);
+    for (var i = 0u; i < num_point_lights; i++) {
+        let point_light = cluster_light(cluster, i);
+        let to_light = point_light.position - in.world_position;
+        let point_dir = normalize(vec3<f32>(
+            dot(in.world_tangent, to_light),
+            dot(in.world_bitangent, to_light),
+            dot(in.world_normal, to_light),
+        ));
+        let point_half_dir = normalize(view_dir + point_dir);
+        let radiance = point_light.color * point_light.intensity
+            * point_light_attenuation(length(to_light), point_light.range);
+        point_color += radiance * (max(dot(tangent_normal, point_dir), 0.0)
+            + pow(max(dot(tangent_normal, point_half_dir), 0.0), 32.0));
+    }
+
+    let result = (ambient_color + diffuse_color + specular_color + point_color + in.emissive) * object_color.xyz;
+
+    return vec4<f32>(apply_fog(result, camera.view_pos.xyz, in.world_position), object_color.a);
+}
 
-    return vec4<f32>(result, object_color.a);
-}
\ No newline at end of file
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = shade(in);
+    if (material.alpha_mode == ALPHA_MODE_MASK && color.a < material.alpha_cutoff) {
+        discard;
+    }
+    return color;
+}
+
+// For masked materials drawn with alpha-to-coverage. Sharpens alpha around the cutoff so the coverage
+// becomes a ~1 pixel wide antialiased edge instead of a discard.
+@fragment
+fn fs_alpha_to_coverage(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = shade(in);
+    let alpha = (color.a - material.alpha_cutoff) / max(fwidth(color.a), 0.0001) + 0.5;
+    return vec4<f32>(color.rgb, clamp(alpha, 0.0, 1.0));
+}
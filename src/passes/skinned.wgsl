@@ -6,13 +6,6 @@ struct Camera {
 @group(1) @binding(0)
 var<uniform> camera: Camera;
 
-struct Light {
-    position: vec3<f32>,
-    color: vec3<f32>,
-}
-@group(2) @binding(0)
-var<uniform> light: Light;
-
 struct BoneMatrix {
     data: array<mat4x4<f32>>,
 };
@@ -22,8 +15,19 @@ var<storage, read> bone_matrices: BoneMatrix;
 @group(3) @binding(1)
 var<uniform> num_bones: u32;
 
+// Blend shapes. MAX_MORPH_TARGETS weights per character, and the model's deltas laid out
+// target by target, starting at the mesh's offset.
+struct MorphDelta {
+    position: vec4<f32>,
+    normal: vec4<f32>,
+}
+const MAX_MORPH_TARGETS: u32 = 32u;
+@group(3) @binding(2)
+var<storage, read> morph_weights: array<f32>;
+@group(3) @binding(3)
+var<storage, read> morph_deltas: array<MorphDelta>;
+
 struct VertexInput {
-    @builtin(instance_index) instance_index: u32,
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
     @location(2) normal: vec3<f32>,
@@ -38,6 +42,26 @@ struct InstanceInput {
     @location(8) model_matrix_1: vec4<f32>,
     @location(9) model_matrix_2: vec4<f32>,
     @location(10) model_matrix_3: vec4<f32>,
+    @location(11) skeleton_index: u32,
+    // Offset into morph_deltas, vertex count and target count of the mesh.
+    @location(12) morph: vec3<u32>,
+    @location(13) tint: vec4<f32>,
+    @location(14) emissive: f32,
+    @location(15) user_data: vec4<f32>,
+}
+
+fn apply_morphs(vertex_index: u32, skeleton_index: u32, morph: vec3<u32>, position: vec3<f32>, normal: vec3<f32>) -> MorphDelta {
+    var result = MorphDelta(vec4<f32>(position, 1.0), vec4<f32>(normal, 0.0));
+    for (var t = 0u; t < min(morph.z, MAX_MORPH_TARGETS); t++) {
+        let weight = morph_weights[skeleton_index * MAX_MORPH_TARGETS + t];
+        if weight == 0.0 {
+            continue;
+        }
+        let delta = morph_deltas[morph.x + t * morph.y + vertex_index];
+        result.position += vec4<f32>(delta.position.xyz * weight, 0.0);
+        result.normal += vec4<f32>(delta.normal.xyz * weight, 0.0);
+    }
+    return result;
 }
 
 struct VertexOutput {
@@ -46,10 +70,20 @@ struct VertexOutput {
     @location(1) tangent_position: vec3<f32>,
     @location(2) tangent_light_position: vec3<f32>,
     @location(3) tangent_view_position: vec3<f32>,
+    @location(4) @interpolate(flat) tint: vec4<f32>,
+    @location(5) @interpolate(flat) emissive: f32,
+    // For custom shaders, the instance's user data.
+    @location(6) @interpolate(flat) user_data: vec4<f32>,
+    // For the point lights, which are brought into tangent space per fragment.
+    @location(7) world_position: vec3<f32>,
+    @location(8) world_tangent: vec3<f32>,
+    @location(9) world_bitangent: vec3<f32>,
+    @location(10) world_normal: vec3<f32>,
 }
 
 @vertex
 fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
     model: VertexInput,
     instance: InstanceInput,
 ) -> VertexOutput {
@@ -60,14 +94,16 @@ fn vs_main(
         instance.model_matrix_3,
     );
  
-    let offset = num_bones * model.instance_index;
+    let offset = num_bones * instance.skeleton_index;
     let bone_transform = mat4x4<f32>(
         (bone_matrices.data[offset + model.bone_indices.x] * model.bone_weights.x) + (bone_matrices.data[offset + model.bone_indices.y] * model.bone_weights.y) + (bone_matrices.data[offset + model.bone_indices.z] * model.bone_weights.z) + (bone_matrices.data[offset + model.bone_indices.w] * model.bone_weights.w )
     );
 
+    let morphed = apply_morphs(vertex_index, instance.skeleton_index, instance.morph, model.position, model.normal);
+
     let world_matrix = model_matrix * bone_transform;
-    let world_position = world_matrix * vec4<f32>(model.position, 1.0);
-    let skinned_normal = normalize(mat3x3<f32>(world_matrix[0].xyz, world_matrix[1].xyz, world_matrix[2].xyz) * model.normal);
+    let world_position = world_matrix * morphed.position;
+    let skinned_normal = normalize(mat3x3<f32>(world_matrix[0].xyz, world_matrix[1].xyz, world_matrix[2].xyz) * morphed.normal.xyz);
     let transformed_tangent = bone_transform * vec4<f32>(model.tangent, 0.0);
     let skinned_tangent = normalize(transformed_tangent.xyz);
     let skinned_bitangent = cross(skinned_normal, skinned_tangent);
@@ -83,6 +119,13 @@ fn vs_main(
     out.tangent_position = tbn_matrix * world_position.xyz;
     out.tangent_view_position = tbn_matrix * camera.view_pos.xyz;
     out.tangent_light_position = tbn_matrix * light.position;
+    out.tint = instance.tint;
+    out.emissive = instance.emissive;
+    out.user_data = instance.user_data;
+    out.world_position = world_position.xyz;
+    out.world_tangent = skinned_tangent;
+    out.world_bitangent = skinned_bitangent;
+    out.world_normal = skinned_normal;
     return out;
 }
 
@@ -97,9 +140,17 @@ var t_normal: texture_2d<f32>;
 @group(0) @binding(3)
 var s_normal: sampler;
 
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+struct MaterialParams {
+    alpha_mode: u32,
+    alpha_cutoff: f32,
+}
+@group(0) @binding(4)
+var<uniform> material: MaterialParams;
+
+const ALPHA_MODE_MASK: u32 = 1u;
+
+fn shade(in: VertexOutput) -> vec4<f32> {
+    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords) * in.tint;
     let object_normal: vec4<f32> = textureSample(t_normal, s_normal, in.tex_coords);
     
     // We don't need (or want) much ambient light, so 0.1 is fine
@@ -118,7 +169,43 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let specular_strength = pow(max(dot(tangent_normal, half_dir), 0.0), 32.0);
     let specular_color = specular_strength * light.color;
 
-    let result = (ambient_color + diffuse_color + specular_color) * object_color.xyz;
+    var point_color = vec3<f32>(0.0);
+    let cluster = cluster_index(in.clip_position.xy, in.world_position);
+    let num_point_lights = cluster_light_count(cluster);
+    for (var i = 0u; i < num_point_lights; i++) {
+        let point_light = cluster_light(cluster, i);
+        let to_light = point_light.position - in.world_position;
+        let point_dir = normalize(vec3<f32>(
+            dot(in.world_tangent, to_light),
+            dot(in.world_bitangent, to_light),
+            dot(in.world_normal, to_light),
+        ));
+        let point_half_dir = normalize(view_dir + point_dir);
+        let radiance = point_light.color * point_light.intensity
+            * point_light_attenuation(length(to_light), point_light.range);
+        point_color += radiance * (max(dot(tangent_normal, point_dir), 0.0)
+            + pow(max(dot(tangent_normal, point_half_dir), 0.0), 32.0));
+    }
+
+    let result = (ambient_color + diffuse_color + specular_color + point_color + in.emissive) * object_color.xyz;
+
+    return vec4<f32>(apply_fog(result, camera.view_pos.xyz, in.world_position), object_color.a);
+}
 
-    return vec4<f32>(result, object_color.a);
-}
\ No newline at end of file
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = shade(in);
+    if (material.alpha_mode == ALPHA_MODE_MASK && color.a < material.alpha_cutoff) {
+        discard;
+    }
+    return color;
+}
+
+// For masked materials drawn with alpha-to-coverage. Sharpens alpha around the cutoff so the coverage
+// becomes a ~1 pixel wide antialiased edge instead of a discard.
+@fragment
+fn fs_alpha_to_coverage(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = shade(in);
+    let alpha = (color.a - material.alpha_cutoff) / max(fwidth(color.a), 0.0001) + 0.5;
+    return vec4<f32>(color.rgb, clamp(alpha, 0.0, 1.0));
+}
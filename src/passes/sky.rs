@@ -0,0 +1,134 @@
+// Fills the background of the scene with its sky, or its fog colour, after the opaque passes and before anything
+// blended. Skipped for scenes with neither, which keep the forward renderer's clear colour.
+
+use crate::graphics::*;
+use crate::passes::render_graph::*;
+use crate::texture::*;
+use std::any::Any;
+
+pub const SKY: &str = "sky";
+
+pub struct SkyPass {
+    pub pipeline: wgpu::RenderPipeline,
+    pub color_target: String,
+    pub depth_target: String,
+}
+
+impl RenderNode for SkyPass {
+    fn name(&self) -> &str {
+        SKY
+    }
+
+    fn reads(&self) -> Vec<String> {
+        vec![
+            LIGHT_BUFFER.to_owned(),
+            self.color_target.clone(),
+            self.depth_target.clone(),
+        ]
+    }
+
+    // Depth too, though it only tests against it, so that it's ordered with the other scene passes rather than
+    // after every one of them.
+    fn writes(&self) -> Vec<String> {
+        vec![self.color_target.clone(), self.depth_target.clone()]
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn execute(
+        &mut self,
+        frame: &mut FrameContext,
+        resources: &GraphResources,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        if !frame.scene.environment.draws_background() {
+            return;
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Sky Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: resources.view(&self.color_target),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: resources.view(&self.depth_target),
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+            multiview_mask: None,
+        });
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(2, resources.bind_group(LIGHT_BUFFER), &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+impl SkyPass {
+    pub fn new(
+        device: &wgpu::Device,
+        light_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Sky Pipeline Layout"),
+            // At group 2, where the shared chunks expect the lights.
+            bind_group_layouts: &[None, None, Some(light_bind_group_layout)],
+            immediate_size: 0,
+        });
+
+        let pipeline = {
+            let shader = wgpu::ShaderModuleDescriptor {
+                label: Some("Sky Shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    concat!(
+                        include_str!("clustered_lighting.wgsl"),
+                        include_str!("environment.wgsl"),
+                        include_str!("sky.wgsl"),
+                    )
+                    .into(),
+                ),
+            };
+            create_render_pipeline_with_options(
+                device,
+                &layout,
+                color_format,
+                Some(Texture::DEPTH_FORMAT),
+                &[],
+                shader,
+                &RenderPipelineOptions {
+                    // The triangle is at the far plane, which only the cleared depth matches.
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::LessEqual,
+                    cull_mode: None,
+                    sample_count,
+                    ..Default::default()
+                },
+            )
+        };
+
+        Self {
+            pipeline,
+            color_target: HDR.to_owned(),
+            depth_target: DEPTH.to_owned(),
+        }
+    }
+}
@@ -0,0 +1,35 @@
+// The sky behind the scene: the atmosphere when the scene has one, otherwise the fog colour. Drawn at the far plane,
+// so only where nothing else was. Reads the light bind group's cluster params and environment, from the chunks
+// prepended to it.
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+// A single triangle that covers the screen.
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.ndc = uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+    out.clip_position = vec4<f32>(out.ndc, 1.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    if (environment.sun_direction.w == 0.0) {
+        return vec4<f32>(environment.fog_color.rgb, 1.0);
+    }
+    // The view's rotation is orthonormal, so its transpose brings the direction back to world space.
+    let view_point = clusters.inverse_projection * vec4<f32>(in.ndc, 1.0, 1.0);
+    let view_rotation = mat3x3<f32>(clusters.view[0].xyz, clusters.view[1].xyz, clusters.view[2].xyz);
+    let direction = normalize(transpose(view_rotation) * (view_point.xyz / view_point.w));
+
+    var color = sky_color(direction);
+    let cos_theta = dot(direction, environment.sun_direction.xyz);
+    let disk = smoothstep(environment.sun_color.w - 0.0001, environment.sun_color.w, cos_theta);
+    color += environment.sun_color.rgb * disk * saturate(direction.y * 50.0 + 1.0);
+    return vec4<f32>(color, 1.0);
+}
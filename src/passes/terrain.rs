@@ -0,0 +1,277 @@
+// Draws the Scene's terrains after the opaque pass. Chunks outside the view are skipped,
+// and each visible one is drawn at the level of detail for its distance to the eye.
+
+use crate::culling::*;
+use crate::graphics::*;
+use crate::model::Vertex;
+use crate::passes::render_graph::*;
+use crate::terrain::*;
+use crate::texture::*;
+use std::any::Any;
+
+pub const TERRAIN: &str = "terrain";
+
+pub struct TerrainRenderer {
+    pub pipeline: wgpu::RenderPipeline,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    // Repeating, for the tiled layers.
+    pub layer_sampler: wgpu::Sampler,
+    pub frustum: Frustum,
+    pub culling_enabled: bool,
+    // Chunks drawn last frame, out of how many.
+    pub visible_chunks: usize,
+    pub total_chunks: usize,
+    pub color_target: String,
+    pub depth_target: String,
+}
+
+impl RenderNode for TerrainRenderer {
+    fn name(&self) -> &str {
+        TERRAIN
+    }
+
+    fn reads(&self) -> Vec<String> {
+        vec![
+            CAMERA_BUFFER.to_owned(),
+            LIGHT_BUFFER.to_owned(),
+            self.color_target.clone(),
+            self.depth_target.clone(),
+        ]
+    }
+
+    fn writes(&self) -> Vec<String> {
+        vec![self.color_target.clone(), self.depth_target.clone()]
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn execute(
+        &mut self,
+        frame: &mut FrameContext,
+        resources: &GraphResources,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        let terrains = &mut frame.scene.terrains;
+        if terrains.iter().all(|t| !t.visible) {
+            return;
+        }
+
+        self.frustum = if self.culling_enabled {
+            Frustum::from_view_projection(&frame.view_projection)
+        } else {
+            Frustum::default()
+        };
+        self.visible_chunks = 0;
+        self.total_chunks = 0;
+
+        for t in terrains.iter_mut() {
+            if t.bind_group.is_none() {
+                t.bind_group = Some(self.create_bind_group(frame.device, t));
+            }
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Terrain Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: resources.view(&self.color_target),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: resources.view(&self.depth_target),
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+            multiview_mask: None,
+        });
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(1, resources.bind_group(CAMERA_BUFFER), &[]);
+        render_pass.set_bind_group(2, resources.bind_group(LIGHT_BUFFER), &[]);
+
+        for t in terrains.iter().filter(|t| t.visible) {
+            render_pass.set_bind_group(0, t.bind_group.as_ref().unwrap(), &[]);
+            for chunk in t.chunks.iter() {
+                self.total_chunks += 1;
+                if !self.frustum.intersects_sphere(&chunk.bounds) {
+                    continue;
+                }
+                self.visible_chunks += 1;
+
+                let distance = (frame.eye.distance(chunk.bounds.center) - chunk.bounds.radius).max(0.0);
+                let lod = &chunk.lods[t.settings.lod_for_distance(distance).min(chunk.lods.len() - 1)];
+                render_pass.set_vertex_buffer(0, lod.vertex_buffer.slice(..));
+                render_pass.set_index_buffer(lod.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.draw_indexed(0..lod.num_elements, 0, 0..1);
+            }
+        }
+    }
+}
+
+impl TerrainRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        light_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let texture_entry = |binding: u32| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let sampler_entry = |binding: u32| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0),
+                sampler_entry(1),
+                texture_entry(2),
+                texture_entry(3),
+                texture_entry(4),
+                texture_entry(5),
+                sampler_entry(6),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 7,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("Terrain Bind Group Layout"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Terrain Pipeline Layout"),
+            bind_group_layouts: &[
+                Some(&bind_group_layout),
+                Some(camera_bind_group_layout),
+                Some(light_bind_group_layout),
+            ],
+            immediate_size: 0,
+        });
+
+        let pipeline = {
+            let shader = wgpu::ShaderModuleDescriptor {
+                label: Some("Terrain Shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    concat!(
+                        include_str!("clustered_lighting.wgsl"),
+                        include_str!("environment.wgsl"),
+                        include_str!("terrain.wgsl"),
+                    )
+                    .into(),
+                ),
+            };
+            create_render_pipeline_with_options(
+                device,
+                &layout,
+                color_format,
+                Some(Texture::DEPTH_FORMAT),
+                &[Some(TerrainVertex::desc())],
+                shader,
+                &RenderPipelineOptions {
+                    // Skirts are seen from both sides.
+                    cull_mode: None,
+                    sample_count,
+                    ..Default::default()
+                },
+            )
+        };
+
+        let layer_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Terrain Layer Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            layer_sampler,
+            frustum: Frustum::default(),
+            culling_enabled: true,
+            visible_chunks: 0,
+            total_chunks: 0,
+            color_target: HDR.to_owned(),
+            depth_target: DEPTH.to_owned(),
+        }
+    }
+
+    fn create_bind_group(&self, device: &wgpu::Device, terrain: &Terrain) -> wgpu::BindGroup {
+        let layers = &terrain.material.layers;
+        let layer_view = |i: usize| &layers[i.min(layers.len() - 1)].view;
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&terrain.material.splat_map.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&terrain.material.splat_map.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(layer_view(0)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(layer_view(1)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(layer_view(2)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(layer_view(3)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::Sampler(&self.layer_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: terrain.uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("Terrain Bind Group"),
+        })
+    }
+}
@@ -0,0 +1,83 @@
+// Terrain, blending four tiled layers by the weights in the splat map.
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(1) @binding(0)
+var<uniform> camera: Camera;
+
+struct TerrainParams {
+    texture_scale: f32,
+}
+@group(0) @binding(0)
+var t_splat: texture_2d<f32>;
+@group(0) @binding(1)
+var s_splat: sampler;
+@group(0) @binding(2)
+var t_layer0: texture_2d<f32>;
+@group(0) @binding(3)
+var t_layer1: texture_2d<f32>;
+@group(0) @binding(4)
+var t_layer2: texture_2d<f32>;
+@group(0) @binding(5)
+var t_layer3: texture_2d<f32>;
+@group(0) @binding(6)
+var s_layer: sampler;
+@group(0) @binding(7)
+var<uniform> params: TerrainParams;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) splat_coords: vec2<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) splat_coords: vec2<f32>,
+}
+
+@vertex
+fn vs_main(model: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(model.position, 1.0);
+    out.world_position = model.position;
+    out.world_normal = model.normal;
+    out.splat_coords = model.splat_coords;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    var weights = textureSample(t_splat, s_splat, in.splat_coords);
+    weights = weights / max(weights.r + weights.g + weights.b + weights.a, 0.0001);
+
+    let uv = in.world_position.xz / params.texture_scale;
+    let albedo = textureSample(t_layer0, s_layer, uv).rgb * weights.r
+        + textureSample(t_layer1, s_layer, uv).rgb * weights.g
+        + textureSample(t_layer2, s_layer, uv).rgb * weights.b
+        + textureSample(t_layer3, s_layer, uv).rgb * weights.a;
+
+    let normal = normalize(in.world_normal);
+    let light_dir = normalize(light.position - in.world_position);
+    let ambient = light.color * 0.1;
+    let diffuse = light.color * max(dot(normal, light_dir), 0.0);
+
+    var point_diffuse = vec3<f32>(0.0);
+    let cluster = cluster_index(in.clip_position.xy, in.world_position);
+    let num_point_lights = cluster_light_count(cluster);
+    for (var i = 0u; i < num_point_lights; i++) {
+        let point_light = cluster_light(cluster, i);
+        let to_light = point_light.position - in.world_position;
+        let light_distance = length(to_light);
+        point_diffuse += point_light.color * point_light.intensity
+            * point_light_attenuation(light_distance, point_light.range)
+            * max(dot(normal, to_light / max(light_distance, 0.0001)), 0.0);
+    }
+
+    let lit = albedo * (ambient + diffuse + point_diffuse);
+    return vec4<f32>(apply_fog(lit, camera.view_pos.xyz, in.world_position), 1.0);
+}
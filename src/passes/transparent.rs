@@ -0,0 +1,388 @@
+// Alpha blended geometry. Runs after the opaque pass, testing against its depth without writing to it.
+// Every blended mesh instance in view is sorted back to front (by the distance from the eye to the mesh's origin)
+// and written into one per-frame buffer. Consecutive instances of the same mesh are drawn together.
+
+use crate::culling::*;
+use crate::graphics::*;
+use crate::index_types::*;
+use crate::instance::*;
+use crate::model::*;
+use crate::passes::render_graph::*;
+use crate::skinned_model::*;
+use crate::texture::*;
+use rayon::prelude::*;
+use std::any::Any;
+
+pub const TRANSPARENT: &str = "transparent";
+
+pub struct TransparentRenderer {
+    pub render_pipeline: wgpu::RenderPipeline,
+    pub skinned_render_pipeline: wgpu::RenderPipeline,
+    pub instance_buffer: InstanceBuffer,
+    pub skinned_instance_buffer: InstanceBuffer,
+    pub frustum: Frustum,
+    pub culling_enabled: bool,
+    pub color_target: String,
+    pub depth_target: String,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Source {
+    Model,
+    Character,
+}
+
+struct SortedInstance {
+    source: Source,
+    node_idx: usize,
+    mesh_idx: usize,
+    distance: f32,
+    instance: usize,
+}
+
+// A run of sorted instances sharing a mesh, stored from `start` in the buffer of its source.
+struct Batch {
+    source: Source,
+    node_idx: usize,
+    mesh_idx: usize,
+    start: u32,
+    count: u32,
+}
+
+impl RenderNode for TransparentRenderer {
+    fn name(&self) -> &str {
+        TRANSPARENT
+    }
+
+    fn reads(&self) -> Vec<String> {
+        vec![
+            CAMERA_BUFFER.to_owned(),
+            LIGHT_BUFFER.to_owned(),
+            self.color_target.clone(),
+            self.depth_target.clone(),
+        ]
+    }
+
+    fn writes(&self) -> Vec<String> {
+        vec![self.color_target.clone()]
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn execute(
+        &mut self,
+        frame: &mut FrameContext,
+        resources: &GraphResources,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        self.frustum = if self.culling_enabled {
+            Frustum::from_view_projection(&frame.view_projection)
+        } else {
+            Frustum::default()
+        };
+        let frustum = self.frustum;
+
+        let models = frame.models;
+        let skinned_models = frame.skinned_models;
+        let model_nodes = &frame.scene.model_nodes;
+        let characters_contexts = &frame.scene.characters_contexts;
+        let eye = frame.eye;
+
+        let mut sorted = Vec::<SortedInstance>::new();
+
+        for (node_idx, m) in model_nodes.iter().enumerate() {
+            let model = &models[m.model_idx];
+            for (mesh_idx, mesh) in model.meshes.iter().enumerate() {
+                if !model.materials[mesh.material].is_transparent() {
+                    continue;
+                }
+                let bounds = mesh_bounding_sphere(mesh.translation, mesh.scale, mesh.dimensions);
+                sorted.par_extend(m.instances.par_iter().enumerate().filter_map(|(idx, i)| {
+                    let sphere = bounds.transformed_by_instance(i);
+                    (m.is_visible_at(idx) && frustum.intersects_sphere(&sphere))
+                        .then(|| SortedInstance {
+                            source: Source::Model,
+                            node_idx,
+                            mesh_idx,
+                            distance: eye.distance_squared(sphere.center),
+                            instance: idx,
+                        })
+                }));
+            }
+        }
+
+        for (node_idx, c) in characters_contexts.iter().enumerate() {
+            let node = &c.skinned_model_node;
+            let model = &skinned_models[node.skinned_model_idx];
+            for (mesh_idx, mesh) in model.meshes.iter().enumerate() {
+                if !model.materials[mesh.material].is_transparent() {
+                    continue;
+                }
+                let mut bounds = mesh_bounding_sphere(mesh.translation, mesh.scale, mesh.dimensions);
+                bounds.radius *= SKINNED_BOUNDS_PADDING;
+                sorted.par_extend(node.instances.par_iter().enumerate().filter_map(|(idx, i)| {
+                    let sphere = bounds.transformed_by_instance(i);
+                    frustum
+                        .intersects_sphere(&sphere)
+                        .then(|| SortedInstance {
+                            source: Source::Character,
+                            node_idx,
+                            mesh_idx,
+                            distance: eye.distance_squared(sphere.center),
+                            instance: idx,
+                        })
+                }));
+            }
+        }
+
+        if sorted.is_empty() {
+            return;
+        }
+
+        // Farthest first. Equal distances keep mesh order so that batches stay as long as possible.
+        sorted.par_sort_by(|a, b| b.distance.total_cmp(&a.distance));
+
+        let mut batches = Vec::<Batch>::new();
+        let mut instance_data = Vec::<InstanceRaw>::new();
+        let mut skinned_instance_data = Vec::<SkinnedInstanceRaw>::new();
+
+        for s in &sorted {
+            let start = match s.source {
+                Source::Model => {
+                    let m = &model_nodes[s.node_idx];
+                    let mesh = &models[m.model_idx].meshes[TexturedMeshIndex::new(s.mesh_idx)];
+                    let mesh_m_mat = glam::Mat4::from_scale_rotation_translation(
+                        mesh.scale,
+                        mesh.rotation,
+                        mesh.translation,
+                    );
+                    let mesh_n_mat = glam::Mat3::from_quat(mesh.rotation);
+                    instance_data.push(
+                        m.instances[s.instance]
+                            .to_raw()
+                            .composed(&mesh_m_mat, &mesh_n_mat),
+                    );
+                    instance_data.len() - 1
+                }
+                Source::Character => {
+                    let node = &characters_contexts[s.node_idx].skinned_model_node;
+                    let mesh = &skinned_models[node.skinned_model_idx].meshes[SkinnedMeshIndex::new(s.mesh_idx)];
+                    let mesh_mat = glam::Mat4::from_scale_rotation_translation(
+                        mesh.scale,
+                        mesh.rotation,
+                        mesh.translation,
+                    );
+                    skinned_instance_data.push(
+                        node.instances[s.instance]
+                            .to_skinned_raw(s.instance as u32)
+                            .composed(&mesh_mat, mesh.morph_params()),
+                    );
+                    skinned_instance_data.len() - 1
+                }
+            };
+
+            if let Some(b) = batches.last_mut() {
+                if b.source == s.source && b.node_idx == s.node_idx && b.mesh_idx == s.mesh_idx {
+                    b.count += 1;
+                    continue;
+                }
+            }
+            batches.push(Batch {
+                source: s.source,
+                node_idx: s.node_idx,
+                mesh_idx: s.mesh_idx,
+                start: start as u32,
+                count: 1,
+            });
+        }
+
+        self.instance_buffer
+            .write(frame.device, frame.queue, &instance_data);
+        self.skinned_instance_buffer
+            .write(frame.device, frame.queue, &skinned_instance_data);
+
+        let camera_bind_group = resources.bind_group(CAMERA_BUFFER);
+        let light_bind_group = resources.bind_group(LIGHT_BUFFER);
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Transparent Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: resources.view(&self.color_target),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: resources.view(&self.depth_target),
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+            multiview_mask: None,
+        });
+
+        let mut current_source = None;
+        for b in &batches {
+            if current_source != Some(b.source) {
+                current_source = Some(b.source);
+                render_pass.set_pipeline(match b.source {
+                    Source::Model => &self.render_pipeline,
+                    Source::Character => &self.skinned_render_pipeline,
+                });
+            }
+
+            match b.source {
+                Source::Model => {
+                    let model = &models[model_nodes[b.node_idx].model_idx];
+                    let mesh = &model.meshes[TexturedMeshIndex::new(b.mesh_idx)];
+                    render_pass.set_vertex_buffer(
+                        1,
+                        self.instance_buffer
+                            .slice_range(b.start as usize, b.count as usize),
+                    );
+                    render_pass.draw_mesh_instanced(
+                        mesh,
+                        &model.materials[mesh.material],
+                        0..b.count,
+                        camera_bind_group,
+                        light_bind_group,
+                    );
+                }
+                Source::Character => {
+                    let node = &characters_contexts[b.node_idx].skinned_model_node;
+                    let model = &skinned_models[node.skinned_model_idx];
+                    let mesh = &model.meshes[SkinnedMeshIndex::new(b.mesh_idx)];
+                    render_pass.set_vertex_buffer(
+                        1,
+                        self.skinned_instance_buffer
+                            .slice_range(b.start as usize, b.count as usize),
+                    );
+                    render_pass.draw_skinned_mesh_instanced(
+                        mesh,
+                        &model.materials[mesh.material],
+                        0..b.count,
+                        camera_bind_group,
+                        light_bind_group,
+                        &node.bind_group,
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl TransparentRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        texture_bind_group_layout: &wgpu::BindGroupLayout,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        light_bind_group_layout: &wgpu::BindGroupLayout,
+        bone_matrices_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let options = RenderPipelineOptions {
+            sample_count,
+            ..RenderPipelineOptions::transparent()
+        };
+
+        let render_pipeline = {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Transparent Pipeline Layout"),
+                bind_group_layouts: &[
+                    Some(texture_bind_group_layout),
+                    Some(camera_bind_group_layout),
+                    Some(light_bind_group_layout),
+                ],
+                immediate_size: 0,
+            });
+            let shader = wgpu::ShaderModuleDescriptor {
+                label: Some("Transparent Shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    concat!(
+                        include_str!("clustered_lighting.wgsl"),
+                        include_str!("environment.wgsl"),
+                        include_str!("shader.wgsl"),
+                    )
+                    .into(),
+                ),
+            };
+            create_render_pipeline_with_options(
+                device,
+                &layout,
+                color_format,
+                Some(Texture::DEPTH_FORMAT),
+                &[Some(ModelVertex::desc()), Some(InstanceRaw::desc())],
+                shader,
+                &options,
+            )
+        };
+
+        let skinned_render_pipeline = {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Transparent Skinned Pipeline Layout"),
+                bind_group_layouts: &[
+                    Some(texture_bind_group_layout),
+                    Some(camera_bind_group_layout),
+                    Some(light_bind_group_layout),
+                    Some(bone_matrices_bind_group_layout),
+                ],
+                immediate_size: 0,
+            });
+            let shader = wgpu::ShaderModuleDescriptor {
+                label: Some("Transparent Skinned Shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    concat!(
+                        include_str!("clustered_lighting.wgsl"),
+                        include_str!("environment.wgsl"),
+                        include_str!("skinned.wgsl"),
+                    )
+                    .into(),
+                ),
+            };
+            create_render_pipeline_with_options(
+                device,
+                &layout,
+                color_format,
+                Some(Texture::DEPTH_FORMAT),
+                &[Some(SkinnedModelVertex::desc()), Some(SkinnedInstanceRaw::desc())],
+                shader,
+                &options,
+            )
+        };
+
+        Self {
+            render_pipeline,
+            skinned_render_pipeline,
+            instance_buffer: InstanceBuffer::new(
+                device,
+                "Transparent Instance Buffer",
+                std::mem::size_of::<InstanceRaw>(),
+                64,
+            ),
+            skinned_instance_buffer: InstanceBuffer::new(
+                device,
+                "Transparent Skinned Instance Buffer",
+                std::mem::size_of::<SkinnedInstanceRaw>(),
+                64,
+            ),
+            frustum: Frustum::default(),
+            culling_enabled: true,
+            color_target: HDR.to_owned(),
+            depth_target: DEPTH.to_owned(),
+        }
+    }
+}
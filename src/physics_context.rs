@@ -1,16 +1,28 @@
-use rapier3d::pipeline::PhysicsWorld;
-use salva3d::LiquidWorld;
+use crate::physics_debug::PhysicsDebugRenderer;
+use rapier3d::{geometry::ColliderHandle, pipeline::PhysicsWorld};
+use salva3d::{
+    LiquidWorld,
+    integrations::rapier::{ColliderCouplingSet, ColliderSampling},
+    object::{Boundary, Fluid, FluidHandle},
+    solver::Akinci2013SurfaceTension,
+};
 
 static LIQUIDS_TIMESTEP: f32 = 1.0 / 200.0;
 static NO_LIQUIDS_TIMESTEP: f32 = 1.0 / 60.0;
 pub struct PhysicsContext {
     pub rigid_world: PhysicsWorld,
     pub liquid_world: Option<LiquidWorld>,
+    // The colliders the liquids can't pass through and push on. See couple_collider.
+    pub liquid_coupling: ColliderCouplingSet,
+    // From the properties the liquids were enabled with, for add_liquid.
+    boundary_force_coefficient: f32,
+    pub debug_renderer: PhysicsDebugRenderer,
 }
 
 pub struct LiquidWorldProperties {
     pub particle_radius: f32,
     pub smoothing_factor: f32,
+    // How strongly the liquids added with add_liquid stick to the colliders they are coupled with.
     pub boundary_force_coefficient: f32,
 }
 
@@ -26,6 +38,82 @@ impl PhysicsContext {
         Self {
             rigid_world,
             liquid_world: None,
+            liquid_coupling: ColliderCouplingSet::new(),
+            boundary_force_coefficient: 0.0,
+            debug_renderer: PhysicsDebugRenderer::new(),
         }
     }
+
+    // Creates the liquid world and shortens the rigid-body timestep to match it.
+    pub fn enable_liquids(&mut self, properties: &LiquidWorldProperties) {
+        self.liquid_world = Some(LiquidWorld::new(
+            properties.particle_radius,
+            properties.smoothing_factor,
+        ));
+        self.liquid_coupling = ColliderCouplingSet::new();
+        self.boundary_force_coefficient = properties.boundary_force_coefficient;
+        self.rigid_world.integration_parameters.dt = LIQUIDS_TIMESTEP;
+    }
+
+    // Adds a body of liquid with a particle at each position. Returns None when liquids aren't enabled.
+    pub fn add_liquid(&mut self, positions: &[glam::Vec3], density: f32) -> Option<FluidHandle> {
+        let liquid_world = self.liquid_world.as_mut()?;
+        let positions = positions
+            .iter()
+            .map(|p| salva3d::math::Point::new(p.x, p.y, p.z))
+            .collect();
+        let mut fluid = Fluid::new(positions, liquid_world.particle_radius(), density);
+        fluid
+            .nonpressure_forces
+            .push(Box::new(Akinci2013SurfaceTension::new(0.0, self.boundary_force_coefficient)));
+        Some(liquid_world.add_fluid(fluid))
+    }
+
+    // Liquids fall through everything until they are coupled with colliders: the floor, containers, terrain.
+    // Coupled colliders keep the liquids out, and those with dynamic bodies are pushed by them in turn.
+    // Returns false when liquids aren't enabled.
+    pub fn couple_collider(&mut self, collider: ColliderHandle) -> bool {
+        let liquid_world = match &mut self.liquid_world {
+            Some(val) => val,
+            None => return false,
+        };
+        let boundary = liquid_world.add_boundary(Boundary::new(Vec::new()));
+        self.liquid_coupling
+            .register_coupling(boundary, collider, ColliderSampling::DynamicContactSampling);
+        true
+    }
+
+    // Call before removing a coupled collider from the rigid world.
+    pub fn uncouple_collider(&mut self, collider: ColliderHandle) {
+        if let Some(boundary) = self.liquid_coupling.unregister_coupling(collider) {
+            if let Some(liquid_world) = &mut self.liquid_world {
+                liquid_world.remove_boundary(boundary);
+            }
+        }
+    }
+
+    // Steps the liquids against the coupled colliders, and applies their push to the colliders' bodies.
+    pub fn step_liquids(&mut self) {
+        if let Some(liquid_world) = &mut self.liquid_world {
+            let world = &mut self.rigid_world;
+            let g = world.gravity;
+            let gravity = salva3d::math::Vector::new(g.x, g.y, g.z);
+            let dt = world.integration_parameters.dt;
+            let mut coupling = self
+                .liquid_coupling
+                .as_manager_mut(&world.colliders, &mut world.bodies);
+            liquid_world.step_with_coupling(dt, &gravity, &mut coupling);
+        }
+    }
+
+    // Positions of every particle of every fluid.
+    pub fn liquid_particle_positions(&self) -> Vec<glam::Vec3> {
+        let mut results = Vec::<glam::Vec3>::new();
+        if let Some(liquid_world) = &self.liquid_world {
+            for (_, fluid) in liquid_world.fluids().iter() {
+                results.extend(fluid.positions.iter().map(|p| glam::Vec3::new(p.x, p.y, p.z)));
+            }
+        }
+        results
+    }
 }
\ No newline at end of file
@@ -0,0 +1,91 @@
+// Collider, contact and joint wireframes for the rigid-body world, fed into the DebugDraw.
+// Rapier's debug render pipeline walks the world and picks the colors (by body type, dimmed when sleeping).
+
+use crate::debug_draw::*;
+use rapier3d::math::Vector;
+use rapier3d::pipeline::{
+    DebugColor, DebugRenderBackend, DebugRenderMode, DebugRenderObject, DebugRenderPipeline,
+    DebugRenderStyle, PhysicsWorld,
+};
+
+pub struct PhysicsDebugRenderer {
+    pub enabled: bool,
+    // When false the wireframes are drawn over the scene, so colliders inside of meshes stay visible.
+    pub depth_test: bool,
+    pub pipeline: DebugRenderPipeline,
+}
+
+impl PhysicsDebugRenderer {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            depth_test: false,
+            pipeline: DebugRenderPipeline::new(
+                DebugRenderStyle::default(),
+                DebugRenderMode::COLLIDER_SHAPES | DebugRenderMode::JOINTS | DebugRenderMode::CONTACTS,
+            ),
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+    }
+
+    // Queues a single frame's worth of lines. Call once per update.
+    pub fn draw(&mut self, world: &PhysicsWorld, debug_draw: &mut DebugDraw) {
+        if !self.enabled {
+            return;
+        }
+
+        let mut backend = DebugDrawBackend {
+            debug_draw,
+            options: DebugDrawOptions {
+                depth_test: self.depth_test,
+                ..Default::default()
+            },
+        };
+
+        self.pipeline.render(
+            &mut backend,
+            &world.bodies,
+            &world.colliders,
+            &world.impulse_joints,
+            &world.multibody_joints,
+            &world.narrow_phase,
+        );
+    }
+}
+
+struct DebugDrawBackend<'a> {
+    debug_draw: &'a mut DebugDraw,
+    options: DebugDrawOptions,
+}
+
+impl DebugRenderBackend for DebugDrawBackend<'_> {
+    fn draw_line(&mut self, _object: DebugRenderObject, a: Vector, b: Vector, color: DebugColor) {
+        self.debug_draw.line(
+            glam::Vec3::new(a.x, a.y, a.z),
+            glam::Vec3::new(b.x, b.y, b.z),
+            hsla_to_rgba(color),
+            self.options,
+        );
+    }
+}
+
+// Rapier's debug colors are hue (in degrees), saturation, lightness and alpha.
+pub fn hsla_to_rgba(color: DebugColor) -> [f32; 4] {
+    let [h, s, l, a] = color;
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let h = h.rem_euclid(360.0) / 60.0;
+    let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = l - c * 0.5;
+    [r + m, g + m, b + m, a]
+}
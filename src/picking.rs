@@ -0,0 +1,226 @@
+// Ray casts against the scene's instances. Bounding spheres reject most of them, the triangles of the meshes
+// decide the exact hit. Characters are tested in their current pose by skinning the candidates on the CPU.
+
+use crate::culling::*;
+use crate::instance::Instance;
+use crate::model::Model;
+use crate::model_node::ModelNode;
+use crate::skinned_model::*;
+use crate::skinned_model_node::SkinnedModelNode;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Ray {
+    pub origin: glam::Vec3,
+    // Normalized, so hit distances are in world units.
+    pub direction: glam::Vec3,
+}
+
+impl Ray {
+    pub fn new(origin: glam::Vec3, direction: glam::Vec3) -> Self {
+        Self {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    pub fn at(&self, distance: f32) -> glam::Vec3 {
+        self.origin + self.direction * distance
+    }
+
+    // Distance to where the ray enters the sphere, or zero if it starts inside of it.
+    pub fn intersect_sphere(&self, sphere: &BoundingSphere) -> Option<f32> {
+        let offset = self.origin - sphere.center;
+        let b = offset.dot(self.direction);
+        let c = offset.length_squared() - sphere.radius * sphere.radius;
+        if c > 0.0 && b > 0.0 {
+            return None;
+        }
+        let discriminant = b * b - c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        Some((-b - discriminant.sqrt()).max(0.0))
+    }
+}
+
+// Möller–Trumbore, hitting both sides. direction doesn't need to be normalized, the result is in units of it.
+pub fn intersect_triangle(
+    origin: glam::Vec3,
+    direction: glam::Vec3,
+    a: glam::Vec3,
+    b: glam::Vec3,
+    c: glam::Vec3,
+) -> Option<f32> {
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let p = direction.cross(edge2);
+    let determinant = edge1.dot(p);
+    if determinant.abs() < f32::EPSILON {
+        return None;
+    }
+    let inverse_determinant = 1.0 / determinant;
+    let t_vec = origin - a;
+    let u = t_vec.dot(p) * inverse_determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = t_vec.cross(edge1);
+    let v = direction.dot(q) * inverse_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = edge2.dot(q) * inverse_determinant;
+    if t > 0.0 { Some(t) } else { None }
+}
+
+// Closest hit against an indexed triangle list whose vertices are already in the ray's space.
+fn intersect_triangles(
+    origin: glam::Vec3,
+    direction: glam::Vec3,
+    positions: &[glam::Vec3],
+    indices: &[u32],
+) -> Option<f32> {
+    let mut results: Option<f32> = None;
+    for triangle in indices.chunks_exact(3) {
+        let hit = intersect_triangle(
+            origin,
+            direction,
+            positions[triangle[0] as usize],
+            positions[triangle[1] as usize],
+            positions[triangle[2] as usize],
+        );
+        if let Some(t) = hit {
+            if results.is_none_or(|best| t < best) {
+                results = Some(t);
+            }
+        }
+    }
+    results
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PickTarget {
+    ModelInstance { node_idx: usize, instance_idx: usize },
+    Character { context_idx: usize, character_idx: usize },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PickHit {
+    pub target: PickTarget,
+    pub distance: f32,
+    pub position: glam::Vec3,
+}
+
+fn instance_matrix(instance: &Instance) -> glam::Mat4 {
+    glam::Mat4::from_scale_rotation_translation(
+        instance.scale.into(),
+        instance.orientation,
+        instance.position.into(),
+    )
+}
+
+// Closest hit among the instances of a model node, if closer than max_distance.
+pub fn pick_model_node(
+    ray: &Ray,
+    node: &ModelNode,
+    model: &Model,
+    max_distance: f32,
+) -> Option<(usize, f32)> {
+    let bounds = model.bounding_sphere();
+    let mut results: Option<(usize, f32)> = None;
+
+    for (instance_idx, instance) in node.instances.iter().enumerate() {
+        if !node.is_visible_at(instance_idx) {
+            continue;
+        }
+        let best = results.map_or(max_distance, |(_, d)| d);
+        match ray.intersect_sphere(&bounds.transformed_by_instance(instance)) {
+            Some(d) if d < best => {}
+            _ => continue,
+        }
+
+        let instance_mat = instance_matrix(instance);
+        for mesh in &model.meshes {
+            let mesh_mat = glam::Mat4::from_scale_rotation_translation(
+                mesh.scale,
+                mesh.rotation,
+                mesh.translation,
+            );
+            // Test in the mesh's space instead of transforming every vertex. The direction isn't renormalized,
+            // so distances stay in world units.
+            let inverse = (instance_mat * mesh_mat).inverse();
+            let origin = inverse.transform_point3(ray.origin);
+            let direction = inverse.transform_vector3(ray.direction);
+
+            if let Some(d) = intersect_triangles(origin, direction, &mesh.positions, &mesh.indices) {
+                if d < results.map_or(max_distance, |(_, best)| best) {
+                    results = Some((instance_idx, d));
+                }
+            }
+        }
+    }
+
+    results
+}
+
+// Closest hit among the characters of a skinned model node, in their pose from the last update.
+pub fn pick_skinned_model_node(
+    ray: &Ray,
+    node: &SkinnedModelNode,
+    model: &SkinnedModel,
+    max_distance: f32,
+) -> Option<(usize, f32)> {
+    let bounds = model.bounding_sphere();
+    let num_bones = node.num_bones as usize;
+    let mut results: Option<(usize, f32)> = None;
+
+    for (instance_idx, instance) in node.instances.iter().enumerate() {
+        let best = results.map_or(max_distance, |(_, d)| d);
+        match ray.intersect_sphere(&bounds.transformed_by_instance(instance)) {
+            Some(d) if d < best => {}
+            _ => continue,
+        }
+
+        let bones = match node
+            .bone_matrices
+            .get(instance_idx * num_bones..(instance_idx + 1) * num_bones)
+        {
+            Some(val) => val,
+            None => continue,
+        };
+
+        let instance_mat = instance_matrix(instance);
+        for mesh in &model.meshes {
+            let mesh_mat = glam::Mat4::from_scale_rotation_translation(
+                mesh.scale,
+                mesh.rotation,
+                mesh.translation,
+            );
+            let world_mat = instance_mat * mesh_mat;
+
+            // Same skinning as skinned.wgsl
+            let positions: Vec<glam::Vec3> = mesh
+                .positions
+                .iter()
+                .zip(mesh.bone_indices.iter().zip(mesh.bone_weights.iter()))
+                .map(|(p, (indices, weights))| {
+                    let mut bone_mat = glam::Mat4::ZERO;
+                    for k in 0..4 {
+                        if let Some(b) = bones.get(indices[k] as usize) {
+                            bone_mat += *b * weights[k];
+                        }
+                    }
+                    (world_mat * bone_mat).transform_point3(*p)
+                })
+                .collect();
+
+            if let Some(d) = intersect_triangles(ray.origin, ray.direction, &positions, &mesh.indices) {
+                if d < results.map_or(max_distance, |(_, best)| best) {
+                    results = Some((instance_idx, d));
+                }
+            }
+        }
+    }
+
+    results
+}
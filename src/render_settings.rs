@@ -0,0 +1,66 @@
+// Runtime rendering options. Held by the GraphicsContext and read by the render graph every frame,
+// so they can be changed from the update or GUI callbacks.
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Tonemapper {
+    Aces,
+    AgX,
+}
+
+// Cheaper alternatives to MSAA, applied to the tonemapped image.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PostAntiAliasing {
+    None,
+    Fxaa,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RenderSettings {
+    // 1 (off), 2, 4 or 8. Counts the adapter doesn't support fall back to the next lower one.
+    pub msaa_samples: u32,
+    pub post_anti_aliasing: PostAntiAliasing,
+    // Linear multiplier applied to the HDR scene before tonemapping.
+    pub exposure: f32,
+    pub tonemapper: Tonemapper,
+    pub bloom_enabled: bool,
+    // How much of the bloom chain is added back onto the scene.
+    pub bloom_intensity: f32,
+    // Brightness above which pixels start to bloom, with a soft knee so the cutoff isn't visible.
+    pub bloom_threshold: f32,
+    pub bloom_knee: f32,
+    // Drawn around highlighted instances. Linear, with alpha blending over the HDR scene.
+    pub outline_color: glam::Vec4,
+    // In pixels, up to 8.
+    pub outline_width: f32,
+    // Assigns point lights to clusters with a compute pass. Off uses the CPU reference instead, which is slower.
+    pub gpu_light_culling: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            msaa_samples: 1,
+            post_anti_aliasing: PostAntiAliasing::None,
+            exposure: 1.0,
+            tonemapper: Tonemapper::Aces,
+            bloom_enabled: true,
+            bloom_intensity: 0.04,
+            bloom_threshold: 1.0,
+            bloom_knee: 0.5,
+            outline_color: glam::Vec4::new(1.0, 0.55, 0.1, 1.0),
+            outline_width: 2.0,
+            gpu_light_culling: true,
+        }
+    }
+}
+
+impl RenderSettings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Whether going from `other` to these settings changes the passes or attachments, so the render graph has to be rebuilt.
+    pub fn requires_rebuild(&self, other: &RenderSettings) -> bool {
+        self.msaa_samples != other.msaa_samples || self.post_anti_aliasing != other.post_anti_aliasing
+    }
+}
@@ -0,0 +1,145 @@
+// Render to texture, for in-world screens, mirrors and minimaps. The window draws a scene through one of its cameras
+// into the target, through a render graph of its own, and the texture can be given to a Material as its diffuse.
+// The graph draws into a separate texture that gets copied into the sampled one, so a target can be seen by
+// its own camera (a mirror seeing itself in another mirror) without being drawn to and sampled in the same pass.
+
+use crate::texture::*;
+use std::sync::Arc;
+
+pub struct RenderTarget {
+    pub label: String,
+    // Sampled by materials.
+    pub texture: Texture,
+    // Drawn into by the render graph, then copied into texture.
+    pub render_texture: wgpu::Texture,
+    pub render_view: wgpu::TextureView,
+    pub width: u32,
+    pub height: u32,
+    pub scene_idx: usize,
+    // Into the scene's cameras. Drawn at the target's aspect ratio, whatever the camera's own.
+    pub camera_idx: usize,
+    // Drawn every nth frame. 1 is every frame, 0 only when requested.
+    pub update_interval: u32,
+    pub enabled: bool,
+    frames_since_update: u32,
+    update_requested: bool,
+}
+
+impl RenderTarget {
+    // The format should be the surface's, as the graph's final pass is built for it.
+    pub fn new(
+        device: &wgpu::Device,
+        label: &str,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        scene_idx: usize,
+        camera_idx: usize,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::MipmapFilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let render_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(&format!("{} Render", label)),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let render_view = render_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            label: label.to_owned(),
+            texture: Texture {
+                texture,
+                view,
+                sampler,
+                source: Some(Arc::new(TextureSource::RenderTarget)),
+            },
+            render_texture,
+            render_view,
+            width: size.width,
+            height: size.height,
+            scene_idx,
+            camera_idx,
+            update_interval: 1,
+            enabled: true,
+            frames_since_update: 0,
+            // The first frame fills it, so it doesn't show garbage before its first scheduled update.
+            update_requested: true,
+        }
+    }
+
+    // New textures on a new device, after the old one was lost, in the new surface's format as the graphs are rebuilt
+    // for it. The new texture is added to `recreated`, for the materials showing the old one to find it when they're
+    // recreated.
+    pub fn recreate_gpu_resources(
+        &mut self,
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        recreated: &mut RecreatedTextures,
+    ) {
+        let target = Self::new(
+            device,
+            &self.label,
+            self.width,
+            self.height,
+            format,
+            self.scene_idx,
+            self.camera_idx,
+        );
+        if let Some(source) = &self.texture.source {
+            recreated.insert(Arc::as_ptr(source) as usize, target.texture.clone());
+        }
+        self.texture = target.texture;
+        self.render_texture = target.render_texture;
+        self.render_view = target.render_view;
+        self.update_requested = true;
+    }
+
+    // Draws it on the next frame, whatever the interval.
+    pub fn request_update(&mut self) {
+        self.update_requested = true;
+    }
+
+    // Called by the window once per frame. Whether the target is drawn this frame.
+    pub fn advance(&mut self) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        self.frames_since_update = self.frames_since_update.saturating_add(1);
+        let due = self.update_requested
+            || (self.update_interval > 0 && self.frames_since_update >= self.update_interval);
+        if due {
+            self.frames_since_update = 0;
+            self.update_requested = false;
+        }
+        due
+    }
+}
@@ -6,31 +6,27 @@ use crate::skeletal_context::SkeletalContext;
 use crate::skinned_model::*;
 use crate::texture;
 use crate::texture::Texture;
-use msgpacker::*;
 use std::path::*;
-use wgpu::util::DeviceExt;
 
-pub fn load_serialized_model(filepath: &std::path::Path) -> SerializedModel {
+pub fn load_serialized_model(filepath: &std::path::Path) -> anyhow::Result<SerializedModel> {
     let mut path = PathBuf::new();
     for p in filepath {
         path.push(p);
     }
     println!("Full path {:?}", path.as_path());
-    let data = std::fs::read(path.as_path()).unwrap();
-    let deserialized = SerializedModel::unpack(&data).unwrap();
-    println!("Dimensions {:?}", deserialized.meshes[0].dimensions);
-    println!("Scale {:?}", deserialized.meshes[0].scale);
-    println!("Translation {:?}", deserialized.meshes[0].translation);
-    println!("Rotation {:?}", deserialized.meshes[0].rotation);
-    println!(
-        "Diffuse texture {}",
-        deserialized.materials[0].diffuse_texture_path
-    );
-    println!(
-        "Normals texture {}",
-        deserialized.materials[0].normals_texture_path
-    );
-    deserialized
+    let data = std::fs::read(path.as_path())?;
+    let deserialized = SerializedModel::from_bytes(&data)?;
+    if let Some(mesh) = deserialized.meshes.first() {
+        println!("Dimensions {:?}", mesh.dimensions);
+        println!("Scale {:?}", mesh.scale);
+        println!("Translation {:?}", mesh.translation);
+        println!("Rotation {:?}", mesh.rotation);
+    }
+    if let Some(material) = deserialized.materials.first() {
+        println!("Diffuse texture {}", material.diffuse_texture_path);
+        println!("Normals texture {}", material.normals_texture_path);
+    }
+    Ok(deserialized)
 }
 
 // TODO: Robustify
@@ -44,6 +40,25 @@ pub fn load_skinned_model_from_serialized(
     skeletal_context: &SkeletalContext,
 ) -> Option<SkinnedModel> {
     let mut model_results = SkinnedModel::new();
+
+    // Targets with the same name in different meshes (e.g. face and teeth) are driven by the same weight.
+    for m in model.meshes.iter() {
+        for name in m.morph_target_names.iter() {
+            if !model_results.morph_target_names.contains(name) {
+                model_results.morph_target_names.push(name.clone());
+            }
+        }
+    }
+    if model_results.morph_target_names.len() > MAX_MORPH_TARGETS {
+        println!(
+            "Model has {} morph targets, only the first {} are used",
+            model_results.morph_target_names.len(),
+            MAX_MORPH_TARGETS
+        );
+        model_results.morph_target_names.truncate(MAX_MORPH_TARGETS);
+    }
+    let mut morph_deltas = Vec::<MorphDelta>::new();
+
     for m in model.meshes.iter_mut() {
         println!("Mesh {}", m.name);
         let mut verts = Vec::<ModelVertex>::new();
@@ -101,17 +116,31 @@ pub fn load_skinned_model_from_serialized(
             skinned_verts.push(sv);
         }
 
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some(&format!("{:?} Skinned Vertex Buffer", m.name)),
-            contents: bytemuck::cast_slice(&skinned_verts),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
+        let (vertex_buffer, index_buffer) =
+            create_mesh_buffers(device, &format!("{:?} Skinned", m.name), &skinned_verts, &indices);
 
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some(&format!("{:?} Skinned Index Buffer", m.name)),
-            contents: bytemuck::cast_slice(&indices),
-            usage: wgpu::BufferUsages::INDEX,
-        });
+        // Laid out by the model's targets, with zeros for the ones this mesh doesn't have.
+        let morph_offset = morph_deltas.len() as u32;
+        let mut num_morph_targets = 0;
+        if !m.morph_target_names.is_empty() {
+            num_morph_targets = model_results.morph_target_names.len() as u32;
+            for name in model_results.morph_target_names.iter() {
+                let target = m.morph_target_names.iter().position(|n| n == name);
+                for v in 0..m.positions.len() {
+                    let delta = |deltas: &Vec<Vec<[f32; 3]>>| {
+                        target
+                            .and_then(|t| deltas.get(t))
+                            .and_then(|d| d.get(v))
+                            .map(|d| [d[0], d[1], d[2], 0.0])
+                            .unwrap_or([0.0; 4])
+                    };
+                    morph_deltas.push(MorphDelta {
+                        position: delta(&m.morph_position_deltas),
+                        normal: delta(&m.morph_normal_deltas),
+                    });
+                }
+            }
+        }
 
         model_results.meshes.push(SkinnedTexturedMesh {
             name: m.name.clone(),
@@ -123,9 +152,19 @@ pub fn load_skinned_model_from_serialized(
             rotation: glam::Quat::from_array(m.rotation),
             scale: glam::Vec3::from_array(m.scale),
             dimensions: glam::Vec3::from_array(m.dimensions),
+            positions: m.positions.iter().map(|p| glam::Vec3::from_array(*p)).collect(),
+            indices,
+            bone_indices: skinned_verts.iter().map(|v| v.bone_indices).collect(),
+            bone_weights: skinned_verts.iter().map(|v| v.bone_weights).collect(),
+            vertices: skinned_verts,
+            morph_offset,
+            num_morph_targets,
         });
     }
 
+    model_results.morph_deltas_buffer = create_morph_deltas_buffer(device, &morph_deltas);
+    model_results.morph_deltas = morph_deltas;
+
     if model.materials.len() == 0 {
         model_results.materials.push(default_material.clone());
     }
@@ -253,16 +292,8 @@ pub fn load_model_from_serialized(
         calculate_tangents_and_bitangents(&mut verts, &indices);
 
         // println!("Full path: {}", full_path);
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some(&format!("{:?} Vertex Buffer", m.name)),
-            contents: bytemuck::cast_slice(&verts),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some(&format!("{:?} Index Buffer", m.name)),
-            contents: bytemuck::cast_slice(&indices),
-            usage: wgpu::BufferUsages::INDEX,
-        });
+        let (vertex_buffer, index_buffer) =
+            create_mesh_buffers(device, &format!("{:?}", m.name), &verts, &indices);
 
         model_results.meshes.push(TexturedMesh {
             name: m.name.clone(),
@@ -274,6 +305,9 @@ pub fn load_model_from_serialized(
             rotation: glam::Quat::from_array(m.rotation),
             scale: glam::Vec3::from_array(m.scale),
             dimensions: glam::Vec3::from_array(m.dimensions),
+            positions: m.positions.iter().map(|p| glam::Vec3::from_array(*p)).collect(),
+            indices,
+            vertices: verts,
         });
     }
 
@@ -1,12 +1,16 @@
 // This is where our game world resides.
 
+use anyhow::Context;
 use std::{collections::HashMap, rc::Rc};
 
 use simple_animgraph::{animgraph::AnimGraph, animgraph_definition::AnimGraphDefinition};
 
 use crate::{
     camera::Camera, instance::Instance, model_node::ModelNode, character::Character, 
-    physics_context::PhysicsContext, skinned_model_node::SkinnedModelNode, skinned_model::SkinnedModel
+    physics_context::PhysicsContext, skinned_model_node::SkinnedModelNode, skinned_model::{SkinnedModel, MAX_MORPH_TARGETS},
+    model::Model, particle_system::ParticleEmitter, picking::*, fluid_surface::FluidSurface,
+    terrain::*, crowd::Crowd, baked_animation::BakedAnimationData,
+    environment::Environment, texture::RecreatedTextures,
 };
 
 pub struct CharactersContext {
@@ -21,6 +25,13 @@ pub struct Scene {
     pub physics_context: PhysicsContext,
     pub characters_contexts: Vec<CharactersContext>,
     pub character_types_by_name: HashMap<String, usize>,
+    pub particle_emitters: Vec<ParticleEmitter>,
+    // Set it to see the liquids of the physics context.
+    pub fluid_surface: Option<FluidSurface>,
+    pub terrains: Vec<Terrain>,
+    pub crowds: Vec<Crowd>,
+    // Fog and sky.
+    pub environment: Environment,
 }
 
 impl Scene {
@@ -32,9 +43,49 @@ impl Scene {
             physics_context: PhysicsContext::new(gravity, false),
             characters_contexts: Vec::new(),
             character_types_by_name: HashMap::new(),
+            particle_emitters: Vec::new(),
+            fluid_surface: None,
+            terrains: Vec::new(),
+            crowds: Vec::new(),
+            environment: Environment::default(),
         }
     }
 
+    // Recreates what the scene keeps on the GPU, on a new device after the old one was lost. Instance buffers are
+    // recreated by the renderers. Particle and terrain textures are made again from what they were loaded from.
+    pub fn recreate_gpu_resources(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bone_matrices_bind_group_layout: &wgpu::BindGroupLayout,
+        recreated: &mut RecreatedTextures,
+    ) -> anyhow::Result<()> {
+        for node in self.model_nodes.iter_mut() {
+            node.instance_buffers.clear();
+            node.mark_dirty();
+        }
+        for characters_ctx in self.characters_contexts.iter_mut() {
+            characters_ctx
+                .skinned_model_node
+                .recreate_gpu_resources(device, bone_matrices_bind_group_layout);
+        }
+        for (idx, emitter) in self.particle_emitters.iter_mut().enumerate() {
+            if let Some(texture) = &emitter.texture {
+                let texture = texture
+                    .recreate(device, queue, recreated)
+                    .with_context(|| format!("Could not recreate the texture of particle emitter {}", idx))?;
+                emitter.set_texture(Some(texture));
+            }
+        }
+        for terrain in self.terrains.iter_mut() {
+            terrain.recreate_gpu_resources(device, queue, recreated)?;
+        }
+        for crowd in self.crowds.iter_mut() {
+            crowd.recreate_gpu_resources();
+        }
+        Ok(())
+    }
+
     // pub fn load_physics(&mut self, data: &Vec<u8>) {
     //     self.physics_context = wincode::decode_from_slice(data)
     //         .unwrap()
@@ -49,14 +100,74 @@ impl Scene {
     // }
 
     pub fn step_physics(&mut self) {
-        self.physics_context.rigid_world.step()
+        self.physics_context.rigid_world.step();
+        self.physics_context.step_liquids();
+    }
+
+    // Rebuilds the liquids' surface from where their particles are now. Called by the window once per update.
+    pub fn update_fluid_surface(&mut self) {
+        if let Some(surface) = &mut self.fluid_surface {
+            if surface.visible {
+                surface.reconstruct(&self.physics_context.liquid_particle_positions());
+            }
+        }
+    }
+
+    // Steps every emitter, with the gravity and colliders of the rigid-body world.
+    pub fn update_particles(&mut self, dt: web_time::Duration) {
+        let world = &self.physics_context.rigid_world;
+        for emitter in self.particle_emitters.iter_mut() {
+            emitter.update(dt.as_secs_f32(), Some(world));
+        }
+    }
+
+    // Returns the index of the emitter.
+    pub fn add_particle_emitter(&mut self, emitter: ParticleEmitter) -> usize {
+        self.particle_emitters.push(emitter);
+        self.particle_emitters.len() - 1
+    }
+
+    // Builds the terrain's chunks and its heightfield collider. Returns the index of the terrain.
+    pub fn add_terrain(
+        &mut self,
+        device: &wgpu::Device,
+        heightmap: Heightmap,
+        material: TerrainMaterial,
+        settings: TerrainSettings,
+    ) -> usize {
+        let mut terrain = Terrain::new(device, heightmap, material, settings);
+        terrain.create_collider(&mut self.physics_context);
+        self.terrains.push(terrain);
+        self.terrains.len() - 1
+    }
+
+    // Returns the index of the crowd. Add its members with Crowd::add_member.
+    pub fn add_crowd(&mut self, skinned_model_idx: usize, animation: Rc<BakedAnimationData>) -> usize {
+        self.crowds.push(Crowd::new(skinned_model_idx, animation));
+        self.crowds.len() - 1
+    }
+
+    // Moves every crowd member along its clip. Called by the window once per update, for the active scene.
+    pub fn update_crowds(&mut self, dt: web_time::Duration) {
+        for crowd in self.crowds.iter_mut() {
+            crowd.advance(dt);
+        }
     }
 
-    pub fn update_characters(&mut self, dt: web_time::Duration, skinned_models: &Vec<SkinnedModel>, queue: &wgpu::Queue) {
+    pub fn update_characters(
+        &mut self,
+        dt: web_time::Duration,
+        skinned_models: &Vec<SkinnedModel>,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bone_matrices_bind_group_layout: &wgpu::BindGroupLayout,
+    ) {
         for characters_ctx in self.characters_contexts.iter_mut() {
             
             characters_ctx.skinned_model_node.instances.clear();
             characters_ctx.skinned_model_node.bone_matrices.clear();
+            characters_ctx.skinned_model_node.morph_weights.clear();
+            characters_ctx.skinned_model_node.dirty = true;
             
             for c in &mut characters_ctx.characters {
                 let r = c.anim_graph.evaluate(dt);
@@ -68,9 +179,16 @@ impl Scene {
                     position: c.position.into(),
                     orientation: c.orientation,
                     scale: glam::Vec3A::splat(1.0),
+                    tint: c.tint,
+                    emissive: c.emissive,
+                    user_data: c.user_data,
                 };
                 
                 characters_ctx.skinned_model_node.instances.push(instance);
+
+                let weights = &c.morph_weights[..c.morph_weights.len().min(MAX_MORPH_TARGETS)];
+                characters_ctx.skinned_model_node.morph_weights.extend_from_slice(weights);
+                characters_ctx.skinned_model_node.morph_weights.resize((characters_ctx.skinned_model_node.instances.len()) * MAX_MORPH_TARGETS, 0.0);
                 
                 let output = c.anim_graph.get_skeletal_matrices();
                 
@@ -78,12 +196,65 @@ impl Scene {
                     characters_ctx.skinned_model_node.bone_matrices.push(o * skinned_models[characters_ctx.skinned_model_node.skinned_model_idx].inverse_bind_matrices[i]);
                 }
             }
-            
-            let bones_storage_buffer = &characters_ctx.skinned_model_node.bones_storage_buffer;
-            queue.write_buffer(&bones_storage_buffer, 0, bytemuck::cast_slice(&characters_ctx.skinned_model_node.bone_matrices));
+
+            characters_ctx
+                .skinned_model_node
+                .write_buffers(device, queue, bone_matrices_bind_group_layout);
         }
     }
 
+    // Finds the closest model instance or character under the cursor, as seen from the active camera.
+    // cursor and window_size are in physical pixels.
+    pub fn pick(
+        &self,
+        cursor: glam::Vec2,
+        window_size: glam::Vec2,
+        models: &Vec<Model>,
+        skinned_models: &Vec<SkinnedModel>,
+    ) -> Option<PickHit> {
+        let camera = self.cameras.get(self.active_camera)?;
+        let ray = camera.cursor_ray(cursor, window_size);
+        self.pick_ray(&ray, models, skinned_models)
+    }
+
+    pub fn pick_ray(&self, ray: &Ray, models: &Vec<Model>, skinned_models: &Vec<SkinnedModel>) -> Option<PickHit> {
+        let mut results: Option<PickHit> = None;
+        let mut max_distance = f32::INFINITY;
+
+        for (node_idx, node) in self.model_nodes.iter().enumerate() {
+            let model = match models.get(node.model_idx) {
+                Some(val) => val,
+                None => continue,
+            };
+            if let Some((instance_idx, distance)) = pick_model_node(ray, node, model, max_distance) {
+                max_distance = distance;
+                results = Some(PickHit {
+                    target: PickTarget::ModelInstance { node_idx, instance_idx },
+                    distance,
+                    position: ray.at(distance),
+                });
+            }
+        }
+
+        for (context_idx, ctx) in self.characters_contexts.iter().enumerate() {
+            let node = &ctx.skinned_model_node;
+            let model = match skinned_models.get(node.skinned_model_idx) {
+                Some(val) => val,
+                None => continue,
+            };
+            if let Some((character_idx, distance)) = pick_skinned_model_node(ray, node, model, max_distance) {
+                max_distance = distance;
+                results = Some(PickHit {
+                    target: PickTarget::Character { context_idx, character_idx },
+                    distance,
+                    position: ray.at(distance),
+                });
+            }
+        }
+
+        results
+    }
+
     pub fn add_characters(
         &mut self,
         device: &mut wgpu::Device,
@@ -116,7 +287,10 @@ impl Scene {
                 animations_by_name,
             );
             match character {
-                Some(val) => {
+                Some(mut val) => {
+                    val.tint = instance.tint;
+                    val.emissive = instance.emissive;
+                    val.user_data = instance.user_data;
                     self.characters_contexts[characters_ctx_idx].characters.push(val);
                 }
                 None => {
@@ -17,6 +17,10 @@ pub struct SerializedMesh {
     pub indices: Vec<u32>,
     pub bone_names: Vec<String>,
     pub material_index: u32,
+    // Blend shapes. For each target, per vertex offsets from the base positions and normals.
+    pub morph_target_names: Vec<String>,
+    pub morph_position_deltas: Vec<Vec<[f32; 3]>>,
+    pub morph_normal_deltas: Vec<Vec<[f32; 3]>>,
 }
 
 impl SerializedMesh {
@@ -37,6 +41,9 @@ impl SerializedMesh {
             indices: Vec::new(),
             bone_names: Vec::new(),
             material_index: 0,
+            morph_target_names: Vec::new(),
+            morph_position_deltas: Vec::new(),
+            morph_normal_deltas: Vec::new(),
         }
     }
 }
@@ -87,6 +94,21 @@ impl SerializedModel {
         }
     }
 
+    // Fields are packed by position without any header, so files from before the morph targets don't unpack as the
+    // current layout. Whichever layout reads the whole of the data is taken.
+    pub fn from_bytes(data: &[u8]) -> anyhow::Result<Self> {
+        if let Ok((len, model)) = Self::unpack(data) {
+            if len == data.len() {
+                return Ok(model);
+            }
+        }
+        match SerializedModelV1::unpack(data) {
+            Ok((len, model)) if len == data.len() => Ok(model.into()),
+            Ok(_) => anyhow::bail!("The model has data left over after it"),
+            Err(err) => anyhow::bail!("The model can't be read: {:?}", err),
+        }
+    }
+
     // pub fn rotate(&mut self, rotation: glam::Quat) {
     //     let matrix = glam::Mat4::from_quat(rotation);
     //     for m in self.meshes.iter_mut() {
@@ -101,3 +123,130 @@ impl SerializedModel {
     //     }
     // }
 }
+
+// The layout before morph targets were added.
+#[derive(Clone, Debug, PartialEq, MsgPacker)]
+pub struct SerializedMeshV1 {
+    pub name: String,
+    pub translation: [f32; 3],
+    pub scale: [f32; 3],
+    pub max_extents: [f32; 3],
+    pub min_extents: [f32; 3],
+    pub dimensions: [f32; 3],
+    pub rotation: [f32; 4],
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
+    pub bone_indices: Vec<[u32; 4]>,
+    pub bone_weights: Vec<[f32; 4]>,
+    pub indices: Vec<u32>,
+    pub bone_names: Vec<String>,
+    pub material_index: u32,
+}
+
+impl From<SerializedMeshV1> for SerializedMesh {
+    fn from(mesh: SerializedMeshV1) -> Self {
+        Self {
+            name: mesh.name,
+            translation: mesh.translation,
+            scale: mesh.scale,
+            max_extents: mesh.max_extents,
+            min_extents: mesh.min_extents,
+            dimensions: mesh.dimensions,
+            rotation: mesh.rotation,
+            positions: mesh.positions,
+            normals: mesh.normals,
+            uvs: mesh.uvs,
+            bone_indices: mesh.bone_indices,
+            bone_weights: mesh.bone_weights,
+            indices: mesh.indices,
+            bone_names: mesh.bone_names,
+            material_index: mesh.material_index,
+            morph_target_names: Vec::new(),
+            morph_position_deltas: Vec::new(),
+            morph_normal_deltas: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, MsgPacker)]
+pub struct SerializedModelV1 {
+    pub meshes: Vec<SerializedMeshV1>,
+    pub materials: Vec<SerializedMaterial>,
+    pub bone_names: Vec<String>,
+    pub inverse_bind_matrices: Vec<[[f32; 4]; 4]>,
+}
+
+impl From<SerializedModelV1> for SerializedModel {
+    fn from(model: SerializedModelV1) -> Self {
+        Self {
+            meshes: model.meshes.into_iter().map(|m| m.into()).collect(),
+            materials: model.materials,
+            bone_names: model.bone_names,
+            inverse_bind_matrices: model.inverse_bind_matrices,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v1_model() -> SerializedModelV1 {
+        SerializedModelV1 {
+            meshes: vec![SerializedMeshV1 {
+                name: "Triangle".to_owned(),
+                translation: [1.0, 2.0, 3.0],
+                scale: [1.0; 3],
+                max_extents: [1.0, 1.0, 0.0],
+                min_extents: [0.0; 3],
+                dimensions: [1.0, 1.0, 0.0],
+                rotation: [0.0, 0.0, 0.0, 1.0],
+                positions: vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+                normals: vec![[0.0, 0.0, 1.0]; 3],
+                uvs: vec![[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]],
+                bone_indices: Vec::new(),
+                bone_weights: Vec::new(),
+                indices: vec![0, 1, 2],
+                bone_names: Vec::new(),
+                material_index: 0,
+            }],
+            materials: vec![SerializedMaterial::new()],
+            bone_names: vec!["Root".to_owned()],
+            inverse_bind_matrices: vec![glam::Mat4::IDENTITY.to_cols_array_2d()],
+        }
+    }
+
+    #[test]
+    fn reads_current_layout() {
+        let mut model: SerializedModel = v1_model().into();
+        model.meshes[0].morph_target_names = vec!["Smile".to_owned()];
+        model.meshes[0].morph_position_deltas = vec![vec![[0.0, 0.1, 0.0]; 3]];
+        model.meshes[0].morph_normal_deltas = vec![vec![[0.0; 3]; 3]];
+        let mut data = Vec::new();
+        model.pack(&mut data);
+        assert_eq!(SerializedModel::from_bytes(&data).unwrap(), model);
+    }
+
+    #[test]
+    fn reads_file_from_before_morph_targets() {
+        let old = v1_model();
+        let mut data = Vec::new();
+        old.pack(&mut data);
+        let path = std::env::temp_dir().join(format!("noobwerkz_v1_model_{}.msgpack", std::process::id()));
+        std::fs::write(&path, &data).unwrap();
+
+        let loaded = crate::resource::load_serialized_model(&path);
+        std::fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+
+        assert_eq!(loaded, SerializedModel::from(old.clone()));
+        assert_eq!(loaded.meshes[0].positions, old.meshes[0].positions);
+        assert!(loaded.meshes[0].morph_target_names.is_empty());
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(SerializedModel::from_bytes(&[0xc1, 0x00, 0x01]).is_err());
+    }
+}
@@ -1,7 +1,13 @@
+use crate::culling::*;
 use crate::index_types::*;
 use crate::material::*;
 use crate::model::*;
+use crate::texture::RecreatedTextures;
 use std::ops::Range;
+use wgpu::util::DeviceExt;
+
+// Weights per character in the node's morph weights buffer, whatever the model's own target count.
+pub const MAX_MORPH_TARGETS: usize = 32;
 
 #[repr(C)]
 pub struct SkinnedModel {
@@ -9,6 +15,12 @@ pub struct SkinnedModel {
     pub materials: Materials<Material>,
     pub name: String,
     pub inverse_bind_matrices: Vec<glam::Mat4>,
+    // The morph targets of all meshes, by name. A character's morph weights are in this order.
+    pub morph_target_names: Vec<String>,
+    // Every mesh's deltas, target after target. None when the model has no morph targets.
+    pub morph_deltas_buffer: Option<wgpu::Buffer>,
+    // What the deltas buffer was filled with.
+    pub morph_deltas: Vec<MorphDelta>,
 }
 
 impl SkinnedModel {
@@ -18,10 +30,68 @@ impl SkinnedModel {
             materials: Materials::new(),
             name: "".to_owned(),
             inverse_bind_matrices: Vec::new(),
+            morph_target_names: Vec::new(),
+            morph_deltas_buffer: None,
+            morph_deltas: Vec::new(),
         }
     }
+
+    pub fn morph_target_index(&self, name: &str) -> Option<usize> {
+        self.morph_target_names.iter().position(|n| n == name)
+    }
+
+    // Animation can move vertices well outside of the bind pose, so the sphere gets some slack.
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        let spheres: Vec<BoundingSphere> = self
+            .meshes
+            .iter()
+            .map(|m| mesh_bounding_sphere(m.translation, m.scale, m.dimensions))
+            .collect();
+        let mut results = BoundingSphere::enclosing(&spheres);
+        results.radius *= SKINNED_BOUNDS_PADDING;
+        results
+    }
+
+    // Makes the buffers and materials again on a new device, from the copies kept on the CPU. The characters drawn
+    // with it bind the new morph deltas once their own buffers are recreated.
+    pub fn recreate_gpu_resources(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture_layout: &wgpu::BindGroupLayout,
+        recreated: &mut RecreatedTextures,
+    ) -> anyhow::Result<()> {
+        for mesh in self.meshes.iter_mut() {
+            if mesh.vertices.is_empty() && mesh.num_elements > 0 {
+                anyhow::bail!("Skinned mesh {} has no vertices kept to recreate it from", mesh.name);
+            }
+            (mesh.vertex_buffer, mesh.index_buffer) =
+                create_mesh_buffers(device, &format!("{:?} Skinned", mesh.name), &mesh.vertices, &mesh.indices);
+        }
+        self.morph_deltas_buffer = create_morph_deltas_buffer(device, &self.morph_deltas);
+        let mut materials = Materials::new();
+        for material in self.materials.iter() {
+            materials.push(material.recreate(device, queue, texture_layout, recreated)?);
+        }
+        self.materials = materials;
+        Ok(())
+    }
 }
 
+// None without any deltas, as storage buffers can't be empty.
+pub fn create_morph_deltas_buffer(device: &wgpu::Device, deltas: &[MorphDelta]) -> Option<wgpu::Buffer> {
+    if deltas.is_empty() {
+        return None;
+    }
+    Some(device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Morph Deltas Buffer"),
+        contents: bytemuck::cast_slice(deltas),
+        usage: wgpu::BufferUsages::STORAGE,
+    }))
+}
+
+pub const SKINNED_BOUNDS_PADDING: f32 = 1.5;
+
 #[repr(C)]
 pub struct SkinnedTexturedMesh {
     pub name: String,
@@ -33,9 +103,35 @@ pub struct SkinnedTexturedMesh {
     pub rotation: glam::Quat,
     pub scale: glam::Vec3,
     pub dimensions: glam::Vec3,
+    // CPU copies of the geometry, kept for picking. Bone indices are remapped to the skeleton's joints.
+    pub positions: Vec<glam::Vec3>,
+    pub indices: Vec<u32>,
+    pub bone_indices: Vec<[u32; 4]>,
+    pub bone_weights: Vec<[f32; 4]>,
+    // What the vertex buffer was filled with, kept to fill it again after a device loss.
+    pub vertices: Vec<SkinnedModelVertex>,
+    // Where the mesh's deltas start in the model's morph deltas buffer, and how many targets it has there
+    // (the model's count, or 0 when this mesh has none).
+    pub morph_offset: u32,
+    pub num_morph_targets: u32,
     //pub matrices_texture: Option<wgpu::Texture>,
 }
 
+impl SkinnedTexturedMesh {
+    // What the skinned shader needs to find this mesh's deltas, passed along with each instance.
+    pub fn morph_params(&self) -> [u32; 3] {
+        [self.morph_offset, self.positions.len() as u32, self.num_morph_targets]
+    }
+}
+
+// One vertex's offsets for one morph target. Padded to vec4 for the storage buffer.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MorphDelta {
+    pub position: [f32; 4],
+    pub normal: [f32; 4],
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct SkinnedModelVertex {
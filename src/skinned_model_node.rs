@@ -1,6 +1,7 @@
 use std::rc::Rc;
 
-use crate::{instance::Instance};
+use crate::instance::*;
+use crate::skinned_model::*;
 // use rayon::prelude::*;
 use wgpu::{BindGroupLayout, util::*};
 
@@ -12,6 +13,15 @@ pub struct SkinnedModelNode {
     pub num_bones_buffer: wgpu::Buffer,
     pub bind_group: wgpu::BindGroup,
     pub bone_matrices: Vec<glam::Mat4>,
+    // MAX_MORPH_TARGETS per instance, written along with the bones.
+    pub morph_weights: Vec<f32>,
+    pub morph_weights_buffer: wgpu::Buffer,
+    // The model's deltas once the renderer has bound them, a placeholder until then.
+    pub morph_deltas_buffer: wgpu::Buffer,
+    pub morph_targets_bound: bool,
+    pub dirty: bool,
+    // One per mesh of the skinned model. Created by the renderer on first use.
+    pub instance_buffers: Vec<MeshInstanceBuffers>,
 }
 
 impl SkinnedModelNode {
@@ -30,7 +40,7 @@ impl SkinnedModelNode {
         let mut bone_matrices = Vec::<glam::Mat4>::new();
         let mut instances = Vec::new();
         for instance in instances_arg {
-            let i = Instance{ position: instance.position, orientation: instance.orientation, scale: instance.scale};
+            let i = Instance{ position: instance.position, orientation: instance.orientation, scale: instance.scale, tint: instance.tint, emissive: instance.emissive, user_data: instance.user_data};
             instances.push(i);
         }
         let num_bones_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -54,27 +64,20 @@ impl SkinnedModelNode {
         //     (bone_matrices.len() as f32 * 16.0 * 4.0) / (1024.0 * 1024.0),
         //     num_bones
         // );
-        let bones_storage_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Animation matrices storage buffer"),
-            contents: bytemuck::cast_slice(&bone_matrices),
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-        });
+        let bones_storage_buffer = create_bones_storage_buffer(device, &bone_matrices);
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: bone_matrices_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: bones_storage_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: num_bones_buffer.as_entire_binding(),
-                },
-            ],
-            label: Some("Bone matrices bind group"),
-        });
+        let morph_weights = vec![0.0; instances.len().max(1) * MAX_MORPH_TARGETS];
+        let morph_weights_buffer = create_morph_weights_buffer(device, &morph_weights);
+        let morph_deltas_buffer = create_morph_deltas_placeholder(device);
 
+        let bind_group = create_bind_group(
+            device,
+            bone_matrices_bind_group_layout,
+            &bones_storage_buffer,
+            &num_bones_buffer,
+            &morph_weights_buffer,
+            &morph_deltas_buffer,
+        );
 
         Self {
             skinned_model_idx,
@@ -83,10 +86,111 @@ impl SkinnedModelNode {
             bones_storage_buffer,
             num_bones_buffer,
             bind_group,
-            bone_matrices
+            bone_matrices,
+            morph_weights,
+            morph_weights_buffer,
+            morph_deltas_buffer,
+            morph_targets_bound: false,
+            dirty: true,
+            instance_buffers: Vec::new(),
         }
     }
 
+    // Recreates the bone buffers and bind group on a new device, from the bone matrices kept on the CPU.
+    // Instance buffers are left to the renderer.
+    pub fn recreate_gpu_resources(
+        &mut self,
+        device: &wgpu::Device,
+        bone_matrices_bind_group_layout: &BindGroupLayout,
+    ) {
+        if self.bone_matrices.is_empty() {
+            self.bone_matrices.push(glam::Mat4::IDENTITY);
+        }
+        self.num_bones_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Num bones uniform buffer"),
+            contents: bytemuck::cast_slice(&[self.num_bones]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        self.bones_storage_buffer = create_bones_storage_buffer(device, &self.bone_matrices);
+        if self.morph_weights.is_empty() {
+            self.morph_weights.resize(MAX_MORPH_TARGETS, 0.0);
+        }
+        self.morph_weights_buffer = create_morph_weights_buffer(device, &self.morph_weights);
+        self.morph_deltas_buffer = create_morph_deltas_placeholder(device);
+        self.morph_targets_bound = false;
+        self.bind_group = create_bind_group(
+            device,
+            bone_matrices_bind_group_layout,
+            &self.bones_storage_buffer,
+            &self.num_bones_buffer,
+            &self.morph_weights_buffer,
+            &self.morph_deltas_buffer,
+        );
+        self.instance_buffers.clear();
+        self.dirty = true;
+    }
+
+    // Writes the bones and morph weights. Their buffers are made again, bigger, along with the bind group, when there
+    // are more characters than they were made for.
+    pub fn write_buffers(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bone_matrices_bind_group_layout: &BindGroupLayout,
+    ) {
+        let mut grown = false;
+        let bones_size = std::mem::size_of_val(self.bone_matrices.as_slice()) as wgpu::BufferAddress;
+        if bones_size > self.bones_storage_buffer.size() {
+            self.bones_storage_buffer = create_bones_storage_buffer(device, &self.bone_matrices);
+            grown = true;
+        } else {
+            queue.write_buffer(&self.bones_storage_buffer, 0, bytemuck::cast_slice(&self.bone_matrices));
+        }
+
+        let weights_size = std::mem::size_of_val(self.morph_weights.as_slice()) as wgpu::BufferAddress;
+        if weights_size > self.morph_weights_buffer.size() {
+            self.morph_weights_buffer = create_morph_weights_buffer(device, &self.morph_weights);
+            grown = true;
+        } else {
+            queue.write_buffer(&self.morph_weights_buffer, 0, bytemuck::cast_slice(&self.morph_weights));
+        }
+
+        if grown {
+            self.bind_group = create_bind_group(
+                device,
+                bone_matrices_bind_group_layout,
+                &self.bones_storage_buffer,
+                &self.num_bones_buffer,
+                &self.morph_weights_buffer,
+                &self.morph_deltas_buffer,
+            );
+        }
+    }
+
+    // Binds the model's morph deltas in place of the placeholder. Called by the renderer on first use,
+    // as the node is created without its model.
+    pub fn bind_morph_targets(
+        &mut self,
+        device: &wgpu::Device,
+        bone_matrices_bind_group_layout: &BindGroupLayout,
+        skinned_model: &SkinnedModel,
+    ) {
+        self.morph_targets_bound = true;
+        let deltas = match &skinned_model.morph_deltas_buffer {
+            Some(val) => val,
+            None => return,
+        };
+        self.morph_deltas_buffer = deltas.clone();
+        self.bind_group = create_bind_group(
+            device,
+            bone_matrices_bind_group_layout,
+            &self.bones_storage_buffer,
+            &self.num_bones_buffer,
+            &self.morph_weights_buffer,
+            &self.morph_deltas_buffer,
+        );
+    }
+
     // pub fn update(
     //     &mut self,
     //     queue: &mut wgpu::Queue,
@@ -134,3 +238,63 @@ impl SkinnedModelNode {
     //     );
     // }
 }
+
+fn create_bones_storage_buffer(device: &wgpu::Device, bone_matrices: &Vec<glam::Mat4>) -> wgpu::Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Animation matrices storage buffer"),
+        contents: bytemuck::cast_slice(bone_matrices),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+fn create_morph_weights_buffer(device: &wgpu::Device, weights: &Vec<f32>) -> wgpu::Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Morph weights storage buffer"),
+        contents: bytemuck::cast_slice(weights),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+// Models without morph targets never read it, but the binding needs a buffer.
+fn create_morph_deltas_placeholder(device: &wgpu::Device) -> wgpu::Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Morph deltas placeholder"),
+        contents: bytemuck::cast_slice(&[MorphDelta {
+            position: [0.0; 4],
+            normal: [0.0; 4],
+        }]),
+        usage: wgpu::BufferUsages::STORAGE,
+    })
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    bone_matrices_bind_group_layout: &BindGroupLayout,
+    bones_storage_buffer: &wgpu::Buffer,
+    num_bones_buffer: &wgpu::Buffer,
+    morph_weights_buffer: &wgpu::Buffer,
+    morph_deltas_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: bone_matrices_bind_group_layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: bones_storage_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: num_bones_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: morph_weights_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: morph_deltas_buffer.as_entire_binding(),
+            },
+        ],
+        label: Some("Bone matrices bind group"),
+    })
+}
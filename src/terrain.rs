@@ -0,0 +1,416 @@
+// Heightmap terrain. The map is split into square chunks, each with a mesh per level of detail, and the Terrain pass
+// picks a level per chunk from its distance to the eye. Every level has skirts hanging down from its edges, which
+// hide the cracks between neighbours of different levels. Up to four tiled textures are blended by a splat map.
+
+use crate::culling::*;
+use crate::model::Vertex;
+use crate::physics_context::PhysicsContext;
+use crate::texture::*;
+use rapier3d::prelude::{ColliderBuilder, ColliderHandle};
+use wgpu::util::DeviceExt;
+
+pub const MAX_TERRAIN_LAYERS: usize = 4;
+
+// Heights from 0 to 1, row by row along Z.
+pub struct Heightmap {
+    pub width: u32,
+    pub depth: u32,
+    pub heights: Vec<f32>,
+}
+
+impl Heightmap {
+    pub fn from_image(img: &image::DynamicImage) -> Self {
+        let luma = img.to_luma16();
+        Self {
+            width: luma.width(),
+            depth: luma.height(),
+            heights: luma.pixels().map(|p| p.0[0] as f32 / u16::MAX as f32).collect(),
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        Ok(Self::from_image(&image::load_from_memory(bytes)?))
+    }
+
+    pub fn get(&self, x: u32, z: u32) -> f32 {
+        let x = x.min(self.width - 1);
+        let z = z.min(self.depth - 1);
+        self.heights[(z * self.width + x) as usize]
+    }
+
+    // Bilinear, with u and v from 0 to 1 across the map.
+    pub fn sample(&self, u: f32, v: f32) -> f32 {
+        let x = u.clamp(0.0, 1.0) * (self.width - 1) as f32;
+        let z = v.clamp(0.0, 1.0) * (self.depth - 1) as f32;
+        let (x0, z0) = (x.floor() as u32, z.floor() as u32);
+        let (fx, fz) = (x.fract(), z.fract());
+        let top = self.get(x0, z0) * (1.0 - fx) + self.get(x0 + 1, z0) * fx;
+        let bottom = self.get(x0, z0 + 1) * (1.0 - fx) + self.get(x0 + 1, z0 + 1) * fx;
+        top * (1.0 - fz) + bottom * fz
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TerrainSettings {
+    // The terrain is centered on this point, with its lowest possible height at position.y.
+    pub position: glam::Vec3,
+    // Extent along X and Z.
+    pub size: glam::Vec2,
+    pub height_scale: f32,
+    pub chunks_per_side: u32,
+    // Quads along a chunk's side at the most detailed level. Should be a power of two.
+    pub chunk_resolution: u32,
+    // Each level halves the resolution of the one before it.
+    pub lod_count: u32,
+    // Chunks closer than this use the most detailed level. The range doubles for each level after it.
+    pub lod_distance: f32,
+    pub skirt_depth: f32,
+    // World units covered by one repetition of the layer textures.
+    pub texture_scale: f32,
+}
+
+impl Default for TerrainSettings {
+    fn default() -> Self {
+        Self {
+            position: glam::Vec3::ZERO,
+            size: glam::Vec2::splat(256.0),
+            height_scale: 32.0,
+            chunks_per_side: 8,
+            chunk_resolution: 32,
+            lod_count: 4,
+            lod_distance: 48.0,
+            skirt_depth: 2.0,
+            texture_scale: 8.0,
+        }
+    }
+}
+
+impl TerrainSettings {
+    pub fn lod_for_distance(&self, distance: f32) -> usize {
+        let mut lod = 0;
+        let mut range = self.lod_distance;
+        while distance > range && lod + 1 < self.lod_count as usize {
+            lod += 1;
+            range *= 2.0;
+        }
+        lod
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TerrainVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    // From 0 to 1 across the whole terrain, for the splat map.
+    pub splat_coords: [f32; 2],
+}
+
+impl Vertex for TerrainVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<TerrainVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 6]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+            ],
+        }
+    }
+}
+
+pub struct ChunkLod {
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+    pub num_elements: u32,
+}
+
+pub struct TerrainChunk {
+    pub lods: Vec<ChunkLod>,
+    pub bounds: BoundingSphere,
+}
+
+// The splat map's red, green, blue and alpha channels weigh the first to fourth layer.
+pub struct TerrainMaterial {
+    pub splat_map: Texture,
+    // Missing layers repeat the last one given.
+    pub layers: Vec<Texture>,
+}
+
+impl TerrainMaterial {
+    pub fn recreate(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        recreated: &mut RecreatedTextures,
+    ) -> anyhow::Result<Self> {
+        use anyhow::Context;
+        let splat_map = self
+            .splat_map
+            .recreate(device, queue, recreated)
+            .context("Could not recreate the terrain's splat map")?;
+        let mut layers = Vec::with_capacity(self.layers.len());
+        for (idx, layer) in self.layers.iter().enumerate() {
+            layers.push(
+                layer
+                    .recreate(device, queue, recreated)
+                    .with_context(|| format!("Could not recreate terrain layer {}", idx))?,
+            );
+        }
+        Ok(Self { splat_map, layers })
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TerrainUniform {
+    pub texture_scale: f32,
+    pub _padding: [f32; 3],
+}
+
+pub struct Terrain {
+    pub settings: TerrainSettings,
+    pub heightmap: Heightmap,
+    pub chunks: Vec<TerrainChunk>,
+    pub material: TerrainMaterial,
+    pub uniform_buffer: wgpu::Buffer,
+    pub collider: Option<ColliderHandle>,
+    pub visible: bool,
+    // Created by the renderer on first use.
+    pub bind_group: Option<wgpu::BindGroup>,
+}
+
+impl Terrain {
+    pub fn new(
+        device: &wgpu::Device,
+        heightmap: Heightmap,
+        material: TerrainMaterial,
+        settings: TerrainSettings,
+    ) -> Self {
+        let mut chunks = Vec::<TerrainChunk>::new();
+        for chunk_z in 0..settings.chunks_per_side {
+            for chunk_x in 0..settings.chunks_per_side {
+                chunks.push(Self::build_chunk(device, &heightmap, &settings, chunk_x, chunk_z));
+            }
+        }
+
+        let uniform_buffer = Self::create_uniform_buffer(device, &settings);
+
+        Self {
+            settings,
+            heightmap,
+            chunks,
+            material,
+            uniform_buffer,
+            collider: None,
+            visible: true,
+            bind_group: None,
+        }
+    }
+
+    // Rebuilds the chunks, uniform and material on a new device, after the old one was lost.
+    pub fn recreate_gpu_resources(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        recreated: &mut RecreatedTextures,
+    ) -> anyhow::Result<()> {
+        let settings = self.settings;
+        self.chunks.clear();
+        for chunk_z in 0..settings.chunks_per_side {
+            for chunk_x in 0..settings.chunks_per_side {
+                self.chunks
+                    .push(Self::build_chunk(device, &self.heightmap, &settings, chunk_x, chunk_z));
+            }
+        }
+        self.uniform_buffer = Self::create_uniform_buffer(device, &settings);
+        self.material = self.material.recreate(device, queue, recreated)?;
+        self.bind_group = None;
+        Ok(())
+    }
+
+    pub fn set_material(&mut self, material: TerrainMaterial) {
+        self.material = material;
+        self.bind_group = None;
+    }
+
+    fn create_uniform_buffer(device: &wgpu::Device, settings: &TerrainSettings) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Terrain Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[TerrainUniform {
+                texture_scale: settings.texture_scale,
+                _padding: [0.0; 3],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        })
+    }
+
+    // Height in world space at a point on the XZ plane.
+    pub fn height_at(&self, x: f32, z: f32) -> f32 {
+        let (u, v) = self.uv_at(x, z);
+        self.settings.position.y + self.heightmap.sample(u, v) * self.settings.height_scale
+    }
+
+    fn uv_at(&self, x: f32, z: f32) -> (f32, f32) {
+        let s = &self.settings;
+        (
+            (x - s.position.x) / s.size.x + 0.5,
+            (z - s.position.z) / s.size.y + 0.5,
+        )
+    }
+
+    // Adds a static heightfield collider matching the heightmap. Replaces the previous one, if any.
+    pub fn create_collider(&mut self, physics_context: &mut PhysicsContext) {
+        if let Some(handle) = self.collider.take() {
+            physics_context.uncouple_collider(handle);
+            let world = &mut physics_context.rigid_world;
+            world
+                .colliders
+                .remove(handle, &mut world.islands, &mut world.bodies, false);
+        }
+        let world = &mut physics_context.rigid_world;
+
+        let s = &self.settings;
+        let rows = self.heightmap.depth as usize;
+        let columns = self.heightmap.width as usize;
+        // Rows run along Z and columns along X, stored column by column.
+        let mut heights = Vec::<f32>::with_capacity(rows * columns);
+        for x in 0..columns {
+            for z in 0..rows {
+                heights.push(self.heightmap.get(x as u32, z as u32));
+            }
+        }
+        let heights = rapier3d::parry::utils::Array2::new(rows, columns, heights);
+
+        let collider = ColliderBuilder::heightfield(
+            heights,
+            rapier3d::math::Vector::new(s.size.x, s.height_scale, s.size.y),
+        )
+        .translation(rapier3d::math::Vector::new(
+            s.position.x,
+            s.position.y,
+            s.position.z,
+        ))
+        .build();
+        self.collider = Some(world.colliders.insert(collider));
+    }
+
+    fn build_chunk(
+        device: &wgpu::Device,
+        heightmap: &Heightmap,
+        settings: &TerrainSettings,
+        chunk_x: u32,
+        chunk_z: u32,
+    ) -> TerrainChunk {
+        let chunk_size = settings.size / settings.chunks_per_side as f32;
+        let min_corner = glam::Vec2::new(settings.position.x, settings.position.z) - settings.size * 0.5
+            + chunk_size * glam::Vec2::new(chunk_x as f32, chunk_z as f32);
+
+        let mut lods = Vec::<ChunkLod>::new();
+        let mut min_height = f32::MAX;
+        let mut max_height = f32::MIN;
+
+        for lod in 0..settings.lod_count.max(1) {
+            let resolution = (settings.chunk_resolution >> lod).max(1);
+            let row_len = resolution + 1;
+            let mut vertices = Vec::<TerrainVertex>::new();
+            let mut indices = Vec::<u32>::new();
+
+            for z in 0..=resolution {
+                for x in 0..=resolution {
+                    let local = glam::Vec2::new(x as f32, z as f32) / resolution as f32 * chunk_size;
+                    let vertex = Self::vertex_at(heightmap, settings, min_corner + local);
+                    min_height = min_height.min(vertex.position[1]);
+                    max_height = max_height.max(vertex.position[1]);
+                    vertices.push(vertex);
+                }
+            }
+
+            for z in 0..resolution {
+                for x in 0..resolution {
+                    let a = z * row_len + x;
+                    let b = a + row_len;
+                    indices.extend_from_slice(&[a, b, a + 1, a + 1, b, b + 1]);
+                }
+            }
+
+            // Skirts: each edge gets a copy of its vertices lowered by skirt_depth, joined to it by a strip of quads.
+            let edges: [Vec<u32>; 4] = [
+                (0..row_len).collect(),
+                (0..row_len).map(|x| resolution * row_len + x).collect(),
+                (0..row_len).map(|z| z * row_len).collect(),
+                (0..row_len).map(|z| z * row_len + resolution).collect(),
+            ];
+            for edge in edges.iter() {
+                let first_skirt = vertices.len() as u32;
+                for i in edge.iter() {
+                    let mut v = vertices[*i as usize];
+                    v.position[1] -= settings.skirt_depth;
+                    vertices.push(v);
+                }
+                for k in 0..edge.len() as u32 - 1 {
+                    let top_a = edge[k as usize];
+                    let top_b = edge[k as usize + 1];
+                    let bottom_a = first_skirt + k;
+                    let bottom_b = first_skirt + k + 1;
+                    indices.extend_from_slice(&[top_a, bottom_a, top_b, top_b, bottom_a, bottom_b]);
+                }
+            }
+
+            let label = format!("Terrain Chunk {} {} LOD {}", chunk_x, chunk_z, lod);
+            lods.push(ChunkLod {
+                vertex_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{} Vertex Buffer", label)),
+                    contents: bytemuck::cast_slice(&vertices),
+                    usage: wgpu::BufferUsages::VERTEX,
+                }),
+                index_buffer: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{} Index Buffer", label)),
+                    contents: bytemuck::cast_slice(&indices),
+                    usage: wgpu::BufferUsages::INDEX,
+                }),
+                num_elements: indices.len() as u32,
+            });
+        }
+
+        let min = glam::Vec3::new(min_corner.x, min_height - settings.skirt_depth, min_corner.y);
+        let max = glam::Vec3::new(min_corner.x + chunk_size.x, max_height, min_corner.y + chunk_size.y);
+        TerrainChunk {
+            lods,
+            bounds: BoundingSphere::new((min + max) * 0.5, (max - min).length() * 0.5),
+        }
+    }
+
+    fn vertex_at(heightmap: &Heightmap, settings: &TerrainSettings, point: glam::Vec2) -> TerrainVertex {
+        let origin = glam::Vec2::new(settings.position.x, settings.position.z) - settings.size * 0.5;
+        let uv = (point - origin) / settings.size;
+        let height = |u: f32, v: f32| heightmap.sample(u, v) * settings.height_scale;
+
+        // Central differences over one texel of the heightmap, so every level gets the same normals.
+        let du = 1.0 / heightmap.width.max(2) as f32;
+        let dv = 1.0 / heightmap.depth.max(2) as f32;
+        let dx = (height(uv.x + du, uv.y) - height(uv.x - du, uv.y)) / (2.0 * du * settings.size.x);
+        let dz = (height(uv.x, uv.y + dv) - height(uv.x, uv.y - dv)) / (2.0 * dv * settings.size.y);
+        let normal = glam::Vec3::new(-dx, 1.0, -dz).normalize();
+
+        TerrainVertex {
+            position: [point.x, settings.position.y + height(uv.x, uv.y), point.y],
+            normal: normal.into(),
+            splat_coords: uv.into(),
+        }
+    }
+}
@@ -2,12 +2,32 @@
 
 use anyhow::*;
 use image::GenericImageView;
+use std::{collections::HashMap, sync::Arc};
+
+// What a texture was made from, kept so it can be made again on a new device after the old one was lost.
+pub enum TextureSource {
+    Image {
+        label: Option<String>,
+        width: u32,
+        height: u32,
+        rgba: Vec<u8>,
+        is_normal_map: bool,
+    },
+    // Drawn to by a render target, which makes its texture again itself.
+    RenderTarget,
+}
+
+// Textures made again on a new device, by the source they were made from. Clones of a texture share their source,
+// so they stay shared once recreated.
+pub type RecreatedTextures = HashMap<usize, Texture>;
 
 #[derive(Clone)]
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
+    // None for the textures the renderers make for themselves.
+    pub source: Option<Arc<TextureSource>>,
 }
 
 impl Texture {
@@ -52,24 +72,27 @@ impl Texture {
             texture,
             view,
             sampler,
+            source: None,
         }
     }
 
-    pub fn create_mat4f_texture(device: &wgpu::Device, label: &str, num_matrices: u32) -> Self {
+    // Affine matrices laid out in rows of matrices_per_row, each matrix as its top three rows in consecutive texels.
+    // Read with textureLoad, so there is no filtering.
+    pub fn create_mat4f_texture(device: &wgpu::Device, label: &str, matrices_per_row: u32, rows: u32) -> Self {
         let size = wgpu::Extent3d {
-            width: 4,
-            height: 4,
-            depth_or_array_layers: num_matrices * 4,
+            width: (matrices_per_row * 3).max(1),
+            height: rows.max(1),
+            depth_or_array_layers: 1,
         };
         let desc = wgpu::TextureDescriptor {
             label: Some(label),
             size,
             mip_level_count: 1,
             sample_count: 1,
-            dimension: wgpu::TextureDimension::D3,
-            format: wgpu::TextureFormat::R32Float,
-            usage: wgpu::TextureUsages::STORAGE_BINDING,
-            view_formats: &[wgpu::TextureFormat::R32Float],
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
         };
         let texture = device.create_texture(&desc);
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
@@ -77,12 +100,9 @@ impl Texture {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
             mipmap_filter: wgpu::MipmapFilterMode::Nearest,
-            compare: Some(wgpu::CompareFunction::LessEqual),
-            lod_min_clamp: 0.0,
-            lod_max_clamp: 0.0,
             ..Default::default()
         });
 
@@ -90,6 +110,7 @@ impl Texture {
             texture,
             view,
             sampler,
+            source: None,
         }
     }
 
@@ -117,9 +138,55 @@ impl Texture {
         // println!("Obtaining rgba8");
         let rgba = img.to_rgba8();
 
-        let size = wgpu::Extent3d {
+        let source = Arc::new(TextureSource::Image {
+            label: label.map(|l| l.to_owned()),
             width: dimensions.0,
             height: dimensions.1,
+            rgba: rgba.into_raw(),
+            is_normal_map,
+        });
+        Ok(Self::from_source(device, queue, source))
+    }
+
+    // Makes the texture again on a new device, from what it was made from. Render targets have to be recreated
+    // first, so that their new textures are in `recreated`.
+    pub fn recreate(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        recreated: &mut RecreatedTextures,
+    ) -> Result<Self> {
+        let source = match &self.source {
+            Some(val) => val,
+            None => bail!("The texture wasn't made from anything that can be loaded again"),
+        };
+        let key = Arc::as_ptr(source) as usize;
+        if let Some(texture) = recreated.get(&key) {
+            return Ok(texture.clone());
+        }
+        if let TextureSource::RenderTarget = **source {
+            bail!("The render target drawing to the texture wasn't recreated");
+        }
+        let texture = Self::from_source(device, queue, source.clone());
+        recreated.insert(key, texture.clone());
+        Ok(texture)
+    }
+
+    fn from_source(device: &wgpu::Device, queue: &wgpu::Queue, source: Arc<TextureSource>) -> Self {
+        let (label, width, height, rgba, is_normal_map) = match &*source {
+            TextureSource::Image {
+                label,
+                width,
+                height,
+                rgba,
+                is_normal_map,
+            } => (label.as_deref(), *width, *height, rgba, *is_normal_map),
+            TextureSource::RenderTarget => unreachable!("Render targets make their own textures"),
+        };
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
             depth_or_array_layers: 1,
         };
 
@@ -147,11 +214,11 @@ impl Texture {
                 mip_level: 0,
                 origin: wgpu::Origin3d::ZERO,
             },
-            &rgba,
+            rgba,
             wgpu::TexelCopyBufferLayout {
                 offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
+                bytes_per_row: Some(4 * width),
+                rows_per_image: Some(height),
             },
             size,
         );
@@ -167,10 +234,11 @@ impl Texture {
             ..Default::default()
         });
 
-        Ok(Self {
+        Self {
             texture,
             view,
             sampler,
-        })
+            source: Some(source),
+        }
     }
 }
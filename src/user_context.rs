@@ -1,4 +1,7 @@
-use crate::{asset_manager::*, scene::*, skeletal_context::SkeletalContext};
+use crate::{
+    asset_manager::*, graphics::GraphicsContext, debug_draw::DebugDraw, frame_capture::FrameCapture, scene::*,
+    render_target::RenderTarget, skeletal_context::SkeletalContext, texture::RecreatedTextures, viewport::Viewport,
+};
 use kira::{
 	AudioManager, AudioManagerSettings, DefaultBackend,
 };
@@ -8,6 +11,12 @@ pub struct UserContext {
     pub skeletals: Vec<SkeletalContext>,
     pub scenes: Vec<Scene>,
     pub audio_mgr: Option<AudioManager>,
+    pub debug_draw: DebugDraw,
+    pub frame_capture: FrameCapture,
+    // Cameras of the active scene to draw side by side. Empty draws the active camera over the whole window.
+    pub viewports: Vec<Viewport>,
+    // Drawn by the window before its own view, for materials to show.
+    pub render_targets: Vec<RenderTarget>,
     pub active_scene: usize,
     pub time_elapsed: u128,
 }
@@ -30,8 +39,37 @@ impl UserContext {
             skeletals,
             scenes,
             audio_mgr,
+            debug_draw: DebugDraw::new(),
+            frame_capture: FrameCapture::new(),
+            viewports: Vec::new(),
+            render_targets: Vec::new(),
             active_scene: 0,
             time_elapsed: 0,
         }
     }
+
+    // Called by the window after it recreated the device. Rebuilds the render targets, models, textures and scenes
+    // from what they keep on the CPU. What user code created on the old device is left to the device recreated callback.
+    pub fn recreate_gpu_resources(&mut self, gfx_ctx: &mut GraphicsContext) -> anyhow::Result<()> {
+        // Render targets first, so materials showing them find their new textures.
+        let mut recreated = RecreatedTextures::new();
+        for target in self.render_targets.iter_mut() {
+            target.recreate_gpu_resources(&gfx_ctx.device, gfx_ctx.config.format, &mut recreated);
+        }
+        self.asset_mgr.recreate_gpu_resources(
+            &gfx_ctx.device,
+            &gfx_ctx.queue,
+            &gfx_ctx.texture_bind_group_layout_3d,
+            &mut recreated,
+        )?;
+        for scene in self.scenes.iter_mut() {
+            scene.recreate_gpu_resources(
+                &gfx_ctx.device,
+                &gfx_ctx.queue,
+                &gfx_ctx.bone_matrices_bind_group_layout,
+                &mut recreated,
+            )?;
+        }
+        Ok(())
+    }
 }
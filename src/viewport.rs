@@ -0,0 +1,89 @@
+// Split screen and picture-in-picture. Each viewport shows one of the active scene's cameras in a rectangle of the
+// window. The window gives every viewport its own camera uniform and render graph, sized to the rectangle.
+// With no viewports the active camera fills the window, as before.
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ViewportRect {
+    // Fractions of the window, from the top left.
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl ViewportRect {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self { x, y, width, height }
+    }
+
+    pub fn full() -> Self {
+        Self::new(0.0, 0.0, 1.0, 1.0)
+    }
+
+    // The rectangles of an even split of the window, for local co-op. Two players split it left and right,
+    // three or four split it into quarters.
+    pub fn split(count: usize) -> Vec<Self> {
+        match count {
+            0 => Vec::new(),
+            1 => vec![Self::full()],
+            2 => vec![Self::new(0.0, 0.0, 0.5, 1.0), Self::new(0.5, 0.0, 0.5, 1.0)],
+            _ => {
+                let quarters = [
+                    Self::new(0.0, 0.0, 0.5, 0.5),
+                    Self::new(0.5, 0.0, 0.5, 0.5),
+                    Self::new(0.0, 0.5, 0.5, 0.5),
+                    Self::new(0.5, 0.5, 0.5, 0.5),
+                ];
+                quarters[..count.min(4)].to_vec()
+            }
+        }
+    }
+
+    pub fn to_pixels(&self, surface_width: u32, surface_height: u32) -> PixelRect {
+        let x = (self.x.clamp(0.0, 1.0) * surface_width as f32) as u32;
+        let y = (self.y.clamp(0.0, 1.0) * surface_height as f32) as u32;
+        let width = (self.width * surface_width as f32) as u32;
+        let height = (self.height * surface_height as f32) as u32;
+        PixelRect {
+            x,
+            y,
+            width: width.min(surface_width.saturating_sub(x)).max(1),
+            height: height.min(surface_height.saturating_sub(y)).max(1),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PixelRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl PixelRect {
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.x as f32
+            && y >= self.y as f32
+            && x < (self.x + self.width) as f32
+            && y < (self.y + self.height) as f32
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Viewport {
+    // Into the active scene's cameras.
+    pub camera_idx: usize,
+    pub rect: ViewportRect,
+    pub enabled: bool,
+}
+
+impl Viewport {
+    pub fn new(camera_idx: usize, rect: ViewportRect) -> Self {
+        Self {
+            camera_idx,
+            rect,
+            enabled: true,
+        }
+    }
+}
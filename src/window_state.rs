@@ -1,9 +1,14 @@
 use crate::callbacks::*;
 use crate::camera::*;
 use crate::egui_renderer::EguiRenderer;
+use crate::frame_capture::*;
 use crate::graphics::*;
 use crate::light::*;
-use crate::passes::{Pass, forward_renderer::*};
+use crate::passes::{
+    bloom::*, crowd::*, debug_draw::*, environment::*, fluid::*, forward_renderer::*, fxaa::*, light_culling::*,
+    outline::*, particles::*, render_graph::*, resolve::*, sky::*, terrain::*, tonemap::*, transparent::*,
+};
+use crate::render_settings::*;
 use crate::texture::*;
 use crate::user_context::*;
 use egui_wgpu::ScreenDescriptor;
@@ -17,14 +22,196 @@ use winit::{
     window::Window,
 };
 
+// The scene is lit into an HDR target, then bloomed and tonemapped into the surface. egui draws on top afterwards.
+// With MSAA the scene passes draw into multisampled attachments that get resolved into the HDR target,
+// and with post anti-aliasing the tonemapper writes an intermediate image that FXAA then draws to the surface.
+// Viewports and render targets aren't the window's size, so their graphs get a depth buffer of their own.
+fn build_render_graph(
+    gfx_ctx: &GraphicsContext,
+    cam_ctx: &CameraContext,
+    light_ctx: &LightContext,
+    own_depth: bool,
+) -> RenderGraph {
+    let sample_count = gfx_ctx.msaa_sample_count();
+    let settings = &gfx_ctx.render_settings;
+
+    let mut render_graph = RenderGraph::new();
+    if own_depth {
+        render_graph.declare_texture(DEPTH, TransientTextureDesc::new(Texture::DEPTH_FORMAT));
+    }
+    render_graph.import_buffer_with_bind_group(CAMERA_BUFFER, cam_ctx.buffer.clone(), cam_ctx.bind_group.clone());
+    render_graph.import_buffer_with_bind_group(
+        LIGHT_BUFFER,
+        light_ctx.light_buffer.clone(),
+        light_ctx.light_bind_group.clone(),
+    );
+    // COPY_SRC for the fluid pass, which copies the scene behind the water out of it.
+    render_graph.declare_texture(
+        HDR,
+        TransientTextureDesc {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            ..TransientTextureDesc::new(HDR_FORMAT)
+        },
+    );
+    render_graph.declare_texture(SCENE_COLOR, FluidRenderer::scene_color_desc());
+    render_graph.declare_texture(BLOOM, BloomPass::transient_desc());
+    render_graph.declare_texture(OUTLINE_MASK, OutlinePass::mask_desc());
+
+    let environment_pass = EnvironmentPass::new(&gfx_ctx.device);
+    let light_culling_pass = LightCullingPass::new(&gfx_ctx.device, light_ctx);
+    let mut forward_renderer = ForwardRenderer::new(
+        &gfx_ctx.device,
+        &gfx_ctx.texture_bind_group_layout_3d,
+        &cam_ctx.bind_group_layout,
+        &light_ctx.light_bind_group_layout,
+        &gfx_ctx.bone_matrices_bind_group_layout,
+        HDR_FORMAT,
+        sample_count,
+    );
+    let mut terrain_renderer = TerrainRenderer::new(
+        &gfx_ctx.device,
+        &cam_ctx.bind_group_layout,
+        &light_ctx.light_bind_group_layout,
+        HDR_FORMAT,
+        sample_count,
+    );
+    let mut crowd_renderer = CrowdRenderer::new(
+        &gfx_ctx.device,
+        &gfx_ctx.texture_bind_group_layout_3d,
+        &cam_ctx.bind_group_layout,
+        &light_ctx.light_bind_group_layout,
+        HDR_FORMAT,
+        sample_count,
+    );
+    let mut sky_pass = SkyPass::new(&gfx_ctx.device, &light_ctx.light_bind_group_layout, HDR_FORMAT, sample_count);
+    let mut outline_pass = OutlinePass::new(
+        &gfx_ctx.device,
+        &cam_ctx.bind_group_layout,
+        &gfx_ctx.bone_matrices_bind_group_layout,
+        HDR_FORMAT,
+        sample_count,
+    );
+    let mut transparent_renderer = TransparentRenderer::new(
+        &gfx_ctx.device,
+        &gfx_ctx.texture_bind_group_layout_3d,
+        &cam_ctx.bind_group_layout,
+        &light_ctx.light_bind_group_layout,
+        &gfx_ctx.bone_matrices_bind_group_layout,
+        HDR_FORMAT,
+        sample_count,
+    );
+    let mut fluid_renderer = FluidRenderer::new(
+        &gfx_ctx.device,
+        &cam_ctx.bind_group_layout,
+        &light_ctx.light_bind_group_layout,
+        HDR_FORMAT,
+        sample_count,
+    );
+    let mut particle_renderer = ParticleRenderer::new(
+        &gfx_ctx.device,
+        &gfx_ctx.queue,
+        &cam_ctx.bind_group_layout,
+        &light_ctx.light_bind_group_layout,
+        HDR_FORMAT,
+        sample_count,
+    );
+    let mut debug_draw_pass = DebugDrawPass::new(
+        &gfx_ctx.device,
+        &cam_ctx.bind_group_layout,
+        HDR_FORMAT,
+        sample_count,
+    );
+
+    if sample_count > 1 {
+        render_graph.declare_texture(
+            HDR_MSAA,
+            TransientTextureDesc {
+                sample_count,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                ..TransientTextureDesc::new(HDR_FORMAT)
+            },
+        );
+        render_graph.declare_texture(
+            DEPTH_MSAA,
+            TransientTextureDesc {
+                sample_count,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                ..TransientTextureDesc::new(Texture::DEPTH_FORMAT)
+            },
+        );
+        forward_renderer.color_target = HDR_MSAA.to_owned();
+        forward_renderer.depth_target = DEPTH_MSAA.to_owned();
+        terrain_renderer.color_target = HDR_MSAA.to_owned();
+        terrain_renderer.depth_target = DEPTH_MSAA.to_owned();
+        crowd_renderer.color_target = HDR_MSAA.to_owned();
+        crowd_renderer.depth_target = DEPTH_MSAA.to_owned();
+        sky_pass.color_target = HDR_MSAA.to_owned();
+        sky_pass.depth_target = DEPTH_MSAA.to_owned();
+        outline_pass.color_target = HDR_MSAA.to_owned();
+        transparent_renderer.color_target = HDR_MSAA.to_owned();
+        transparent_renderer.depth_target = DEPTH_MSAA.to_owned();
+        fluid_renderer.color_target = HDR_MSAA.to_owned();
+        fluid_renderer.depth_target = DEPTH_MSAA.to_owned();
+        particle_renderer.color_target = HDR_MSAA.to_owned();
+        particle_renderer.depth_target = DEPTH_MSAA.to_owned();
+        debug_draw_pass.color_target = HDR_MSAA.to_owned();
+        debug_draw_pass.depth_target = DEPTH_MSAA.to_owned();
+    }
+
+    render_graph.add_node(environment_pass);
+    render_graph.add_node(light_culling_pass);
+    render_graph.add_node(forward_renderer);
+    render_graph.add_node(terrain_renderer);
+    render_graph.add_node(crowd_renderer);
+    render_graph.add_node(sky_pass);
+    render_graph.add_node(outline_pass);
+    render_graph.add_node(transparent_renderer);
+    render_graph.add_node(fluid_renderer);
+    render_graph.add_node(particle_renderer);
+    render_graph.add_node(debug_draw_pass);
+    if sample_count > 1 {
+        render_graph.add_node(ResolvePass::new(HDR_MSAA, HDR));
+    }
+    render_graph.add_node(BloomPass::new(&gfx_ctx.device));
+
+    let mut tonemap = TonemapPass::new(&gfx_ctx.device, gfx_ctx.config.format);
+    match settings.post_anti_aliasing {
+        PostAntiAliasing::None => {
+            render_graph.add_node(tonemap);
+        }
+        PostAntiAliasing::Fxaa => {
+            render_graph.declare_texture(LDR, TransientTextureDesc::new(gfx_ctx.config.format));
+            tonemap.output = LDR.to_owned();
+            render_graph.add_node(tonemap);
+            render_graph.add_node(FxaaPass::new(&gfx_ctx.device, gfx_ctx.config.format));
+        }
+    }
+    render_graph
+}
+
+// The GPU side of a viewport in UserContext::viewports, or of a render target in UserContext::render_targets.
+pub struct ViewportState {
+    pub camera_ctx: CameraContext,
+    pub render_graph: RenderGraph,
+    pub graph_settings: RenderSettings,
+}
+
 pub struct WindowState {
     pub window: Arc<Window>,
+    // Kept to create a new surface and device after they are lost.
+    pub instance: wgpu::Instance,
     pub surface: wgpu::Surface<'static>,
     pub gfx_ctx: GraphicsContext,
     pub light_ctx: LightContext,
     pub cam_ctx: CameraContext,
     pub user_ctx: UserContext,
-    pub forward_renderer: ForwardRenderer,
+    pub render_graph: RenderGraph,
+    // The settings the render graph was last built for.
+    pub graph_settings: RenderSettings,
+    pub viewport_states: Vec<ViewportState>,
+    pub render_target_states: Vec<ViewportState>,
     pub egui_renderer: EguiRenderer,
     #[allow(dead_code)]
     pub is_surface_configured: bool,
@@ -54,22 +241,14 @@ impl WindowState {
         }
 
         let u = &mut user_ctx;
-        let s = &u.scenes[u.active_scene];
+        let s = &mut u.scenes[u.active_scene];
         let c = &s.cameras[s.active_camera];
 
         let cam_ctx = CameraContext::new(&gfx_ctx.device, &c);
         let light_ctx = LightContext::new(&gfx_ctx.device, lights);
 
-        let forward_renderer = ForwardRenderer::new(
-            &gfx_ctx.device,
-            &light_ctx.light_buffer,
-            &cam_ctx.buffer,
-            &gfx_ctx.texture_bind_group_layout_3d,
-            &cam_ctx.bind_group_layout,
-            &light_ctx.light_bind_group_layout,
-            &gfx_ctx.bone_matrices_bind_group_layout,
-            &gfx_ctx.config,
-        );
+        let render_graph = build_render_graph(&gfx_ctx, &cam_ctx, &light_ctx, false);
+        let graph_settings = gfx_ctx.render_settings;
 
         let egui_renderer =
             EguiRenderer::new(&gfx_ctx.device, gfx_ctx.surface_format, &window.clone());
@@ -88,12 +267,16 @@ impl WindowState {
 
         Ok(Self {
             window,
+            instance,
             surface,
             gfx_ctx,
             light_ctx,
             user_ctx,
             cam_ctx,
-            forward_renderer,
+            render_graph,
+            graph_settings,
+            viewport_states: Vec::new(),
+            render_target_states: Vec::new(),
             egui_renderer,
             is_surface_configured: false,
             mouse_pressed: false,
@@ -150,6 +333,17 @@ impl WindowState {
                 c.move_right();
                 // true
             }
+            (KeyCode::F2, true) => {
+                c.toggle_projection();
+            }
+            (KeyCode::F3, true) => {
+                s.physics_context.debug_renderer.toggle();
+            }
+            // F12 opens the browser's developer tools on the web, where there's no capturing anyway.
+            #[cfg(not(target_arch = "wasm32"))]
+            (KeyCode::F12, true) => {
+                u.frame_capture.timestamped_screenshot(true);
+            }
             (KeyCode::Escape, true) => {
                 event_loop.exit();
             } // true,
@@ -193,6 +387,16 @@ impl WindowState {
         let cam_idx = s.active_camera;
         let c = &mut s.cameras[cam_idx];
 
+        // Orthographic views look the same from any distance, so scrolling zooms them instead.
+        if c.projection.is_orthographic() {
+            let amount = match delta {
+                MouseScrollDelta::LineDelta(_, s) => *s,
+                MouseScrollDelta::PixelDelta(position) => position.y.signum() as f32,
+            };
+            c.projection.zoom_by(if amount < 0.0 { 0.9 } else { 1.1 });
+            return;
+        }
+
         match delta {
             MouseScrollDelta::LineDelta(_, s) => {
                 if *s < 0.0 {
@@ -212,6 +416,10 @@ impl WindowState {
     }
 
     pub fn update(&mut self, dt: std::time::Duration) {
+        // While recording a sequence, every rendered frame is one fixed step, however long it took to render.
+        let dt = self.user_ctx.frame_capture.fixed_timestep().unwrap_or(dt);
+        self.user_ctx.debug_draw.advance(dt.as_secs_f32());
+
         // Here, we call our user update callback
         if let Some(cb) = *USER_UPDATE_CALLBACK.lock().unwrap() {
             cb(
@@ -222,6 +430,16 @@ impl WindowState {
                 dt,
             );
         }
+
+        // After the callback, so the wireframes match the world as it was just stepped.
+        let u = &mut self.user_ctx;
+        let s = &mut u.scenes[u.active_scene];
+        s.update_fluid_surface();
+        s.update_crowds(dt);
+        let physics_context = &mut s.physics_context;
+        physics_context
+            .debug_renderer
+            .draw(&physics_context.rigid_world, &mut u.debug_draw);
     }
 
     pub fn render(&mut self) -> anyhow::Result<()> {
@@ -232,6 +450,11 @@ impl WindowState {
             return Ok(());
         }
 
+        if self.gfx_ctx.is_device_lost() {
+            self.recreate_device()?;
+            return Ok(());
+        }
+
         let output = match self.surface.get_current_texture() {
             wgpu::CurrentSurfaceTexture::Success(surface_texture) => surface_texture,
             wgpu::CurrentSurfaceTexture::Suboptimal(surface_texture) => {
@@ -251,28 +474,89 @@ impl WindowState {
                 return Ok(());
             }
             wgpu::CurrentSurfaceTexture::Lost => {
-                // TODO: Fix
-                anyhow::bail!("Lost device");
+                self.recover_surface()?;
+                return Ok(());
             }
         };
 
+        if self
+            .gfx_ctx
+            .render_settings
+            .requires_rebuild(&self.graph_settings)
+        {
+            self.render_graph = build_render_graph(&self.gfx_ctx, &self.cam_ctx, &self.light_ctx, false);
+            self.graph_settings = self.gfx_ctx.render_settings;
+        }
+
         let view = output
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
 
-        let u = &mut self.user_ctx;
-
+        let u = &self.user_ctx;
         let s = &u.scenes[u.active_scene];
+        let c = &s.cameras[s.active_camera];
+        let view_matrix = c.view_matrix();
+        let view_projection = c.projection.calc_matrix() * view_matrix;
+        let use_viewports = u.viewports.iter().any(|v| v.enabled);
+
+        let mut encoder = self
+            .gfx_ctx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Render Encoder"),
+            });
+
+        // First, so the window's passes sample this frame's images.
+        self.render_targets(&mut encoder)?;
+
+        if use_viewports {
+            self.render_viewports(&output.texture, &view, &mut encoder)?;
+        } else {
+            // The depth texture is recreated on resize, so it is imported every frame along with the surface.
+            self.render_graph
+                .import_texture(SURFACE, output.texture.clone(), view.clone());
+            self.render_graph.import_texture(
+                DEPTH,
+                self.gfx_ctx.depth_texture.texture.clone(),
+                self.gfx_ctx.depth_texture.view.clone(),
+            );
 
-        self.forward_renderer.draw(
+            let u = &mut self.user_ctx;
+            let s = &mut u.scenes[u.active_scene];
+            let eye = s.cameras[s.active_camera].eye;
+
+            let mut frame = FrameContext {
+                device: &self.gfx_ctx.device,
+                queue: &self.gfx_ctx.queue,
+                config: &self.gfx_ctx.config,
+                models: &u.asset_mgr.models,
+                skinned_models: &u.asset_mgr.skinned_models,
+                scene: s,
+                lights: &self.light_ctx,
+                settings: &self.gfx_ctx.render_settings,
+                debug_draw: &u.debug_draw,
+                view: view_matrix,
+                view_projection,
+                eye,
+                size: (self.gfx_ctx.config.width, self.gfx_ctx.config.height),
+                viewport: None,
+            };
+
+            self.render_graph.execute(&mut frame, &mut encoder)?;
+        }
+
+        // Copies are recorded into the same encoder, before and after egui draws.
+        let (scene_only_paths, with_ui_paths) = if self.user_ctx.frame_capture.is_pending() {
+            self.user_ctx.frame_capture.take_frame_requests()
+        } else {
+            (Vec::new(), Vec::new())
+        };
+        let scene_only_capture = PendingCapture::record(
             &self.gfx_ctx.device,
-            &self.gfx_ctx.queue,
-            &u.asset_mgr.models,
-            &u.asset_mgr.skinned_models,
-            &s.model_nodes,
-            &s.characters_contexts,
-            &self.gfx_ctx.depth_texture.view,
-            &view,
+            &mut encoder,
+            &output.texture,
+            &self.gfx_ctx.config,
+            scene_only_paths,
         );
 
         let screen_descriptor = ScreenDescriptor {
@@ -280,13 +564,20 @@ impl WindowState {
             pixels_per_point: self.window.scale_factor() as f32,
         };
 
-        let mut encoder = self
-            .gfx_ctx
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
-
         self.egui_renderer.begin_frame(&self.window);
 
+        // Labels are projected for a single full window view.
+        if !use_viewports {
+            self.user_ctx.debug_draw.paint_labels(
+                self.egui_renderer.context(),
+                &view_projection,
+                egui::vec2(
+                    screen_descriptor.size_in_pixels[0] as f32 / screen_descriptor.pixels_per_point,
+                    screen_descriptor.size_in_pixels[1] as f32 / screen_descriptor.pixels_per_point,
+                ),
+            );
+        }
+
         if let Some(cb) = *USER_GUI_CALLBACK.lock().unwrap() {
             cb(&mut self.egui_renderer, &mut self.user_ctx);
         }
@@ -300,11 +591,274 @@ impl WindowState {
             screen_descriptor,
         );
 
+        let with_ui_capture = PendingCapture::record(
+            &self.gfx_ctx.device,
+            &mut encoder,
+            &output.texture,
+            &self.gfx_ctx.config,
+            with_ui_paths,
+        );
+
         self.gfx_ctx.queue.submit(Some(encoder.finish()));
 
+        for capture in [scene_only_capture, with_ui_capture].into_iter().flatten() {
+            self.user_ctx.frame_capture.save(capture, &self.gfx_ctx.device);
+        }
+
         //output.present();
         self.gfx_ctx.queue.present(output);
 
         Ok(())
     }
+
+    // Draws each enabled viewport through its own camera uniform and render graph, into its part of the surface.
+    fn render_viewports(
+        &mut self,
+        surface_texture: &wgpu::Texture,
+        surface_view: &wgpu::TextureView,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> anyhow::Result<()> {
+        // Cleared once here, the viewports' final passes only draw their own rectangles.
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Viewports Clear"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: surface_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+            multiview_mask: None,
+        });
+
+        let u = &mut self.user_ctx;
+        let s = &mut u.scenes[u.active_scene];
+        let viewports = u.viewports.clone();
+
+        // One state per viewport, by index, created on first use.
+        self.viewport_states.truncate(viewports.len());
+        while self.viewport_states.len() < viewports.len() {
+            let camera_ctx = CameraContext::new(&self.gfx_ctx.device, &s.cameras[s.active_camera]);
+            let render_graph = build_render_graph(&self.gfx_ctx, &camera_ctx, &self.light_ctx, true);
+            self.viewport_states.push(ViewportState {
+                camera_ctx,
+                render_graph,
+                graph_settings: self.gfx_ctx.render_settings,
+            });
+        }
+
+        let (width, height) = (self.gfx_ctx.config.width, self.gfx_ctx.config.height);
+        for (viewport, state) in viewports.iter().zip(self.viewport_states.iter_mut()) {
+            if !viewport.enabled {
+                continue;
+            }
+            let camera = match s.cameras.get(viewport.camera_idx) {
+                Some(val) => val,
+                None => continue,
+            };
+
+            if self
+                .gfx_ctx
+                .render_settings
+                .requires_rebuild(&state.graph_settings)
+            {
+                state.render_graph = build_render_graph(&self.gfx_ctx, &state.camera_ctx, &self.light_ctx, true);
+                state.graph_settings = self.gfx_ctx.render_settings;
+            }
+
+            // The scene camera keeps its own aspect, which the main view and other viewports may differ from.
+            let rect = viewport.rect.to_pixels(width, height);
+            let mut projection = camera.projection;
+            projection.resize(rect.height, rect.width);
+            state
+                .camera_ctx
+                .uniform
+                .update_view_proj(camera, &projection);
+            self.gfx_ctx.queue.write_buffer(
+                &state.camera_ctx.buffer,
+                0,
+                bytemuck::cast_slice(&[state.camera_ctx.uniform]),
+            );
+            let view_matrix = camera.view_matrix();
+            let view_projection = projection.calc_matrix() * view_matrix;
+            let eye = camera.eye;
+
+            state
+                .render_graph
+                .import_texture(SURFACE, surface_texture.clone(), surface_view.clone());
+
+            let mut frame = FrameContext {
+                device: &self.gfx_ctx.device,
+                queue: &self.gfx_ctx.queue,
+                config: &self.gfx_ctx.config,
+                models: &u.asset_mgr.models,
+                skinned_models: &u.asset_mgr.skinned_models,
+                scene: s,
+                lights: &self.light_ctx,
+                settings: &self.gfx_ctx.render_settings,
+                debug_draw: &u.debug_draw,
+                view: view_matrix,
+                view_projection,
+                eye,
+                size: (rect.width, rect.height),
+                viewport: Some(rect),
+            };
+
+            state.render_graph.execute(&mut frame, encoder)?;
+        }
+
+        Ok(())
+    }
+
+    // Draws the render targets due this frame, each through its own camera uniform and render graph.
+    fn render_targets(&mut self, encoder: &mut wgpu::CommandEncoder) -> anyhow::Result<()> {
+        let u = &mut self.user_ctx;
+        let count = u.render_targets.len();
+
+        // The uniform is rewritten before every draw, so any camera will do to create it.
+        self.render_target_states.truncate(count);
+        while self.render_target_states.len() < count {
+            let s = &u.scenes[u.active_scene];
+            let camera_ctx = CameraContext::new(&self.gfx_ctx.device, &s.cameras[s.active_camera]);
+            let render_graph = build_render_graph(&self.gfx_ctx, &camera_ctx, &self.light_ctx, true);
+            self.render_target_states.push(ViewportState {
+                camera_ctx,
+                render_graph,
+                graph_settings: self.gfx_ctx.render_settings,
+            });
+        }
+
+        for (target, state) in u
+            .render_targets
+            .iter_mut()
+            .zip(self.render_target_states.iter_mut())
+        {
+            if !target.advance() {
+                continue;
+            }
+            let s = match u.scenes.get_mut(target.scene_idx) {
+                Some(val) => val,
+                None => continue,
+            };
+            let camera = match s.cameras.get(target.camera_idx) {
+                Some(val) => val,
+                None => continue,
+            };
+
+            if self
+                .gfx_ctx
+                .render_settings
+                .requires_rebuild(&state.graph_settings)
+            {
+                state.render_graph = build_render_graph(&self.gfx_ctx, &state.camera_ctx, &self.light_ctx, true);
+                state.graph_settings = self.gfx_ctx.render_settings;
+            }
+
+            // On a copy, so the camera's aspect stays that of the view it also belongs to.
+            let mut projection = camera.projection;
+            projection.resize(target.height, target.width);
+            state
+                .camera_ctx
+                .uniform
+                .update_view_proj(camera, &projection);
+            self.gfx_ctx.queue.write_buffer(
+                &state.camera_ctx.buffer,
+                0,
+                bytemuck::cast_slice(&[state.camera_ctx.uniform]),
+            );
+            let view_matrix = camera.view_matrix();
+            let view_projection = projection.calc_matrix() * view_matrix;
+            let eye = camera.eye;
+
+            state.render_graph.import_texture(
+                SURFACE,
+                target.render_texture.clone(),
+                target.render_view.clone(),
+            );
+
+            let mut frame = FrameContext {
+                device: &self.gfx_ctx.device,
+                queue: &self.gfx_ctx.queue,
+                config: &self.gfx_ctx.config,
+                models: &u.asset_mgr.models,
+                skinned_models: &u.asset_mgr.skinned_models,
+                scene: s,
+                lights: &self.light_ctx,
+                settings: &self.gfx_ctx.render_settings,
+                debug_draw: &u.debug_draw,
+                view: view_matrix,
+                view_projection,
+                eye,
+                size: (target.width, target.height),
+                viewport: None,
+            };
+
+            state.render_graph.execute(&mut frame, encoder)?;
+
+            encoder.copy_texture_to_texture(
+                target.render_texture.as_image_copy(),
+                target.texture.texture.as_image_copy(),
+                target.render_texture.size(),
+            );
+        }
+
+        Ok(())
+    }
+
+    // The surface can be lost on its own (e.g. the window moved to another display), or along with the device.
+    fn recover_surface(&mut self) -> anyhow::Result<()> {
+        self.surface = self.instance.create_surface(self.window.clone())?;
+        if self.gfx_ctx.is_device_lost() {
+            return self.recreate_device();
+        }
+        self.surface
+            .configure(&self.gfx_ctx.device, &self.gfx_ctx.config);
+        Ok(())
+    }
+
+    // After a driver reset or a GPU switch. A new adapter and device are requested, then everything on the GPU is
+    // created again from what is kept on the CPU: models, textures and scenes rebuild their buffers,
+    // and the render graphs, camera, lights and egui start over. The user's device recreated callback does the rest.
+    fn recreate_device(&mut self) -> anyhow::Result<()> {
+        println!("[WindowState] Recreating the device");
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            anyhow::bail!("Lost device");
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let mut gfx_ctx = pollster::block_on(GraphicsContext::new(&self.window, &self.surface, &self.instance));
+            gfx_ctx.render_settings = self.gfx_ctx.render_settings;
+            gfx_ctx.config.width = self.gfx_ctx.config.width;
+            gfx_ctx.config.height = self.gfx_ctx.config.height;
+            gfx_ctx.depth_texture = Texture::create_depth_texture(&gfx_ctx.device, &gfx_ctx.config, "depth_texture");
+            self.surface.configure(&gfx_ctx.device, &gfx_ctx.config);
+
+            self.user_ctx.recreate_gpu_resources(&mut gfx_ctx)?;
+            if let Some(cb) = *USER_DEVICE_RECREATED_CALLBACK.lock().unwrap() {
+                cb(&mut gfx_ctx, &mut self.user_ctx);
+            }
+
+            let u = &self.user_ctx;
+            let s = &u.scenes[u.active_scene];
+            self.cam_ctx = CameraContext::new(&gfx_ctx.device, &s.cameras[s.active_camera]);
+            let point_lights = std::mem::take(&mut self.light_ctx.point_lights);
+            self.light_ctx = LightContext::new(&gfx_ctx.device, self.light_ctx.light_uniforms.clone());
+            self.light_ctx.point_lights = point_lights;
+            self.render_graph = build_render_graph(&gfx_ctx, &self.cam_ctx, &self.light_ctx, false);
+            self.graph_settings = gfx_ctx.render_settings;
+            self.viewport_states.clear();
+            self.render_target_states.clear();
+            self.egui_renderer = EguiRenderer::new(&gfx_ctx.device, gfx_ctx.surface_format, &self.window);
+            self.gfx_ctx = gfx_ctx;
+            Ok(())
+        }
+    }
 }